@@ -0,0 +1,73 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+use crate::clock::Timestamp;
+use crate::token_amount::TokenAmount;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuctionStatus {
+    /// Accepting bids until the window closes.
+    Open,
+    /// Deals were created with these SPs.
+    Awarded(Vec<PeerId>),
+    /// Fewer deals than the replication factor could be created; the budget for the
+    /// missing replicas was refunded.
+    PartiallyFilled(Vec<PeerId>),
+    /// Not enough qualifying bids; nothing was stored or charged.
+    Failed(String),
+}
+
+/// What a client asks for when posting a storage request.
+#[derive(Clone, Debug)]
+pub struct AuctionTerms {
+    pub replication_factor: usize,
+    pub duration: Duration,
+    pub max_price_per_byte_epoch: TokenAmount,
+    pub bidding_window: Duration,
+}
+
+/// A client's request for storage, auctioned to SPs. The lowest qualifying bids win.
+#[derive(Clone, Debug)]
+pub struct StorageRequest {
+    pub id: u64,
+    pub client_id: PeerId,
+    pub filename: String,
+    pub data: Vec<u8>,
+    pub terms: AuctionTerms,
+    pub opened_at: Timestamp,
+    pub status: AuctionStatus,
+    /// Holds the client's maximum budget while the request is open.
+    pub escrow_id: u64,
+}
+
+impl StorageRequest {
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn closes_at(&self) -> Timestamp {
+        self.opened_at + self.terms.bidding_window
+    }
+
+    pub fn is_open_at(&self, now: Timestamp) -> bool {
+        self.status == AuctionStatus::Open && now < self.closes_at()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BidOutcome {
+    Won { price_per_byte_epoch: TokenAmount },
+    /// `clearing_price` is the highest winning bid, if the auction was awarded at all.
+    Lost { clearing_price: Option<TokenAmount> },
+}
+
+/// Tells a bidder how its bid on a storage request ended.
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuctionNotice {
+    pub request_id: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub storage_node_id: PeerId,
+    pub outcome: BidOutcome,
+}
@@ -29,6 +29,14 @@ impl Client {
         self.files.insert(filename, storage_nodes);
     }
 
+    /// Records one more SP holding `filename`, keeping any locations already known.
+    pub fn add_file_location(&mut self, filename: &str, storage_node: PeerId) {
+        let locations = self.files.entry(filename.to_string()).or_default();
+        if !locations.contains(&storage_node) {
+            locations.push(storage_node);
+        }
+    }
+
     pub fn remove_file(&mut self, filename: &str) -> bool {
         self.files.remove(filename).is_some()
     }
@@ -0,0 +1,95 @@
+use libp2p::{futures::future::join_all, identity, PeerId};
+use rand::seq::SliceRandom;
+use crate::peer::PeerHandle;
+use crate::protocol::{BlockRequest, BlockResponse};
+use crate::TokenAmount;
+
+/// Storage nodes and clients running as separate libp2p peers over `MemoryTransport`
+/// in one process. Every client is connected to every storage node and the storage
+/// nodes to each other, and uploads and downloads travel as protocol messages.
+///
+/// The cluster exercises the storage protocol only. Every storage peer is started at a
+/// fixed price of one base unit per byte-epoch and nothing economic crosses the wire:
+/// no tokens are paid, no escrow is locked and no deals are opened or audited. Those
+/// are modelled by `Network`.
+pub struct TestCluster {
+    pub storage_nodes: Vec<PeerHandle>,
+    pub clients: Vec<PeerHandle>,
+}
+
+impl TestCluster {
+    /// Starts the peers, connects each client to each storage node and the storage nodes
+    /// to each other. Must be called from within a tokio runtime.
+    pub async fn new(storage_nodes: usize, clients: usize) -> Result<Self, String> {
+        let storage_nodes = (0..storage_nodes)
+            .map(|_| PeerHandle::spawn_storage_node(identity::Keypair::generate_ed25519(), TokenAmount::from_base_units(1)))
+            .collect::<Result<Vec<PeerHandle>, String>>()?;
+        let clients = (0..clients)
+            .map(|_| PeerHandle::spawn_client(identity::Keypair::generate_ed25519()))
+            .collect::<Result<Vec<PeerHandle>, String>>()?;
+
+        for client in &clients {
+            let connections = storage_nodes.iter().map(|node| client.connect(*node.peer_id(), node.address().clone()));
+            join_all(connections).await.into_iter().collect::<Result<Vec<()>, String>>()?;
+        }
+        for (index, node) in storage_nodes.iter().enumerate() {
+            let connections = storage_nodes[index + 1..].iter().map(|other| node.connect(*other.peer_id(), other.address().clone()));
+            join_all(connections).await.into_iter().collect::<Result<Vec<()>, String>>()?;
+        }
+        Ok(Self { storage_nodes, clients })
+    }
+
+    pub fn storage_node_ids(&self) -> Vec<PeerId> {
+        self.storage_nodes.iter().map(|node| *node.peer_id()).collect()
+    }
+
+    fn client(&self, index: usize) -> Result<&PeerHandle, String> {
+        self.clients.get(index).ok_or_else(|| format!("No client {} in the cluster", index))
+    }
+
+    /// Stores `data` on `replication_factor` storage nodes picked at random and records
+    /// where it went in the client's file list. The client sends the file once, to the
+    /// first node, and each node forwards it to the next. Returns the nodes in chain order.
+    pub async fn upload(&self, client: usize, filename: &str, data: Vec<u8>, replication_factor: usize) -> Result<Vec<PeerId>, String> {
+        let client = self.client(client)?;
+        if replication_factor == 0 || replication_factor > self.storage_nodes.len() {
+            return Err(format!("Not enough storage nodes. Required: {}, Available: {}", replication_factor, self.storage_nodes.len()));
+        }
+        let targets: Vec<PeerId> = self.storage_node_ids().choose_multiple(&mut rand::thread_rng(), replication_factor).cloned().collect();
+        let request = BlockRequest::Replicate { name: filename.to_string(), data, owner: *client.peer_id(), forward_to: targets[1..].to_vec() };
+        match client.request(targets[0], request).await? {
+            BlockResponse::Stored => {}
+            BlockResponse::Error { message, .. } => return Err(message),
+            other => return Err(format!("Unexpected response to an upload: {:?}", other)),
+        }
+
+        if let Some(client) = client.client() {
+            client.lock().unwrap().add_file(filename.to_string(), targets.clone());
+        }
+        Ok(targets)
+    }
+
+    /// Fetches a file from the first of its recorded locations that still serves it.
+    pub async fn download(&self, client: usize, filename: &str) -> Result<Vec<u8>, String> {
+        let client = self.client(client)?;
+        let locations = client.client()
+            .and_then(|client| client.lock().unwrap().get_file_locations(filename).cloned())
+            .ok_or_else(|| "File not found".to_string())?;
+        let mut last_error = "File has no locations".to_string();
+        for node_id in locations {
+            match client.fetch(node_id, filename).await {
+                Ok(data) => return Ok(data),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Storage nodes that actually hold `filename`, read from each peer's own state.
+    pub fn stored_on(&self, filename: &str) -> Vec<PeerId> {
+        self.storage_nodes.iter()
+            .filter(|node| node.storage_node().is_some_and(|storage_node| storage_node.lock().unwrap().get_file(filename).is_some()))
+            .map(|node| *node.peer_id())
+            .collect()
+    }
+}
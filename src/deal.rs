@@ -0,0 +1,290 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+use crate::clock::Timestamp;
+use crate::network::EPOCH_DURATION;
+use crate::token_amount::TokenAmount;
+
+/// A deal enters `Expiring` once this many unpaid epochs or fewer remain.
+pub const EXPIRING_EPOCHS: u64 = 2;
+
+/// Number of payment epochs in a deal of the given length. A part epoch counts as a
+/// whole one, so every moment of the term is paid for.
+pub(crate) fn epochs_in(duration: Duration) -> u64 {
+    duration.as_nanos().div_ceil(EPOCH_DURATION.as_nanos()).max(1) as u64
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DealState {
+    /// Terms agreed by the client, waiting for the SP.
+    Proposed,
+    /// The SP has reserved space and the payment is escrowed.
+    Accepted,
+    /// The SP holds the data and is paid each epoch it proves storage.
+    Active,
+    /// Nearly over; the client may still renew.
+    Expiring,
+    /// Ran its full term.
+    Expired,
+    /// Ended early by the client or the network, remainder refunded.
+    Terminated,
+    /// Ended because the SP failed a storage proof.
+    Slashed,
+    /// Handed over, with its unpaid value, to another SP when this one left the network
+    /// or the rebalancer moved the replica.
+    Migrated,
+}
+
+impl DealState {
+    pub fn is_final(self) -> bool {
+        matches!(self, DealState::Expired | DealState::Terminated | DealState::Slashed | DealState::Migrated)
+    }
+
+    pub fn can_transition_to(self, next: DealState) -> bool {
+        use DealState::*;
+        matches!(
+            (self, next),
+            (Proposed, Accepted | Terminated)
+                | (Accepted, Active | Terminated)
+                | (Active, Expiring | Expired | Terminated | Slashed | Migrated)
+                | (Expiring, Active | Expired | Terminated | Slashed | Migrated)
+        )
+    }
+}
+
+impl fmt::Display for DealState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for DealState {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "proposed" => Ok(DealState::Proposed),
+            "accepted" => Ok(DealState::Accepted),
+            "active" => Ok(DealState::Active),
+            "expiring" => Ok(DealState::Expiring),
+            "expired" => Ok(DealState::Expired),
+            "terminated" => Ok(DealState::Terminated),
+            "slashed" => Ok(DealState::Slashed),
+            "migrated" => Ok(DealState::Migrated),
+            _ => Err(format!("Unknown deal state: {}", value)),
+        }
+    }
+}
+
+/// When a deal entered a state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DealTransition {
+    pub state: DealState,
+    pub epoch: u64,
+    pub at: Timestamp,
+}
+
+/// What a client and SP agree on before a deal exists.
+#[derive(Clone, Debug)]
+pub struct DealTerms {
+    pub client_id: PeerId,
+    pub storage_node_id: PeerId,
+    pub filename: String,
+    pub duration: Duration,
+    pub total_payment: TokenAmount,
+    /// Digest of the data the SP must keep proving it holds.
+    pub commitment: [u8; 32],
+}
+
+/// Standing instruction to extend a deal from a client-funded escrow when its term
+/// is about to run out.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoRenewal {
+    #[serde(with = "serde_millis")]
+    pub extension: Duration,
+    pub escrow_id: u64,
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Deal {
+    pub(crate) id: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub(crate) client_id: PeerId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub(crate) storage_node_id: PeerId,
+    pub(crate) filename: String,
+    /// When the current term began; unset until the deal first becomes active.
+    #[serde(default)]
+    pub(crate) start_time: Option<Timestamp>,
+    #[serde(with = "serde_millis")]
+    pub(crate) duration: Duration,
+    pub(crate) escrow_id: Option<u64>,
+    pub(crate) total_payment: TokenAmount,
+    pub(crate) epochs: u64,
+    pub(crate) epochs_paid: u64,
+    #[serde(default)]
+    pub(crate) amount_paid: TokenAmount,
+    pub(crate) commitment: [u8; 32],
+    #[serde(default)]
+    pub(crate) auto_renewal: Option<AutoRenewal>,
+    /// When the last escrowed epoch was paid out; cleared when the deal is extended.
+    #[serde(default)]
+    pub(crate) paid_out_at: Option<Timestamp>,
+    state: DealState,
+    history: Vec<DealTransition>,
+}
+
+impl Deal {
+    pub fn propose(id: u64, terms: DealTerms, epoch: u64, now: Timestamp) -> Self {
+        Self {
+            id,
+            client_id: terms.client_id,
+            storage_node_id: terms.storage_node_id,
+            filename: terms.filename,
+            start_time: None,
+            epochs: epochs_in(terms.duration),
+            duration: terms.duration,
+            escrow_id: None,
+            total_payment: terms.total_payment,
+            epochs_paid: 0,
+            amount_paid: TokenAmount::ZERO,
+            commitment: terms.commitment,
+            auto_renewal: None,
+            paid_out_at: None,
+            state: DealState::Proposed,
+            history: vec![DealTransition { state: DealState::Proposed, epoch, at: now }],
+        }
+    }
+
+    /// Moves the deal to `next`, refusing transitions the lifecycle does not allow.
+    pub fn transition(&mut self, next: DealState, epoch: u64, now: Timestamp) -> Result<(), String> {
+        if !self.state.can_transition_to(next) {
+            return Err(format!("Deal {} cannot go from {} to {}", self.id, self.state, next));
+        }
+        if next == DealState::Active && self.start_time.is_none() {
+            // The term starts on first activation; renewals extend it instead
+            self.start_time = Some(now);
+        }
+        self.state = next;
+        self.history.push(DealTransition { state: next, epoch, at: now });
+        Ok(())
+    }
+
+    /// The SP reserved space and `escrow_id`, if any, holds the payment.
+    pub fn accept(&mut self, escrow_id: Option<u64>, epoch: u64, now: Timestamp) -> Result<(), String> {
+        self.transition(DealState::Accepted, epoch, now)?;
+        self.escrow_id = escrow_id;
+        Ok(())
+    }
+
+    /// Lengthens the term by `extension`, paid for by `cost` on top of the original payment.
+    pub fn extend(&mut self, extension: Duration, cost: TokenAmount) -> Result<(), String> {
+        self.total_payment = self.total_payment.checked_add(cost)
+            .ok_or_else(|| format!("Deal {} payment overflows the token supply", self.id))?;
+        self.duration += extension;
+        self.epochs += epochs_in(extension);
+        self.paid_out_at = None;
+        Ok(())
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn state(&self) -> DealState {
+        self.state
+    }
+
+    pub fn is_live(&self) -> bool {
+        matches!(self.state, DealState::Active | DealState::Expiring)
+    }
+
+    /// Every state the deal has been in, oldest first.
+    pub fn history(&self) -> &[DealTransition] {
+        &self.history
+    }
+
+    /// When the deal last entered `state`, if it ever did.
+    pub fn entered(&self, state: DealState) -> Option<&DealTransition> {
+        self.history.iter().rev().find(|transition| transition.state == state)
+    }
+
+    pub fn start_time(&self) -> Option<Timestamp> {
+        self.start_time
+    }
+
+    /// When the current term runs out, if the deal has started.
+    pub fn ends_at(&self) -> Option<Timestamp> {
+        self.start_time.map(|start| start + self.duration)
+    }
+
+    pub fn has_run_out_at(&self, now: Timestamp) -> bool {
+        self.ends_at().is_some_and(|end| now >= end)
+    }
+
+    /// When the term ended, either by the clock or by its last epoch being paid,
+    /// whichever came first. `None` while the deal is still running.
+    pub fn term_ended_at(&self, now: Timestamp) -> Option<Timestamp> {
+        let ran_out = self.ends_at().filter(|&end| now >= end);
+        match (ran_out, self.paid_out_at) {
+            (Some(ran_out), Some(paid_out)) => Some(ran_out.min(paid_out)),
+            (ran_out, paid_out) => ran_out.or(paid_out),
+        }
+    }
+
+    pub fn client_id(&self) -> &PeerId {
+        &self.client_id
+    }
+
+    pub fn storage_node_id(&self) -> &PeerId {
+        &self.storage_node_id
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn escrow_id(&self) -> Option<u64> {
+        self.escrow_id
+    }
+
+    /// Digest of the data the SP must keep proving it holds.
+    pub fn commitment(&self) -> [u8; 32] {
+        self.commitment
+    }
+
+    pub fn total_payment(&self) -> TokenAmount {
+        self.total_payment
+    }
+
+    pub fn epochs(&self) -> u64 {
+        self.epochs
+    }
+
+    pub fn epochs_paid(&self) -> u64 {
+        self.epochs_paid
+    }
+
+    pub fn amount_paid(&self) -> TokenAmount {
+        self.amount_paid
+    }
+
+    pub fn auto_renewal(&self) -> Option<&AutoRenewal> {
+        self.auto_renewal.as_ref()
+    }
+
+    /// Amount owed to the SP for the next epoch. What is still unpaid is spread evenly
+    /// over the remaining epochs, so the payouts add up to `total_payment` exactly even
+    /// after renewals.
+    pub fn next_epoch_payout(&self) -> TokenAmount {
+        if self.epochs_paid >= self.epochs {
+            return TokenAmount::ZERO;
+        }
+        let unpaid = self.total_payment.saturating_sub(self.amount_paid);
+        unpaid.proportion(1, self.epochs - self.epochs_paid)
+    }
+}
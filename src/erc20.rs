@@ -1,29 +1,148 @@
 use std::collections::HashMap;
+use std::fmt;
 use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
 use tokio::sync::broadcast::Sender;
+use crate::token_amount::TokenAmount;
+
+/// What a token movement paid for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Purpose {
+    General,
+    Upload,
+    Replication,
+    Audit,
+    Retrieval,
+    Stake,
+}
+
+/// Where tokens sit: a peer's balance, a ledger-held escrow, or the unallocated treasury.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Account {
+    Peer(PeerId),
+    Escrow(u64),
+    Treasury,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenError {
+    InsufficientBalance { account: PeerId, required: TokenAmount, available: TokenAmount },
+    InsufficientAllowance { required: TokenAmount, available: TokenAmount },
+    InsufficientEscrow { escrow_id: u64, required: TokenAmount, available: TokenAmount },
+    InsufficientTreasury { required: TokenAmount, available: TokenAmount },
+    EscrowNotFound(u64),
+    Overflow,
+    /// The backing ledger (for example a JSON-RPC node) could not complete the call.
+    Backend(String),
+    /// The transaction with this hash was mined but reverted on chain.
+    Reverted(String),
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::InsufficientBalance { account, required, available } =>
+                write!(f, "Insufficient balance for {}. Required: {}, Available: {}", account, required, available),
+            TokenError::InsufficientAllowance { required, available } =>
+                write!(f, "Insufficient allowance. Required: {}, Available: {}", required, available),
+            TokenError::InsufficientEscrow { escrow_id, required, available } =>
+                write!(f, "Escrow {} cannot cover {} tokens, holds {}", escrow_id, required, available),
+            TokenError::InsufficientTreasury { required, available } =>
+                write!(f, "Treasury cannot cover {} tokens, holds {}", required, available),
+            TokenError::EscrowNotFound(escrow_id) => write!(f, "Escrow {} not found", escrow_id),
+            TokenError::Overflow => write!(f, "Token arithmetic overflow"),
+            TokenError::Backend(message) => write!(f, "Token backend error: {}", message),
+            TokenError::Reverted(hash) => write!(f, "Transaction {} reverted", hash),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// One leg of an atomic `transfer_batch`.
+#[derive(Clone, Debug)]
+pub struct BatchTransfer {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub amount: TokenAmount,
+    pub purpose: Purpose,
+    pub memo: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenEvent {
+    /// `from` is `None` for mints and `to` is `None` for burns.
+    Transfer {
+        from: Option<Account>,
+        to: Option<Account>,
+        amount: TokenAmount,
+        purpose: Purpose,
+        memo: String,
+    },
+    Approval {
+        owner: PeerId,
+        spender: PeerId,
+        amount: TokenAmount,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub sequence: u64,
+    pub event: TokenEvent,
+}
+
+impl EventRecord {
+    pub fn involves(&self, peer_id: &PeerId) -> bool {
+        let peer = Some(Account::Peer(*peer_id));
+        match &self.event {
+            TokenEvent::Transfer { from, to, .. } => *from == peer || *to == peer,
+            TokenEvent::Approval { owner, spender, .. } => owner == peer_id || spender == peer_id,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Escrow {
+    pub depositor: PeerId,
+    pub balance: TokenAmount,
+    pub purpose: Purpose,
+}
 
 #[derive(Clone)]
 pub struct ERC20 {
     name: String,
     symbol: String,
-    total_supply: u64,
-    balances: HashMap<PeerId, u64>,
-    allowances: HashMap<PeerId, HashMap<PeerId, u64>>,
+    total_supply: TokenAmount,
+    treasury: TokenAmount,
+    balances: HashMap<PeerId, TokenAmount>,
+    allowances: HashMap<PeerId, HashMap<PeerId, TokenAmount>>,
+    escrows: HashMap<u64, Escrow>,
+    next_escrow_id: u64,
+    events: Vec<EventRecord>,
     debug: bool,
     pub message_sender: Option<Sender<String>>,
 }
 
 impl ERC20 {
-    pub fn new(name: String, symbol: String, initial_supply: u64) -> Self {
-        let erc20 = ERC20 {
+    /// Creates the token with `initial_supply` held unallocated in the treasury.
+    pub fn new(name: String, symbol: String, initial_supply: TokenAmount) -> Self {
+        let mut erc20 = ERC20 {
             name,
             symbol,
             total_supply: initial_supply,
+            treasury: initial_supply,
             balances: HashMap::new(),
             allowances: HashMap::new(),
+            escrows: HashMap::new(),
+            next_escrow_id: 0,
+            events: Vec::new(),
             debug: false,
             message_sender: None,
         };
+        if !initial_supply.is_zero() {
+            erc20.emit_transfer(None, Some(Account::Treasury), initial_supply, Purpose::General, "initial supply");
+        }
         erc20
     }
 
@@ -47,53 +166,310 @@ impl ERC20 {
         }
     }
 
-    pub fn balance_of(&self, account: &PeerId) -> u64 {
-        *self.balances.get(account).unwrap_or(&0)
+    fn emit(&mut self, event: TokenEvent) {
+        let sequence = self.events.len() as u64;
+        self.events.push(EventRecord { sequence, event });
+    }
+
+    fn emit_transfer(&mut self, from: Option<Account>, to: Option<Account>, amount: TokenAmount, purpose: Purpose, memo: &str) {
+        self.emit(TokenEvent::Transfer { from, to, amount, purpose, memo: memo.to_string() });
+    }
+
+    /// Every event ever emitted, in sequence order.
+    pub fn events(&self) -> &[EventRecord] {
+        &self.events
+    }
+
+    pub fn events_since(&self, sequence: u64) -> &[EventRecord] {
+        let start = (sequence as usize).min(self.events.len());
+        &self.events[start..]
+    }
+
+    pub fn events_for(&self, peer_id: &PeerId) -> Vec<&EventRecord> {
+        self.events.iter().filter(|record| record.involves(peer_id)).collect()
+    }
+
+    pub fn events_with_purpose(&self, purpose: Purpose) -> Vec<&EventRecord> {
+        self.events.iter()
+            .filter(|record| matches!(&record.event, TokenEvent::Transfer { purpose: p, .. } if *p == purpose))
+            .collect()
+    }
+
+    /// Rebuilds peer, escrow and treasury balances from an event history.
+    pub fn replay(events: &[EventRecord]) -> HashMap<Account, TokenAmount> {
+        let mut balances: HashMap<Account, TokenAmount> = HashMap::new();
+        for record in events {
+            if let TokenEvent::Transfer { from, to, amount, .. } = &record.event {
+                if let Some(from) = from {
+                    let balance = balances.entry(*from).or_default();
+                    *balance = balance.saturating_sub(*amount);
+                }
+                if let Some(to) = to {
+                    let balance = balances.entry(*to).or_default();
+                    *balance = balance.checked_add(*amount).unwrap_or(TokenAmount::MAX);
+                }
+            }
+        }
+        balances.retain(|_, balance| !balance.is_zero());
+        balances
+    }
+
+    /// Checks that replaying the event history reproduces the current balances.
+    pub fn audit(&self) -> bool {
+        let replayed = Self::replay(&self.events);
+        let peers_match = self.balances.iter()
+            .all(|(peer_id, balance)| replayed.get(&Account::Peer(*peer_id)).copied().unwrap_or_default() == *balance);
+        let escrows_match = self.escrows.iter()
+            .all(|(escrow_id, escrow)| replayed.get(&Account::Escrow(*escrow_id)).copied().unwrap_or_default() == escrow.balance);
+        let treasury_matches = replayed.get(&Account::Treasury).copied().unwrap_or_default() == self.treasury;
+        let nothing_missing = replayed.keys().all(|account| match account {
+            Account::Peer(peer_id) => self.balances.contains_key(peer_id),
+            Account::Escrow(escrow_id) => self.escrows.contains_key(escrow_id),
+            Account::Treasury => true,
+        });
+        peers_match && escrows_match && treasury_matches && nothing_missing && self.supply_is_consistent()
+    }
+
+    /// The ledger invariant: every token in `total_supply` is held by exactly one
+    /// peer balance, escrow or the treasury.
+    pub fn supply_is_consistent(&self) -> bool {
+        let held = self.balances.values()
+            .chain(self.escrows.values().map(|escrow| &escrow.balance))
+            .chain(std::iter::once(&self.treasury))
+            .try_fold(TokenAmount::ZERO, |total, balance| total.checked_add(*balance));
+        held == Some(self.total_supply)
+    }
+
+    pub fn total_supply(&self) -> TokenAmount {
+        self.total_supply
+    }
+
+    pub fn treasury_balance(&self) -> TokenAmount {
+        self.treasury
+    }
+
+    pub fn balance_of(&self, account: &PeerId) -> TokenAmount {
+        self.balances.get(account).copied().unwrap_or_default()
+    }
+
+    /// Moves tokens between two balances, validating both sides before touching either.
+    fn apply_transfer(balances: &mut HashMap<PeerId, TokenAmount>, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let available = balances.get(from).copied().unwrap_or_default();
+        if available < amount {
+            return Err(TokenError::InsufficientBalance { account: *from, required: amount, available });
+        }
+        if from == to {
+            return Ok(());
+        }
+        let credited = balances.get(to).copied().unwrap_or_default().checked_add(amount).ok_or(TokenError::Overflow)?;
+        balances.insert(*from, available - amount);
+        balances.insert(*to, credited);
+        Ok(())
+    }
+
+    fn credit(&mut self, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let credited = self.balance_of(to).checked_add(amount).ok_or(TokenError::Overflow)?;
+        self.balances.insert(*to, credited);
+        Ok(())
+    }
+
+    fn debit(&mut self, from: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let available = self.balance_of(from);
+        if available < amount {
+            return Err(TokenError::InsufficientBalance { account: *from, required: amount, available });
+        }
+        self.balances.insert(*from, available - amount);
+        Ok(())
+    }
+
+    pub fn transfer(&mut self, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        self.transfer_with_memo(from, to, amount, Purpose::General, "")
     }
 
-    pub fn transfer(&mut self, from: &PeerId, to: &PeerId, amount: u64) -> bool {
+    pub fn transfer_with_memo(&mut self, from: &PeerId, to: &PeerId, amount: TokenAmount, purpose: Purpose, memo: &str) -> Result<(), TokenError> {
         self.debug_log(&format!("Attempting transfer: {} tokens from {} to {}", amount, from, to));
-        if self.balance_of(from) < amount {
-            self.debug_log("Transfer failed: Insufficient balance");
-            return false;
+        if let Err(e) = Self::apply_transfer(&mut self.balances, from, to, amount) {
+            self.debug_log(&format!("Transfer failed: {}", e));
+            return Err(e);
         }
-        *self.balances.entry(*from).or_insert(0) -= amount;
-        *self.balances.entry(*to).or_insert(0) += amount;
+        self.emit_transfer(Some(Account::Peer(*from)), Some(Account::Peer(*to)), amount, purpose, memo);
         self.debug_log("Transfer successful");
-        true
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
     }
 
-    pub fn approve(&mut self, owner: &PeerId, spender: &PeerId, amount: u64) -> bool {
+    /// Applies every transfer or none of them. Transfers are applied in order, so a
+    /// later leg may spend tokens received by an earlier one.
+    pub fn transfer_batch(&mut self, transfers: &[BatchTransfer]) -> Result<(), TokenError> {
+        self.debug_log(&format!("Attempting batch of {} transfers", transfers.len()));
+        let mut staged = self.balances.clone();
+        for transfer in transfers {
+            if let Err(e) = Self::apply_transfer(&mut staged, &transfer.from, &transfer.to, transfer.amount) {
+                self.debug_log(&format!("Batch rejected: {}", e));
+                return Err(e);
+            }
+        }
+        self.balances = staged;
+        for transfer in transfers {
+            self.emit_transfer(Some(Account::Peer(transfer.from)), Some(Account::Peer(transfer.to)), transfer.amount, transfer.purpose, &transfer.memo);
+        }
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
+    }
+
+    pub fn approve(&mut self, owner: &PeerId, spender: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
         self.allowances.entry(*owner).or_default().insert(*spender, amount);
-        true
+        self.emit(TokenEvent::Approval { owner: *owner, spender: *spender, amount });
+        Ok(())
+    }
+
+    pub fn allowance(&self, owner: &PeerId, spender: &PeerId) -> TokenAmount {
+        self.allowances.get(owner).and_then(|inner| inner.get(spender)).copied().unwrap_or_default()
     }
 
-    pub fn allowance(&self, owner: &PeerId, spender: &PeerId) -> u64 {
-        *self.allowances.get(owner).and_then(|inner| inner.get(spender)).unwrap_or(&0)
+    /// Moves `amount` tokens from `from` to `to` on behalf of `spender`, drawing down
+    /// the allowance `from` granted to `spender`.
+    pub fn transfer_from(&mut self, spender: &PeerId, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        self.transfer_from_with_memo(spender, from, to, amount, Purpose::General, "")
     }
 
-    pub fn transfer_from(&mut self, from: &PeerId, to: &PeerId, amount: u64) -> bool {
-        let allowance = self.allowance(from, to);
-        if allowance < amount || self.balance_of(from) < amount {
-            return false;
+    pub fn transfer_from_with_memo(&mut self, spender: &PeerId, from: &PeerId, to: &PeerId, amount: TokenAmount, purpose: Purpose, memo: &str) -> Result<(), TokenError> {
+        self.debug_log(&format!("Attempting transfer_from: {} spending {} tokens of {} to {}", spender, amount, from, to));
+        let allowance = self.allowance(from, spender);
+        if allowance < amount {
+            self.debug_log("Transfer failed: Insufficient allowance");
+            return Err(TokenError::InsufficientAllowance { required: amount, available: allowance });
         }
-        *self.allowances.get_mut(from).unwrap().get_mut(to).unwrap() -= amount;
-        *self.balances.entry(*from).or_insert(0) -= amount;
-        *self.balances.entry(*to).or_insert(0) += amount;
-        true
+        Self::apply_transfer(&mut self.balances, from, to, amount)?;
+        self.allowances.entry(*from).or_default().insert(*spender, allowance - amount);
+        self.emit_transfer(Some(Account::Peer(*from)), Some(Account::Peer(*to)), amount, purpose, memo);
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
     }
 
-    pub fn mint(&mut self, to: &PeerId, amount: u64) {
-        *self.balances.entry(*to).or_insert(0) += amount;
-        self.total_supply += amount;
+    pub fn mint(&mut self, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let total_supply = self.total_supply.checked_add(amount).ok_or(TokenError::Overflow)?;
+        self.credit(to, amount)?;
+        self.total_supply = total_supply;
+        self.emit_transfer(None, Some(Account::Peer(*to)), amount, Purpose::General, "mint");
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
     }
 
-    pub fn burn(&mut self, from: &PeerId, amount: u64) -> bool {
-        if self.balance_of(from) < amount {
-            return false;
-        }
-        *self.balances.entry(*from).or_insert(0) -= amount;
+    pub fn burn(&mut self, from: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        self.debit(from, amount)?;
+        // Cannot underflow while the supply invariant holds
         self.total_supply -= amount;
-        true
+        self.emit_transfer(Some(Account::Peer(*from)), None, amount, Purpose::General, "burn");
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
+    }
+
+    /// Pays `amount` tokens out of the unallocated treasury without changing the supply.
+    pub fn disburse(&mut self, to: &PeerId, amount: TokenAmount, memo: &str) -> Result<(), TokenError> {
+        if self.treasury < amount {
+            self.debug_log(&format!("Disbursement failed: treasury cannot cover {} tokens", amount));
+            return Err(TokenError::InsufficientTreasury { required: amount, available: self.treasury });
+        }
+        self.credit(to, amount)?;
+        self.treasury -= amount;
+        self.emit_transfer(Some(Account::Treasury), Some(Account::Peer(*to)), amount, Purpose::General, memo);
+        self.debug_log(&format!("Disbursed {} tokens from the treasury to {}", amount, to));
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
+    }
+
+    /// Locks `amount` tokens from `from` into a new escrow and returns its id. Releases
+    /// and refunds from the escrow are logged under the same `purpose`.
+    pub fn escrow_deposit(&mut self, from: &PeerId, amount: TokenAmount, purpose: Purpose) -> Result<u64, TokenError> {
+        self.debug_log(&format!("Attempting escrow deposit: {} tokens from {}", amount, from));
+        if let Err(e) = self.debit(from, amount) {
+            self.debug_log(&format!("Escrow deposit failed: {}", e));
+            return Err(e);
+        }
+        let escrow_id = self.next_escrow_id;
+        self.next_escrow_id += 1;
+        self.escrows.insert(escrow_id, Escrow { depositor: *from, balance: amount, purpose });
+        self.emit_transfer(Some(Account::Peer(*from)), Some(Account::Escrow(escrow_id)), amount, purpose, "escrow deposit");
+        self.debug_log(&format!("Escrow {} funded with {} tokens", escrow_id, amount));
+        debug_assert!(self.supply_is_consistent());
+        Ok(escrow_id)
+    }
+
+    /// Locks `amount` of `owner`'s tokens into a new escrow on behalf of `spender`,
+    /// drawing down the allowance `owner` granted to `spender`. The escrow still belongs
+    /// to `owner`, so a refund goes back to it. Returns the escrow id.
+    pub fn escrow_deposit_from(&mut self, spender: &PeerId, owner: &PeerId, amount: TokenAmount, purpose: Purpose, memo: &str) -> Result<u64, TokenError> {
+        self.debug_log(&format!("Attempting escrow deposit: {} spending {} tokens of {}", spender, amount, owner));
+        let allowance = self.allowance(owner, spender);
+        if allowance < amount {
+            self.debug_log("Escrow deposit failed: Insufficient allowance");
+            return Err(TokenError::InsufficientAllowance { required: amount, available: allowance });
+        }
+        self.debit(owner, amount)?;
+        self.allowances.entry(*owner).or_default().insert(*spender, allowance - amount);
+        let escrow_id = self.next_escrow_id;
+        self.next_escrow_id += 1;
+        self.escrows.insert(escrow_id, Escrow { depositor: *owner, balance: amount, purpose });
+        self.emit_transfer(Some(Account::Peer(*owner)), Some(Account::Escrow(escrow_id)), amount, purpose, memo);
+        self.debug_log(&format!("Escrow {} funded with {} tokens of {} by {}", escrow_id, amount, owner, spender));
+        debug_assert!(self.supply_is_consistent());
+        Ok(escrow_id)
+    }
+
+    /// Adds `amount` tokens from `from` to an existing escrow.
+    pub fn escrow_top_up(&mut self, escrow_id: u64, from: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let escrow = self.escrows.get(&escrow_id).ok_or(TokenError::EscrowNotFound(escrow_id))?;
+        let purpose = escrow.purpose;
+        let balance = escrow.balance.checked_add(amount).ok_or(TokenError::Overflow)?;
+        self.debit(from, amount)?;
+        self.escrows.get_mut(&escrow_id).unwrap().balance = balance;
+        self.emit_transfer(Some(Account::Peer(*from)), Some(Account::Escrow(escrow_id)), amount, purpose, "escrow top-up");
+        self.debug_log(&format!("Escrow {} topped up with {} tokens from {}", escrow_id, amount, from));
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
+    }
+
+    /// Pays `amount` tokens out of an escrow to `to`.
+    pub fn escrow_release(&mut self, escrow_id: u64, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let escrow = self.escrows.get(&escrow_id).ok_or(TokenError::EscrowNotFound(escrow_id))?;
+        if escrow.balance < amount {
+            self.debug_log(&format!("Escrow release failed: escrow {} cannot cover {} tokens", escrow_id, amount));
+            return Err(TokenError::InsufficientEscrow { escrow_id, required: amount, available: escrow.balance });
+        }
+        let purpose = escrow.purpose;
+        self.credit(to, amount)?;
+        self.escrows.get_mut(&escrow_id).unwrap().balance -= amount;
+        self.emit_transfer(Some(Account::Escrow(escrow_id)), Some(Account::Peer(*to)), amount, purpose, "escrow release");
+        self.debug_log(&format!("Released {} tokens from escrow {} to {}", amount, escrow_id, to));
+        debug_assert!(self.supply_is_consistent());
+        Ok(())
+    }
+
+    /// Hands `amount` tokens of an escrow back to its depositor and keeps the rest locked.
+    pub fn escrow_return(&mut self, escrow_id: u64, amount: TokenAmount) -> Result<(), TokenError> {
+        let depositor = self.escrows.get(&escrow_id).ok_or(TokenError::EscrowNotFound(escrow_id))?.depositor;
+        self.escrow_release(escrow_id, &depositor, amount)
+    }
+
+    /// Closes an escrow, returning whatever is left to the depositor. Returns the refunded amount.
+    pub fn escrow_refund(&mut self, escrow_id: u64) -> Result<TokenAmount, TokenError> {
+        let escrow = self.escrows.get(&escrow_id).cloned().ok_or(TokenError::EscrowNotFound(escrow_id))?;
+        self.credit(&escrow.depositor, escrow.balance)?;
+        self.escrows.remove(&escrow_id);
+        if !escrow.balance.is_zero() {
+            self.emit_transfer(Some(Account::Escrow(escrow_id)), Some(Account::Peer(escrow.depositor)), escrow.balance, escrow.purpose, "escrow refund");
+        }
+        self.debug_log(&format!("Refunded {} tokens from escrow {} to {}", escrow.balance, escrow_id, escrow.depositor));
+        debug_assert!(self.supply_is_consistent());
+        Ok(escrow.balance)
+    }
+
+    pub fn escrow_balance(&self, escrow_id: u64) -> TokenAmount {
+        self.escrows.get(&escrow_id).map_or(TokenAmount::ZERO, |escrow| escrow.balance)
+    }
+
+    pub fn get_escrow(&self, escrow_id: u64) -> Option<&Escrow> {
+        self.escrows.get(&escrow_id)
     }
 }
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use libp2p::PeerId;
+use crate::clock::Timestamp;
+use crate::erc20::TokenError;
+use crate::token_amount::TokenAmount;
+
+const DEFAULT_DRIP: TokenAmount = TokenAmount::from_tokens(1_000);
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(24 * 60 * 60); // one drip per day
+const DEFAULT_LIFETIME_CAP: TokenAmount = TokenAmount::from_tokens(10_000);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FaucetError {
+    /// The identity claimed too recently and must wait `retry_in`.
+    RateLimited { retry_in: Duration },
+    /// The identity has already received everything the faucet will ever give it.
+    CapReached { claimed: TokenAmount, cap: TokenAmount },
+    /// Only registered clients and storage nodes may claim.
+    UnknownPeer,
+    /// The treasury cannot cover the drip.
+    TreasuryEmpty { required: TokenAmount, available: TokenAmount },
+    /// The ledger refused the payout.
+    Ledger(Box<TokenError>),
+}
+
+impl fmt::Display for FaucetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaucetError::RateLimited { retry_in } =>
+                write!(f, "Faucet rate limit reached, try again in {}s", retry_in.as_secs()),
+            FaucetError::CapReached { claimed, cap } =>
+                write!(f, "Faucet cap reached: {} of {} tokens already claimed", claimed, cap),
+            FaucetError::UnknownPeer => write!(f, "Only registered clients and storage nodes can use the faucet"),
+            FaucetError::TreasuryEmpty { required, available } =>
+                write!(f, "Faucet is empty: treasury cannot cover {} tokens, holds {}", required, available),
+            FaucetError::Ledger(e) => write!(f, "Faucet payout failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FaucetError {}
+
+/// What a single identity has taken from the faucet so far.
+#[derive(Clone, Debug)]
+pub struct FaucetClaim {
+    pub last_claim: Timestamp,
+    pub total_claimed: TokenAmount,
+}
+
+/// Hands out treasury tokens in small drips. Each identity may claim once per
+/// `cooldown` and never more than `lifetime_cap` in total. The faucet only decides
+/// what may be paid; the `Network` moves the tokens out of the treasury.
+#[derive(Clone, Debug)]
+pub struct Faucet {
+    drip_amount: TokenAmount,
+    cooldown: Duration,
+    lifetime_cap: TokenAmount,
+    claims: HashMap<PeerId, FaucetClaim>,
+}
+
+impl Default for Faucet {
+    fn default() -> Self {
+        Self::new(DEFAULT_DRIP, DEFAULT_COOLDOWN, DEFAULT_LIFETIME_CAP)
+    }
+}
+
+impl Faucet {
+    pub fn new(drip_amount: TokenAmount, cooldown: Duration, lifetime_cap: TokenAmount) -> Self {
+        Self {
+            drip_amount,
+            cooldown,
+            lifetime_cap,
+            claims: HashMap::new(),
+        }
+    }
+
+    pub fn drip_amount(&self) -> TokenAmount {
+        self.drip_amount
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    pub fn lifetime_cap(&self) -> TokenAmount {
+        self.lifetime_cap
+    }
+
+    pub fn claim_of(&self, peer_id: &PeerId) -> Option<&FaucetClaim> {
+        self.claims.get(peer_id)
+    }
+
+    /// Returns how much `peer_id` may claim at `now`. The last drip is trimmed so the
+    /// lifetime cap is never exceeded.
+    pub fn allowance_at(&self, peer_id: &PeerId, now: Timestamp) -> Result<TokenAmount, FaucetError> {
+        let Some(claim) = self.claims.get(peer_id) else {
+            return Ok(self.drip_amount.min(self.lifetime_cap));
+        };
+        if claim.total_claimed >= self.lifetime_cap {
+            return Err(FaucetError::CapReached { claimed: claim.total_claimed, cap: self.lifetime_cap });
+        }
+        let elapsed = now.saturating_duration_since(claim.last_claim);
+        if elapsed < self.cooldown {
+            return Err(FaucetError::RateLimited { retry_in: self.cooldown - elapsed });
+        }
+        Ok(self.drip_amount.min(self.lifetime_cap - claim.total_claimed))
+    }
+
+    /// Records a successful payout.
+    pub fn record_claim(&mut self, peer_id: &PeerId, amount: TokenAmount, now: Timestamp) -> Result<(), FaucetError> {
+        let claim = self.claims.entry(*peer_id).or_insert(FaucetClaim { last_claim: now, total_claimed: TokenAmount::ZERO });
+        claim.total_claimed = claim.total_claimed.checked_add(amount).ok_or(FaucetError::Ledger(Box::new(TokenError::Overflow)))?;
+        claim.last_claim = now;
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use libp2p::PeerId;
+use serde_json::{json, Value};
+use crate::erc20::TokenError;
+use crate::token_amount::TokenAmount;
+use crate::token_ledger::TokenLedger;
+
+pub type Address = [u8; 20];
+
+// 4-byte function selectors: the first bytes of keccak256 of each signature
+pub const BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31]; // balanceOf(address)
+pub const ALLOWANCE_SELECTOR: [u8; 4] = [0xdd, 0x62, 0xed, 0x3e]; // allowance(address,address)
+pub const TRANSFER_SELECTOR: [u8; 4] = [0xa9, 0x05, 0x9c, 0xbb]; // transfer(address,uint256)
+pub const APPROVE_SELECTOR: [u8; 4] = [0x09, 0x5e, 0xa7, 0xb3]; // approve(address,uint256)
+pub const TRANSFER_FROM_SELECTOR: [u8; 4] = [0x23, 0xb8, 0x72, 0xdd]; // transferFrom(address,address,uint256)
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for a sent transaction to be mined, and how often to check.
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(30);
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+fn encode_address(address: &Address) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(address);
+    word
+}
+
+fn encode_uint(value: u128) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+fn encode_call(selector: [u8; 4], words: &[[u8; 32]]) -> Vec<u8> {
+    let mut data = selector.to_vec();
+    for word in words {
+        data.extend_from_slice(word);
+    }
+    data
+}
+
+pub fn encode_balance_of(owner: &Address) -> Vec<u8> {
+    encode_call(BALANCE_OF_SELECTOR, &[encode_address(owner)])
+}
+
+pub fn encode_allowance(owner: &Address, spender: &Address) -> Vec<u8> {
+    encode_call(ALLOWANCE_SELECTOR, &[encode_address(owner), encode_address(spender)])
+}
+
+pub fn encode_transfer(to: &Address, amount: TokenAmount) -> Vec<u8> {
+    encode_call(TRANSFER_SELECTOR, &[encode_address(to), encode_uint(amount.base_units())])
+}
+
+pub fn encode_approve(spender: &Address, amount: TokenAmount) -> Vec<u8> {
+    encode_call(APPROVE_SELECTOR, &[encode_address(spender), encode_uint(amount.base_units())])
+}
+
+pub fn encode_transfer_from(from: &Address, to: &Address, amount: TokenAmount) -> Vec<u8> {
+    encode_call(TRANSFER_FROM_SELECTOR, &[encode_address(from), encode_address(to), encode_uint(amount.base_units())])
+}
+
+/// Decodes a uint256 token amount, rejecting values that do not fit in a `u128`.
+pub fn decode_uint(data: &[u8]) -> Result<TokenAmount, TokenError> {
+    if data.len() != 32 {
+        return Err(TokenError::Backend(format!("Expected a 32 byte word, got {} bytes", data.len())));
+    }
+    if data[..16].iter().any(|&byte| byte != 0) {
+        return Err(TokenError::Overflow);
+    }
+    let mut value = [0u8; 16];
+    value.copy_from_slice(&data[16..]);
+    Ok(TokenAmount::from_base_units(u128::from_be_bytes(value)))
+}
+
+fn decode_address(word: &[u8]) -> Option<Address> {
+    let mut address = [0u8; 20];
+    address.copy_from_slice(word.get(12..32)?);
+    Some(address)
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+pub fn from_hex(value: &str) -> Result<Vec<u8>, TokenError> {
+    hex::decode(value.trim_start_matches("0x")).map_err(|e| TokenError::Backend(format!("Invalid hex: {}", e)))
+}
+
+/// Sends one HTTP/1.1 POST and returns the response body.
+fn http_post(host: &str, path: &str, body: &str) -> io::Result<String> {
+    let mut stream = TcpStream::connect(host)?;
+    stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+    stream.set_write_timeout(Some(RPC_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path, host, body.len(), body
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    response.split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed HTTP response"))
+}
+
+/// A `TokenLedger` backed by an ERC20 contract reached over Ethereum JSON-RPC.
+/// Reads go through `eth_call`; writes are `eth_sendTransaction`s from the node's
+/// unlocked account for the acting peer, as on a Geth dev testnet.
+pub struct JsonRpcLedger {
+    host: String,
+    path: String,
+    contract: Address,
+    addresses: HashMap<PeerId, Address>,
+    next_id: AtomicU64,
+}
+
+impl JsonRpcLedger {
+    /// `endpoint` is an `http://host:port[/path]` URL.
+    pub fn new(endpoint: &str, contract: Address) -> Self {
+        let without_scheme = endpoint.trim_start_matches("http://");
+        let (host, path) = match without_scheme.find('/') {
+            Some(index) => (&without_scheme[..index], &without_scheme[index..]),
+            None => (without_scheme, "/"),
+        };
+        Self {
+            host: host.to_string(),
+            path: path.to_string(),
+            contract,
+            addresses: HashMap::new(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Maps a peer to the Ethereum account that holds its tokens.
+    pub fn register_account(&mut self, peer_id: PeerId, address: Address) {
+        self.addresses.insert(peer_id, address);
+    }
+
+    fn address_of(&self, peer_id: &PeerId) -> Result<Address, TokenError> {
+        self.addresses.get(peer_id).copied()
+            .ok_or_else(|| TokenError::Backend(format!("No Ethereum address registered for {}", peer_id)))
+    }
+
+    fn request(&self, method: &str, params: Value) -> Result<Value, TokenError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let body = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }).to_string();
+        let response = http_post(&self.host, &self.path, &body)
+            .map_err(|e| TokenError::Backend(format!("JSON-RPC request failed: {}", e)))?;
+        let response: Value = serde_json::from_str(&response)
+            .map_err(|e| TokenError::Backend(format!("Invalid JSON-RPC response: {}", e)))?;
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or("unknown error");
+            return Err(TokenError::Backend(format!("{} failed: {}", method, message)));
+        }
+        response.get("result").cloned().ok_or_else(|| TokenError::Backend("JSON-RPC response has no result".to_string()))
+    }
+
+    fn call(&self, data: Vec<u8>) -> Result<TokenAmount, TokenError> {
+        let result = self.request("eth_call", json!([{ "to": to_hex(&self.contract), "data": to_hex(&data) }, "latest"]))?;
+        let result = result.as_str().ok_or_else(|| TokenError::Backend("eth_call result is not a string".to_string()))?;
+        decode_uint(&from_hex(result)?)
+    }
+
+    /// Sends a transaction and waits for its receipt, so a transaction that reverts on
+    /// chain fails here rather than only when the balances are next read.
+    fn send_transaction(&self, from: &PeerId, data: Vec<u8>) -> Result<(), TokenError> {
+        let from = self.address_of(from)?;
+        let hash = self.request("eth_sendTransaction", json!([{ "from": to_hex(&from), "to": to_hex(&self.contract), "data": to_hex(&data) }]))?;
+        let hash = hash.as_str().ok_or_else(|| TokenError::Backend("eth_sendTransaction result is not a string".to_string()))?;
+        self.wait_for_receipt(hash)
+    }
+
+    fn wait_for_receipt(&self, hash: &str) -> Result<(), TokenError> {
+        let mut waited = Duration::ZERO;
+        loop {
+            let receipt = self.request("eth_getTransactionReceipt", json!([hash]))?;
+            if !receipt.is_null() {
+                return match receipt.get("status").and_then(Value::as_str) {
+                    Some("0x1") => Ok(()),
+                    Some("0x0") => Err(TokenError::Reverted(hash.to_string())),
+                    status => Err(TokenError::Backend(format!("Receipt for {} has an invalid status: {:?}", hash, status))),
+                };
+            }
+            if waited >= RECEIPT_TIMEOUT {
+                return Err(TokenError::Backend(format!("Transaction {} was not mined within {:?}", hash, RECEIPT_TIMEOUT)));
+            }
+            thread::sleep(RECEIPT_POLL_INTERVAL);
+            waited += RECEIPT_POLL_INTERVAL;
+        }
+    }
+}
+
+impl TokenLedger for JsonRpcLedger {
+    fn balance_of(&self, account: &PeerId) -> Result<TokenAmount, TokenError> {
+        self.call(encode_balance_of(&self.address_of(account)?))
+    }
+
+    fn allowance(&self, owner: &PeerId, spender: &PeerId) -> Result<TokenAmount, TokenError> {
+        self.call(encode_allowance(&self.address_of(owner)?, &self.address_of(spender)?))
+    }
+
+    fn transfer(&mut self, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let data = encode_transfer(&self.address_of(to)?, amount);
+        self.send_transaction(from, data)
+    }
+
+    fn approve(&mut self, owner: &PeerId, spender: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let data = encode_approve(&self.address_of(spender)?, amount);
+        self.send_transaction(owner, data)
+    }
+
+    fn transfer_from(&mut self, spender: &PeerId, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        let data = encode_transfer_from(&self.address_of(from)?, &self.address_of(to)?, amount);
+        self.send_transaction(spender, data)
+    }
+}
+
+/// Contract state held by the mock node.
+#[derive(Default)]
+struct MockContract {
+    balances: HashMap<Address, TokenAmount>,
+    allowances: HashMap<(Address, Address), TokenAmount>,
+    transactions: u64,
+    /// Whether each mined transaction succeeded, by hash.
+    receipts: HashMap<String, bool>,
+}
+
+impl MockContract {
+    fn move_tokens(&mut self, from: Address, to: Address, amount: TokenAmount) -> Result<(), &'static str> {
+        let available = self.balances.get(&from).copied().unwrap_or_default();
+        if available < amount {
+            return Err("execution reverted: transfer amount exceeds balance");
+        }
+        let credited = self.balances.get(&to).copied().unwrap_or_default().checked_add(amount).ok_or("execution reverted: overflow")?;
+        self.balances.insert(from, available - amount);
+        self.balances.insert(to, credited);
+        Ok(())
+    }
+
+    fn call(&self, data: &[u8]) -> Result<TokenAmount, &'static str> {
+        let word = |index: usize| data.get(4 + index * 32..4 + (index + 1) * 32).ok_or("execution reverted: short calldata");
+        match data.get(..4) {
+            Some(selector) if selector == BALANCE_OF_SELECTOR => {
+                let owner = decode_address(word(0)?).ok_or("execution reverted")?;
+                Ok(self.balances.get(&owner).copied().unwrap_or_default())
+            }
+            Some(selector) if selector == ALLOWANCE_SELECTOR => {
+                let owner = decode_address(word(0)?).ok_or("execution reverted")?;
+                let spender = decode_address(word(1)?).ok_or("execution reverted")?;
+                Ok(self.allowances.get(&(owner, spender)).copied().unwrap_or_default())
+            }
+            _ => Err("execution reverted: unknown selector"),
+        }
+    }
+
+    fn transact(&mut self, sender: Address, data: &[u8]) -> Result<(), &'static str> {
+        let word = |index: usize| data.get(4 + index * 32..4 + (index + 1) * 32).ok_or("execution reverted: short calldata");
+        let amount = |index: usize| word(index).and_then(|word| decode_uint(word).map_err(|_| "execution reverted: amount too large"));
+        match data.get(..4) {
+            Some(selector) if selector == TRANSFER_SELECTOR => {
+                let to = decode_address(word(0)?).ok_or("execution reverted")?;
+                self.move_tokens(sender, to, amount(1)?)
+            }
+            Some(selector) if selector == APPROVE_SELECTOR => {
+                let spender = decode_address(word(0)?).ok_or("execution reverted")?;
+                self.allowances.insert((sender, spender), amount(1)?);
+                Ok(())
+            }
+            Some(selector) if selector == TRANSFER_FROM_SELECTOR => {
+                let from = decode_address(word(0)?).ok_or("execution reverted")?;
+                let to = decode_address(word(1)?).ok_or("execution reverted")?;
+                let amount = amount(2)?;
+                let allowance = self.allowances.get(&(from, sender)).copied().unwrap_or_default();
+                if allowance < amount {
+                    return Err("execution reverted: insufficient allowance");
+                }
+                self.move_tokens(from, to, amount)?;
+                self.allowances.insert((from, sender), allowance - amount);
+                Ok(())
+            }
+            _ => Err("execution reverted: unknown selector"),
+        }
+    }
+
+    fn handle(&mut self, request: &Value) -> Value {
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let params = request.get("params").and_then(Value::as_array).cloned().unwrap_or_default();
+        let tx = params.first().cloned().unwrap_or(Value::Null);
+        let data = tx.get("data").and_then(Value::as_str).map(from_hex);
+        let result = match (request.get("method").and_then(Value::as_str), data) {
+            (Some("eth_call"), Some(Ok(data))) => self.call(&data).map(|value| json!(to_hex(&encode_uint(value.base_units())))),
+            (Some("eth_sendTransaction"), Some(Ok(data))) => {
+                let sender = tx.get("from").and_then(Value::as_str).map(from_hex);
+                match sender {
+                    Some(Ok(sender)) if sender.len() == 20 => {
+                        let mut address = [0u8; 20];
+                        address.copy_from_slice(&sender);
+                        // Like a real node, a transaction that reverts is still mined and
+                        // only its receipt shows the failure
+                        let succeeded = self.transact(address, &data).is_ok();
+                        self.transactions += 1;
+                        let hash = to_hex(&encode_uint(self.transactions as u128));
+                        self.receipts.insert(hash.clone(), succeeded);
+                        Ok(json!(hash))
+                    }
+                    _ => Err("invalid sender"),
+                }
+            }
+            (Some("eth_getTransactionReceipt"), _) => {
+                let hash = tx.as_str().unwrap_or_default().to_string();
+                Ok(match self.receipts.get(&hash) {
+                    Some(&succeeded) => json!({ "transactionHash": hash, "status": if succeeded { "0x1" } else { "0x0" } }),
+                    None => Value::Null,
+                })
+            }
+            (Some("eth_chainId"), _) => Ok(json!("0x539")),
+            _ => Err("unsupported request"),
+        };
+        match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(message) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32000, "message": message } }),
+        }
+    }
+}
+
+/// A minimal in-process stand-in for a Geth node with one ERC20 contract deployed,
+/// so `JsonRpcLedger` can be exercised offline. Stops when dropped.
+pub struct MockRpcServer {
+    address: SocketAddr,
+    contract: Arc<Mutex<MockContract>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl MockRpcServer {
+    pub fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let contract = Arc::new(Mutex::new(MockContract::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let server_contract = Arc::clone(&contract);
+        let server_shutdown = Arc::clone(&shutdown);
+        thread::spawn(move || {
+            while !server_shutdown.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let _ = Self::serve(stream, &server_contract);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(5)),
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Self { address, contract, shutdown })
+    }
+
+    fn serve(stream: TcpStream, contract: &Mutex<MockContract>) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(RPC_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+            }
+        }
+        let mut body = vec![0u8; content_length];
+        reader.read_exact(&mut body)?;
+
+        let response = match serde_json::from_slice::<Value>(&body) {
+            Ok(request) => contract.lock().unwrap().handle(&request),
+            Err(_) => json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": "parse error" } }),
+        }.to_string();
+
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.len(), response
+        )?;
+        stream.flush()
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Credits `amount` tokens to `address`, standing in for the contract's initial distribution.
+    pub fn mint(&self, address: Address, amount: TokenAmount) -> Result<(), TokenError> {
+        let mut contract = self.contract.lock().unwrap();
+        let balance = contract.balances.entry(address).or_default();
+        *balance = balance.checked_add(amount).ok_or(TokenError::Overflow)?;
+        Ok(())
+    }
+
+    pub fn balance(&self, address: &Address) -> TokenAmount {
+        self.contract.lock().unwrap().balances.get(address).copied().unwrap_or_default()
+    }
+}
+
+impl Drop for MockRpcServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+}
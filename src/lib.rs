@@ -2,10 +2,28 @@ pub mod network;
 pub mod storage_node;
 pub mod client;
 pub mod erc20;
+pub mod payment_channel;
+pub mod token_ledger;
+pub mod jsonrpc;
+pub mod token_amount;
+pub mod faucet;
+pub mod auction;
+pub mod placement;
+pub mod marketplace;
+pub mod reputation;
+pub mod pricing;
+pub mod deal;
+pub mod clock;
+pub mod scheduler;
+pub mod rebalance;
+pub mod peer;
+pub mod protocol;
+pub mod cluster;
 
 pub use network::{Network, DebugLevel};
 pub use storage_node::StorageNode;
 pub use client::Client;
+pub use token_amount::TokenAmount;
 
 
 
@@ -17,8 +35,8 @@ mod tests {
     #[test]
     fn test_network_creation() -> Result<(), Box<dyn std::error::Error>> {
         let network = Network::new()?;
-        assert!(!network.storage_nodes().is_empty(), "Network should initialize with default storage nodes");
-        assert!(!network.clients().is_empty(), "Network should initialize with default clients");
+        assert!(network.storage_nodes().is_empty(), "Network should start without storage nodes");
+        assert!(network.clients().is_empty(), "Network should start without clients");
         Ok(())
     }
 
@@ -27,7 +45,7 @@ mod tests {
         let mut network = Network::new()?;
         let initial_count = network.storage_nodes().len();
         let peer_id = PeerId::random();
-        network.add_storage_node(peer_id, 10); // Add a default price of 10
+        network.add_storage_node(peer_id, TokenAmount::from_base_units(10)); // Add a default price of 10 base units per byte-epoch
         assert_eq!(network.storage_nodes().len(), initial_count + 1);
         assert!(network.storage_nodes().contains_key(&peer_id));
         Ok(())
@@ -53,16 +71,17 @@ mod tests {
         // Add multiple storage nodes to ensure enough are available
         for _ in 0..5 {
             let storage_node_id = PeerId::random();
-            network.add_storage_node(storage_node_id, 10); // Add a default price of 10
+            network.add_storage_node(storage_node_id, TokenAmount::from_base_units(10)); // Add a default price of 10 base units per byte-epoch
         }
-        Ok(());
 
         let filename = "test.txt".to_string();
         let data = b"Hello, world!".to_vec();
 
         // Check initial balance
+        assert_eq!(network.get_balance(&client_id), TokenAmount::ZERO, "New clients should start without tokens");
+        network.request_faucet_funds(&client_id)?;
         let initial_balance = network.get_balance(&client_id);
-        assert_eq!(initial_balance, 1_000_000, "Initial balance should be 1,000,000");
+        assert_eq!(initial_balance, network.faucet.drip_amount(), "Initial balance should be one faucet drip");
 
         // Upload file
         network.upload_file(&client_id, filename.clone(), data.clone(), 3) // Using default replication factor of 3
@@ -74,8 +93,8 @@ mod tests {
 
         // Check that the client's balance is deducted
         let client_balance = network.get_balance(&client_id);
-        assert!(client_balance < 1_000_000, "Balance should be less than initial balance");
-        let expected_deduction = 3 * 10; // 3 replications * 10 tokens per GB (rounded up)
+        assert!(client_balance < initial_balance, "Balance should be less than initial balance");
+        let expected_deduction = TokenAmount::from_base_units(3 * 13 * 24 * 10); // 3 replications * 13 bytes * 24 epochs * 10
         assert_eq!(initial_balance - client_balance, expected_deduction, "Balance should be deducted by the correct amount");
 
         // Remove file
@@ -83,6 +102,7 @@ mod tests {
 
         // Try to download removed file
         assert!(network.download_file(&client_id, &filename).is_err());
+        Ok(())
     }
 
     #[test]
@@ -94,16 +114,18 @@ mod tests {
         // Add multiple storage nodes to ensure enough are available for replication
         for _ in 0..5 {
             let storage_node_id = PeerId::random();
-            network.add_storage_node(storage_node_id, 10); // Add a default price of 10
+            network.add_storage_node(storage_node_id, TokenAmount::from_base_units(10)); // Add a default price of 10 base units per byte-epoch
         }
-        Ok(());
 
         let filename = "replicated.txt".to_string();
         let data = b"Replicate me!".to_vec();
 
         // Check initial balance
+        assert_eq!(network.get_balance(&client_id), TokenAmount::ZERO, "New clients should start without tokens");
+        network.request_faucet_funds(&client_id)?;
+        assert!(network.request_faucet_funds(&client_id).is_err(), "A second drip should wait for the cooldown");
         let initial_balance = network.get_balance(&client_id);
-        assert_eq!(initial_balance, 1_000_000, "Initial balance should be 1,000,000");
+        assert_eq!(initial_balance, network.faucet.drip_amount(), "Initial balance should be one faucet drip");
 
         // Upload file
         network.upload_file(&client_id, filename.clone(), data.clone(), 3)
@@ -118,7 +140,9 @@ mod tests {
 
         // Check that the client's balance is deducted
         let client_balance = network.get_balance(&client_id);
-        assert!(client_balance < 1_000_000, "Balance should be less than initial balance");
-        assert!(client_balance >= 999_970, "Balance should not be deducted by more than 30 tokens");
+        assert!(client_balance < initial_balance, "Balance should be less than initial balance");
+        let expected_deduction = TokenAmount::from_base_units(5 * 13 * 24 * 10); // 3 uploaded and 2 replicated copies
+        assert_eq!(initial_balance - client_balance, expected_deduction, "Balance should be deducted for every stored copy");
+        Ok(())
     }
 }
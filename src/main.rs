@@ -12,15 +12,24 @@ use ratatui::{
     widgets::{Block, Borders, List, ListItem, Paragraph, ListState},
     Frame, Terminal,
 };
-use std::{env, error::Error, io, time::{Duration, Instant}};
-use pioneerfs::{Network, DebugLevel};
+use std::{collections::HashSet, env, error::Error, io, time::{Duration, Instant}};
+use pioneerfs::{Network, DebugLevel, TokenAmount};
+use pioneerfs::auction::AuctionTerms;
+use pioneerfs::network::EPOCH_DURATION;
+use pioneerfs::placement::{strategy_by_name, UploadConstraints};
+use pioneerfs::pricing::PricingPolicy;
+use pioneerfs::rebalance::RebalancePolicy;
+use pioneerfs::deal::{Deal, DealState};
+use pioneerfs::scheduler::{self, MaintenanceJob};
 use std::sync::{Arc, Mutex};
 use tokio::task;
 
 mod webui;
-use libp2p::{PeerId, swarm::SwarmEvent};
+use libp2p::PeerId;
 use rand::Rng;
 
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
 enum InputMode {
     Normal,
     Editing,
@@ -51,7 +60,6 @@ impl App {
         let sender = tx.clone();
         app.network.lock().unwrap().message_sender = Some(sender.clone());
         app.network.lock().unwrap().message_sender = Some(sender);
-        adjust_pricing(&mut network.lock().unwrap());
         app
     }
 }
@@ -62,19 +70,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
     
     if args.contains(&"--test".to_string()) {
         // Run in test mode
-        let mut network = Network::new();
+        let mut network = Network::new()?;
         network.set_debug_level(DebugLevel::Low);
         let (tx, _rx) = broadcast::channel(100);
         let _ = run_replication_tests(&mut network, tx.clone());
     } else if args.contains(&"--advanced-tests".to_string()) {
         // Run advanced network tests
-        let mut network = Network::new();
+        let mut network = Network::new()?;
         network.set_debug_level(DebugLevel::Low);
         let (tx, _rx) = broadcast::channel(100);
         run_advanced_network_tests(&mut network, tx.clone());
     } else {
         // Run in normal mode
-        let network = Arc::new(Mutex::new(Network::new()));
+        let network = Arc::new(Mutex::new(Network::new()?));
 
         let (tx, rx) = broadcast::channel(100);
 
@@ -86,6 +94,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
             })
         };
 
+        // Deal expiry, pricing, audits, repairs and offer expiry run in the background
+        let _scheduler_handle = scheduler::spawn(Arc::clone(&network), SCHEDULER_TICK);
+
         let terminal_handle = {
             let network_clone = Arc::clone(&network);
             task::spawn_blocking(move || -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -138,9 +149,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn adjust_pricing(_network: &mut Network) {
-}
-
 fn run_replication_tests(_network: &mut Network, _tx: broadcast::Sender<String>) -> Result<(), Box<dyn Error>> {
     let _rng = rand::thread_rng();
     // Add the missing closing brace for the function
@@ -298,6 +306,19 @@ fn ui(f: &mut Frame, app: &mut App) {
     f.render_stateful_widget(messages, chunks[2], &mut app.messages_state);
 }
 
+/// Parses a hex-encoded peer id from a command argument, reporting a bad one in `messages`.
+fn parse_peer_id(messages: &mut Vec<String>, hex_id: &str) -> Option<PeerId> {
+    let peer_id = hex::decode(hex_id).map_err(|e| e.to_string())
+        .and_then(|bytes| PeerId::from_bytes(&bytes).map_err(|e| e.to_string()));
+    match peer_id {
+        Ok(peer_id) => Some(peer_id),
+        Err(e) => {
+            messages.push(format!("Invalid peer id {}: {}", hex_id, e));
+            None
+        }
+    }
+}
+
 fn execute_command(app: &mut App) {
     let command = app.input.trim();
     app.messages.push(format!("Executing: {}", command));
@@ -312,32 +333,82 @@ fn execute_command(app: &mut App) {
             app.messages.push("Available commands:".to_string());
             app.messages.push("  help - Display this help message".to_string());
             app.messages.push("  add_client - Add a new client".to_string());
-            app.messages.push("  add_sp <price_per_gb> - Add a new storage provider (SP)".to_string());
+            app.messages.push("  faucet <peer_id> - Request a rate-limited drip of tokens from the treasury".to_string());
+            app.messages.push("  add_sp <price_per_byte_epoch> - Add a new storage provider (SP), priced per byte stored per epoch".to_string());
             app.messages.push("  list_clients - List all clients".to_string());
             app.messages.push("  list_sps - List all storage providers".to_string());
             app.messages.push("  upload_file <client_id> <sp_id> <filename> <content> - Upload a file".to_string());
+            app.messages.push("  upload_constrained <client_id> <filename> <content> <replication_factor> <max_price_per_byte_epoch> <min_reputation> <min_free_space> [excluded_sp_ids] - Upload only to SPs that meet the given limits".to_string());
+            app.messages.push("  upload_for <client_id> <filename> <content> <replication_factor> <hours> - Upload under deals of a chosen length".to_string());
+            app.messages.push("  upload_with_placement <client_id> <filename> <content> <replication_factor> <strategy> - Upload with a specific placement strategy".to_string());
+            app.messages.push("  set_placement <random|cheapest|reputation|capacity|region> - Set the network's default placement strategy".to_string());
+            app.messages.push("  set_region <sp_id> <region> - Record the region an SP is located in".to_string());
+            app.messages.push("  stream_upload <client_id> <filename> <content> <replication_factor> <budget_per_sp> - Upload a file paying per byte as it streams".to_string());
             app.messages.push("  download_file <client_id> <sp_id> <filename> - Download a file".to_string());
-            app.messages.push("  renew_deal <client_id> <sp_id> <filename> - Renew a storage deal".to_string());
-            app.messages.push("  check_deals - Check and remove expired deals".to_string());
-            app.messages.push("  get_reputation <sp_id> - Get the reputation of a storage provider".to_string());
-            app.messages.push("  add_storage_offer <sp_id> <price_per_gb> <available_space> - Add a storage offer to the marketplace".to_string());
-            app.messages.push("  list_storage_offers - List all storage offers in the marketplace".to_string());
-            app.messages.push("  accept_storage_offer <client_id> <offer_index> <file_size> - Accept a storage offer".to_string());
+            app.messages.push("  replicate_file <client_id> <filename> <replications> - Chain-replicate a file, paid through its first SP".to_string());
+            app.messages.push("  replication_payments <client_id> <filename> - Show who paid whom for a file's chain replication".to_string());
+            app.messages.push("  renew_deal <client_id> <sp_id> <filename> <hours> - Extend a storage deal at the SP's current price".to_string());
+            app.messages.push("  auto_renew <client_id> <deal_id> <hours> <budget> - Renew a deal automatically from an escrowed budget".to_string());
+            app.messages.push("  cancel_auto_renew <client_id> <deal_id> - Stop auto-renewal and refund the unspent budget".to_string());
+            app.messages.push("  adjust_prices - Reprice every SP from utilisation, offers and auction results".to_string());
+            app.messages.push("  set_pricing_policy <sp_id> <floor> <ceiling> <max_step_bps> <target_utilisation_bps> - Opt an SP into automatic pricing within bounds".to_string());
+            app.messages.push("  clear_pricing_policy <sp_id> - Stop repricing an SP automatically".to_string());
+            app.messages.push("  price_history <sp_id> - Show the automatic price changes of an SP".to_string());
+            app.messages.push("  set_rebalance_policy <on|off> <max_bytes_per_run> <max_bytes_per_node> <min_gap_bps> - Configure moving replicas to emptier SPs".to_string());
+            app.messages.push("  rebalance - Move replicas to emptier SPs now".to_string());
+            app.messages.push("  rebalance_history - Show the replicas the rebalancer has moved".to_string());
+            app.messages.push("  list_deals [client <client_id> | sp <sp_id> | file <client_id> <filename> | state <state>] - List deals, optionally filtered".to_string());
+            app.messages.push("  deal_info <deal_id> - Show a deal and the states it has been through".to_string());
+            app.messages.push("  check_deals - Renew or expire deals whose term has run out, deleting data after the grace period".to_string());
+            app.messages.push("  jobs - Show the background jobs with their last and next runs".to_string());
+            app.messages.push("  run_job <job> - Run a background job now (check_deals, adjust_prices, audits, repair_scan, expire_offers, rebalance)".to_string());
+            app.messages.push("  pause_job <job|all> / resume_job <job|all> - Pause or resume one background job or the whole scheduler".to_string());
+            app.messages.push("  set_job_interval <job> <seconds> - Change how often a background job runs".to_string());
+            app.messages.push("  under_replicated - List files that lost replicas no SP could replace yet".to_string());
+            app.messages.push("  repair_replication <client_id> <filename> - Retry placing a file's missing replicas".to_string());
+            app.messages.push("  advance_epoch - Pay out escrowed deals for one epoch".to_string());
+            app.messages.push("  terminate_deal <client_id> <sp_id> <filename> - End a deal early and refund the remainder".to_string());
+            app.messages.push("  get_reputation <sp_id> - Show an SP's reputation score and its breakdown".to_string());
+            app.messages.push("  set_retrieval_price <sp_id> <price_per_byte> - Publish the price an SP charges for serving data".to_string());
+            app.messages.push("  stake <sp_id> <amount> - Lock an SP's tokens as stake".to_string());
+            app.messages.push("  drain <sp_id> - Move an SP's deals to other SPs and release its stake so it can leave".to_string());
+            app.messages.push("  get_earnings <sp_id> - Show an SP's storage and retrieval earnings".to_string());
+            app.messages.push("  token_history <peer_id> - Show the token events involving a peer".to_string());
+            app.messages.push("  post_request <client_id> <filename> <content> <replication_factor> <epochs> <max_price_per_byte_epoch> <window_secs> - Auction a storage request to SPs".to_string());
+            app.messages.push("  bid <sp_id> <request_id> <price_per_byte_epoch> - Bid on an open storage request".to_string());
+            app.messages.push("  close_request <request_id> - Award a storage request to the lowest bids once bidding has closed".to_string());
+            app.messages.push("  auction_notices <sp_id> - Show how an SP's bids ended".to_string());
+            app.messages.push("  add_storage_offer <sp_id> <price_per_byte_epoch> <available_space> <lifetime_epochs> - Add a storage offer to the marketplace".to_string());
+            app.messages.push("  list_storage_offers - List all storage offers in the marketplace, cheapest first".to_string());
+            app.messages.push("  accept_storage_offer <client_id> <offer_id> <filename> <content> - Store a file under a storage offer".to_string());
+            app.messages.push("  cancel_storage_offer <sp_id> <offer_id> - Withdraw an SP's storage offer".to_string());
+            app.messages.push("  update_offer_price <sp_id> <offer_id> <price_per_byte_epoch> - Reprice the unfilled part of an offer".to_string());
         }
         "add_client" => {
             let peer_id = PeerId::random();
             app.network.lock().unwrap().add_client(peer_id);
             app.messages.push(format!("Added client with PeerId: {}", peer_id));
         }
+        "faucet" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: faucet <peer_id>".to_string());
+                return;
+            }
+            let Some(peer_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            match app.network.lock().unwrap().request_faucet_funds(&peer_id) {
+                Ok(amount) => app.messages.push(format!("Faucet sent {} tokens to {}", amount, peer_id)),
+                Err(e) => app.messages.push(format!("Faucet request failed: {}", e)),
+            }
+        }
         "add_sp" => {
             if parts.len() != 2 {
-                app.messages.push("Usage: add_sp <price_per_gb>".to_string());
+                app.messages.push("Usage: add_sp <price_per_byte_epoch>".to_string());
                 return;
             }
             let peer_id = PeerId::random();
-            let price_per_gb = parts[1].parse::<u64>().unwrap_or(0);
-            app.network.lock().unwrap().add_storage_node(peer_id, price_per_gb);
-            app.messages.push(format!("Added storage provider (SP) with PeerId: {} and price per GB: {}", peer_id, price_per_gb));
+            let price_per_byte_epoch = parts[1].parse::<TokenAmount>().unwrap_or_default();
+            app.network.lock().unwrap().add_storage_node(peer_id, price_per_byte_epoch);
+            app.messages.push(format!("Added storage provider (SP) with PeerId: {} and price per byte-epoch: {}", peer_id, price_per_byte_epoch));
         }
         "list_clients" => {
             let clients = app.network.lock().unwrap().list_clients();
@@ -358,8 +429,8 @@ fn execute_command(app: &mut App) {
                 app.messages.push("Usage: upload_file <client_id> <sp_id> <filename> <content> <replication_factor>".to_string());
                 return;
             }
-            let client_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
-            let _sp_id = PeerId::from_bytes(&hex::decode(parts[2]).unwrap()).unwrap();
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let Some(_sp_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
             let filename = parts[3].to_string();
             let content = parts[4].as_bytes().to_vec();
             let replication_factor = parts[5].parse::<usize>().unwrap_or(3); // Default to 3 if parsing fails
@@ -369,13 +440,118 @@ fn execute_command(app: &mut App) {
                 Err(e) => app.messages.push(format!("Failed to upload file: {}", e)),
             }
         }
+        "upload_constrained" => {
+            if parts.len() != 8 && parts.len() != 9 {
+                app.messages.push("Usage: upload_constrained <client_id> <filename> <content> <replication_factor> <max_price_per_byte_epoch> <min_reputation> <min_free_space> [excluded_sp_ids]".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2].to_string();
+            let content = parts[3].as_bytes().to_vec();
+            let replication_factor = parts[4].parse::<usize>().unwrap_or(3);
+            let mut excluded_peers = HashSet::new();
+            for id in parts.get(8).map(|ids| ids.split(',').collect::<Vec<_>>()).unwrap_or_default() {
+                let Some(peer_id) = parse_peer_id(&mut app.messages, id) else { return; };
+                excluded_peers.insert(peer_id);
+            }
+            let constraints = UploadConstraints {
+                max_price_per_byte_epoch: parts[5].parse::<TokenAmount>().ok(),
+                min_reputation: parts[6].parse::<u64>().unwrap_or(0),
+                min_free_space: parts[7].parse::<usize>().unwrap_or(0),
+                excluded_peers,
+                duration: None,
+            };
+
+            match app.network.lock().unwrap().upload_file_with_constraints(&client_id, filename, content, replication_factor, &constraints, None) {
+                Ok(nodes) => app.messages.push(format!("File uploaded to {:?}", nodes)),
+                Err(e) => app.messages.push(format!("Failed to upload file: {}", e)),
+            }
+        }
+        "upload_for" => {
+            if parts.len() != 6 {
+                app.messages.push("Usage: upload_for <client_id> <filename> <content> <replication_factor> <hours>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2].to_string();
+            let content = parts[3].as_bytes().to_vec();
+            let replication_factor = parts[4].parse::<usize>().unwrap_or(3);
+            let duration = Duration::from_secs(parts[5].parse::<u64>().unwrap_or(24) * 60 * 60);
+
+            match app.network.lock().unwrap().upload_file_for(&client_id, filename, content, replication_factor, duration) {
+                Ok(nodes) => app.messages.push(format!("File uploaded to {:?} for {} hours", nodes, duration.as_secs() / 3600)),
+                Err(e) => app.messages.push(format!("Failed to upload file: {}", e)),
+            }
+        }
+        "upload_with_placement" => {
+            if parts.len() != 6 {
+                app.messages.push("Usage: upload_with_placement <client_id> <filename> <content> <replication_factor> <strategy>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2].to_string();
+            let content = parts[3].as_bytes().to_vec();
+            let replication_factor = parts[4].parse::<usize>().unwrap_or(3);
+            let strategy = match strategy_by_name(parts[5]) {
+                Ok(strategy) => strategy,
+                Err(e) => {
+                    app.messages.push(e);
+                    return;
+                }
+            };
+
+            match app.network.lock().unwrap().upload_file_with_constraints(&client_id, filename, content, replication_factor, &UploadConstraints::default(), Some(strategy.as_ref())) {
+                Ok(nodes) => app.messages.push(format!("File placed by {} on {:?}", strategy.name(), nodes)),
+                Err(e) => app.messages.push(format!("Failed to upload file: {}", e)),
+            }
+        }
+        "set_placement" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: set_placement <random|cheapest|reputation|capacity|region>".to_string());
+                return;
+            }
+            match strategy_by_name(parts[1]) {
+                Ok(strategy) => {
+                    app.messages.push(format!("Default placement strategy set to {}", strategy.name()));
+                    app.network.lock().unwrap().set_placement_strategy(strategy);
+                }
+                Err(e) => app.messages.push(e),
+            }
+        }
+        "set_region" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: set_region <sp_id> <region>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            match app.network.lock().unwrap().set_storage_node_region(&sp_id, Some(parts[2].to_string())) {
+                Ok(_) => app.messages.push(format!("SP {} is now in region {}", sp_id, parts[2])),
+                Err(e) => app.messages.push(format!("Failed to set region: {}", e)),
+            }
+        }
+        "stream_upload" => {
+            if parts.len() != 6 {
+                app.messages.push("Usage: stream_upload <client_id> <filename> <content> <replication_factor> <budget_per_sp>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2].to_string();
+            let content = parts[3].as_bytes().to_vec();
+            let replication_factor = parts[4].parse::<usize>().unwrap_or(3);
+            let budget_per_sp = parts[5].parse::<TokenAmount>().unwrap_or_default();
+
+            match app.network.lock().unwrap().upload_file_streaming(&client_id, filename, content, replication_factor, 1024, budget_per_sp) {
+                Ok(nodes) => app.messages.push(format!("File streamed to {} storage providers", nodes.len())),
+                Err(e) => app.messages.push(format!("Failed to stream file: {}", e)),
+            }
+        }
         "download_file" => {
             if parts.len() != 4 {
                 app.messages.push("Usage: download_file <client_id> <sp_id> <filename>".to_string());
                 return;
             }
-            let client_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
-            let _sp_id = PeerId::from_bytes(&hex::decode(parts[2]).unwrap()).unwrap();
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let Some(_sp_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
             let filename = parts[3];
 
             match app.network.lock().unwrap().download_file(&client_id, filename) {
@@ -383,75 +559,527 @@ fn execute_command(app: &mut App) {
                 Err(e) => app.messages.push(format!("Failed to download file: {}", e)),
             }
         }
-        "renew_deal" => {
+        "replicate_file" => {
             if parts.len() != 4 {
-                app.messages.push("Usage: renew_deal <client_id> <sp_id> <filename>".to_string());
+                app.messages.push("Usage: replicate_file <client_id> <filename> <replications>".to_string());
                 return;
             }
-            let client_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
-            let sp_id = PeerId::from_bytes(&hex::decode(parts[2]).unwrap()).unwrap();
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2];
+            let replications = parts[3].parse::<usize>().unwrap_or(0);
+
+            match app.network.lock().unwrap().replicate_file(&client_id, filename, replications) {
+                Ok(_) => app.messages.push(format!("File {} replicated to {} more storage providers", filename, replications)),
+                Err(e) => app.messages.push(format!("Failed to replicate file: {}", e)),
+            }
+        }
+        "replication_payments" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: replication_payments <client_id> <filename>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2];
+            let network = app.network.lock().unwrap();
+            app.messages.push(format!("Replication payments for {}:", filename));
+            for payment in network.get_replication_payments(&client_id, filename) {
+                app.messages.push(format!("  {} paid {} tokens to {}", payment.payer_id, payment.amount, payment.storage_node_id));
+            }
+        }
+        "renew_deal" => {
+            if parts.len() != 5 {
+                app.messages.push("Usage: renew_deal <client_id> <sp_id> <filename> <hours>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
             let filename = parts[3];
+            let extension = Duration::from_secs(parts[4].parse::<u64>().unwrap_or(24) * 60 * 60);
 
-            match app.network.lock().unwrap().renew_deal(&client_id, &sp_id, filename) {
-                Ok(_) => app.messages.push("Deal renewed successfully".to_string()),
+            match app.network.lock().unwrap().renew_deal(&client_id, &sp_id, filename, extension) {
+                Ok(cost) => app.messages.push(format!("Deal renewed for {} tokens", cost)),
                 Err(e) => app.messages.push(format!("Failed to renew deal: {}", e)),
             }
         }
+        "auto_renew" => {
+            if parts.len() != 5 {
+                app.messages.push("Usage: auto_renew <client_id> <deal_id> <hours> <budget>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let deal_id = parts[2].parse::<u64>().unwrap_or(u64::MAX);
+            let extension = Duration::from_secs(parts[3].parse::<u64>().unwrap_or(24) * 60 * 60);
+            let budget = match parts[4].parse::<TokenAmount>() {
+                Ok(budget) => budget,
+                Err(e) => {
+                    app.messages.push(format!("Invalid budget: {}", e));
+                    return;
+                }
+            };
+
+            match app.network.lock().unwrap().set_auto_renewal(&client_id, deal_id, extension, budget) {
+                Ok(()) => app.messages.push(format!("Deal {} will renew itself from a budget of {} tokens", deal_id, budget)),
+                Err(e) => app.messages.push(format!("Failed to set up auto-renewal: {}", e)),
+            }
+        }
+        "cancel_auto_renew" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: cancel_auto_renew <client_id> <deal_id>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let deal_id = parts[2].parse::<u64>().unwrap_or(u64::MAX);
+
+            match app.network.lock().unwrap().cancel_auto_renewal(&client_id, deal_id) {
+                Ok(refund) => app.messages.push(format!("Auto-renewal cancelled, {} tokens refunded", refund)),
+                Err(e) => app.messages.push(format!("Failed to cancel auto-renewal: {}", e)),
+            }
+        }
+        "adjust_prices" => {
+            let changes = app.network.lock().unwrap().adjust_prices();
+            app.messages.push(format!("Repriced {} storage nodes", changes.len()));
+            for change in changes {
+                app.messages.push(format!("  {}: {} -> {} ({})", change.storage_node_id, change.old_price, change.new_price, change.reason));
+            }
+        }
+        "set_pricing_policy" => {
+            if parts.len() != 6 {
+                app.messages.push("Usage: set_pricing_policy <sp_id> <floor> <ceiling> <max_step_bps> <target_utilisation_bps>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let policy = PricingPolicy {
+                floor: parts[2].parse::<TokenAmount>().unwrap_or_default(),
+                ceiling: parts[3].parse::<TokenAmount>().unwrap_or(TokenAmount::MAX),
+                max_step_bps: parts[4].parse::<u64>().unwrap_or(1_000),
+                target_utilisation_bps: parts[5].parse::<u64>().unwrap_or(6_000),
+            };
+            match app.network.lock().unwrap().set_pricing_policy(&sp_id, Some(policy)) {
+                Ok(_) => app.messages.push(format!("Pricing policy set for SP {}", sp_id)),
+                Err(e) => app.messages.push(format!("Failed to set pricing policy: {}", e)),
+            }
+        }
+        "clear_pricing_policy" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: clear_pricing_policy <sp_id>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            match app.network.lock().unwrap().set_pricing_policy(&sp_id, None) {
+                Ok(_) => app.messages.push(format!("SP {} now keeps its own price", sp_id)),
+                Err(e) => app.messages.push(format!("Failed to clear pricing policy: {}", e)),
+            }
+        }
+        "price_history" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: price_history <sp_id>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let network = app.network.lock().unwrap();
+            app.messages.push(format!("Price changes for SP {}:", sp_id));
+            for change in network.pricing.history().iter().filter(|change| change.storage_node_id == sp_id) {
+                app.messages.push(format!("  epoch {}: {} -> {} ({})", change.epoch, change.old_price, change.new_price, change.reason));
+            }
+        }
+        "set_rebalance_policy" => {
+            if parts.len() != 5 || !["on", "off"].contains(&parts[1]) {
+                app.messages.push("Usage: set_rebalance_policy <on|off> <max_bytes_per_run> <max_bytes_per_node> <min_gap_bps>".to_string());
+                return;
+            }
+            let policy = RebalancePolicy {
+                enabled: parts[1] == "on",
+                max_bytes_per_run: parts[2].parse::<usize>().unwrap_or(0),
+                max_bytes_per_node: parts[3].parse::<usize>().unwrap_or(0),
+                min_gap_bps: parts[4].parse::<u64>().unwrap_or(500),
+            };
+            match app.network.lock().unwrap().set_rebalance_policy(policy) {
+                Ok(_) => app.messages.push(format!("Rebalancing turned {}", parts[1])),
+                Err(e) => app.messages.push(format!("Failed to set rebalance policy: {}", e)),
+            }
+        }
+        "rebalance" => {
+            let mut network = app.network.lock().unwrap();
+            if !network.rebalancer.policy().enabled {
+                app.messages.push("Rebalancing is off; turn it on with set_rebalance_policy".to_string());
+                return;
+            }
+            let moves = network.rebalance();
+            app.messages.push(format!("Moved {} replicas", moves.len()));
+            for replica_move in moves {
+                app.messages.push(format!("  {} ({} bytes) from {} to {}", replica_move.filename, replica_move.bytes, replica_move.from, replica_move.to));
+            }
+        }
+        "rebalance_history" => {
+            let network = app.network.lock().unwrap();
+            app.messages.push("Replicas moved by the rebalancer:".to_string());
+            for replica_move in network.rebalancer.history() {
+                app.messages.push(format!("  epoch {}: {} of {} ({} bytes) from {} to {}, deal {} -> {}",
+                    replica_move.epoch, replica_move.filename, replica_move.client_id, replica_move.bytes,
+                    replica_move.from, replica_move.to, replica_move.from_deal, replica_move.to_deal));
+            }
+        }
+        "list_deals" => {
+            let network = app.network.lock().unwrap();
+            let deals: Vec<&Deal> = match parts.get(1).copied() {
+                None => network.deals.values().collect(),
+                Some("client") if parts.len() == 3 => {
+                    let Some(client_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
+                    network.deals_by_client(&client_id)
+                }
+                Some("sp") if parts.len() == 3 => {
+                    let Some(sp_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
+                    network.deals_by_storage_node(&sp_id)
+                }
+                Some("file") if parts.len() == 4 => {
+                    let Some(client_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
+                    network.deals_for_file(&client_id, parts[3])
+                }
+                Some("state") if parts.len() == 3 => match parts[2].parse::<DealState>() {
+                    Ok(state) => network.deals_in_state(state),
+                    Err(e) => {
+                        app.messages.push(e);
+                        return;
+                    }
+                },
+                _ => {
+                    app.messages.push("Usage: list_deals [client <client_id> | sp <sp_id> | file <client_id> <filename> | state <state>]".to_string());
+                    return;
+                }
+            };
+            app.messages.push(format!("{} deals:", deals.len()));
+            for deal in deals {
+                app.messages.push(format!("  {}: {} for {} with SP {}, {} of {} epochs paid",
+                    deal.id(), deal.state(), deal.filename(), deal.storage_node_id(), deal.epochs_paid(), deal.epochs()));
+            }
+        }
+        "deal_info" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: deal_info <deal_id>".to_string());
+                return;
+            }
+            let deal_id = parts[1].parse::<u64>().unwrap_or(u64::MAX);
+            let network = app.network.lock().unwrap();
+            let Some(deal) = network.get_deal(deal_id) else {
+                app.messages.push(format!("Deal {} not found", deal_id));
+                return;
+            };
+            app.messages.push(format!("Deal {}: client {} with SP {} for {}, {} total, now {}",
+                deal.id(), deal.client_id(), deal.storage_node_id(), deal.filename(), deal.total_payment(), deal.state()));
+            for transition in deal.history() {
+                app.messages.push(format!("  {} at epoch {}", transition.state, transition.epoch));
+            }
+        }
+        "under_replicated" => {
+            let network = app.network.lock().unwrap();
+            app.messages.push(format!("{} under-replicated files:", network.under_replicated.len()));
+            for ((client_id, filename), missing) in &network.under_replicated {
+                app.messages.push(format!("  {} of {}: {} replicas missing", filename, client_id, missing));
+            }
+        }
+        "repair_replication" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: repair_replication <client_id> <filename>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2];
+
+            match app.network.lock().unwrap().repair_replication(&client_id, filename) {
+                Ok(nodes) => app.messages.push(format!("Placed {} replacement replicas on {:?}", nodes.len(), nodes)),
+                Err(e) => app.messages.push(format!("Failed to repair {}: {}", filename, e)),
+            }
+        }
+        "jobs" => {
+            let network = app.network.lock().unwrap();
+            let now = network.now();
+            app.messages.push(format!("Scheduler{}:", if network.scheduler.is_paused() { " (paused)" } else { "" }));
+            for status in network.scheduler.jobs() {
+                let last_run = status.last_run.map_or("never".to_string(), |at| format!("{}s ago", now.saturating_duration_since(at).as_secs()));
+                let next_run = match status.next_run() {
+                    _ if status.paused => "paused".to_string(),
+                    Some(at) if at > now => format!("in {}s", at.saturating_duration_since(now).as_secs()),
+                    _ => "due".to_string(),
+                };
+                app.messages.push(format!("  {} every {}s: last run {}, next {}, {} runs{}",
+                    status.job, status.interval.as_secs(), last_run, next_run, status.runs,
+                    status.last_summary.as_ref().map_or(String::new(), |summary| format!(" ({})", summary))));
+            }
+        }
+        "run_job" | "pause_job" | "resume_job" => {
+            if parts.len() != 2 {
+                app.messages.push(format!("Usage: {} <job|all>", parts[0]));
+                return;
+            }
+            let mut network = app.network.lock().unwrap();
+            if parts[1] == "all" && parts[0] != "run_job" {
+                network.scheduler.set_paused(parts[0] == "pause_job");
+                app.messages.push(format!("Scheduler {}", if parts[0] == "pause_job" { "paused" } else { "resumed" }));
+                return;
+            }
+            let job = match parts[1].parse::<MaintenanceJob>() {
+                Ok(job) => job,
+                Err(e) => {
+                    app.messages.push(e);
+                    return;
+                }
+            };
+            let result = match parts[0] {
+                "run_job" => Ok(network.run_job(job)),
+                "pause_job" => network.scheduler.set_job_paused(job, true).map(|_| "paused".to_string()),
+                _ => network.scheduler.set_job_paused(job, false).map(|_| "resumed".to_string()),
+            };
+            match result {
+                Ok(summary) => app.messages.push(format!("{}: {}", job, summary)),
+                Err(e) => app.messages.push(e),
+            }
+        }
+        "set_job_interval" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: set_job_interval <job> <seconds>".to_string());
+                return;
+            }
+            let job = match parts[1].parse::<MaintenanceJob>() {
+                Ok(job) => job,
+                Err(e) => {
+                    app.messages.push(e);
+                    return;
+                }
+            };
+            let interval = Duration::from_secs(parts[2].parse::<u64>().unwrap_or(0));
+            match app.network.lock().unwrap().scheduler.set_interval(job, interval) {
+                Ok(()) => app.messages.push(format!("{} now runs every {}s", job, interval.as_secs())),
+                Err(e) => app.messages.push(e),
+            }
+        }
         "check_deals" => {
             app.network.lock().unwrap().check_deals();
             app.messages.push("Checked and removed expired deals".to_string());
         }
+        "advance_epoch" => {
+            let mut network = app.network.lock().unwrap();
+            network.advance_epoch();
+            app.messages.push(format!("Advanced to epoch {}", network.current_epoch));
+        }
+        "terminate_deal" => {
+            if parts.len() != 4 {
+                app.messages.push("Usage: terminate_deal <client_id> <sp_id> <filename>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[2]) else { return; };
+            let filename = parts[3];
+
+            match app.network.lock().unwrap().terminate_deal(&client_id, &sp_id, filename) {
+                Ok(refund) => app.messages.push(format!("Deal terminated, refunded {} tokens", refund)),
+                Err(e) => app.messages.push(format!("Failed to terminate deal: {}", e)),
+            }
+        }
         "get_reputation" => {
             if parts.len() != 2 {
                 app.messages.push("Usage: get_reputation <sp_id>".to_string());
                 return;
             }
-            let sp_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            match app.network.lock().unwrap().get_reputation(&sp_id) {
+                Ok(breakdown) => {
+                    app.messages.push(format!("Reputation of SP {}: {}", sp_id, breakdown.score));
+                    app.messages.push(format!("  audits {:.2}, retrievals {:.2}, latency {:.2}, uptime {:.2}, deal completion {:.2}",
+                        breakdown.audits, breakdown.retrievals, breakdown.latency, breakdown.uptime, breakdown.deal_completion));
+                }
+                Err(e) => app.messages.push(format!("Failed to get reputation: {}", e)),
+            }
+        }
+        "set_retrieval_price" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: set_retrieval_price <sp_id> <price_per_byte>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let price_per_byte = parts[2].parse::<TokenAmount>().unwrap_or_default();
+            match app.network.lock().unwrap().set_retrieval_price(&sp_id, price_per_byte) {
+                Ok(_) => app.messages.push(format!("Retrieval price of SP {} set to {} per byte", sp_id, price_per_byte)),
+                Err(e) => app.messages.push(format!("Failed to set retrieval price: {}", e)),
+            }
+        }
+        "stake" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: stake <sp_id> <amount>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let amount = parts[2].parse::<TokenAmount>().unwrap_or_default();
+            match app.network.lock().unwrap().stake(&sp_id, amount) {
+                Ok(total) => app.messages.push(format!("SP {} staked {} tokens, {} in total", sp_id, amount, total)),
+                Err(e) => app.messages.push(format!("Failed to stake: {}", e)),
+            }
+        }
+        "drain" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: drain <sp_id>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            match app.network.lock().unwrap().drain_storage_node(&sp_id) {
+                Ok(report) => {
+                    for migration in &report.migrated {
+                        app.messages.push(format!("Moved deal {} to SP {} as deal {} with {} tokens", migration.from_deal, migration.storage_node_id, migration.to_deal, migration.value));
+                    }
+                    match report.stake_released {
+                        Some(stake) => app.messages.push(format!("SP {} has left the network, {} tokens of stake released", sp_id, stake)),
+                        None => app.messages.push(format!("SP {} is draining, {} deals still waiting for another SP", sp_id, report.pending.len())),
+                    }
+                }
+                Err(e) => app.messages.push(format!("Failed to drain SP: {}", e)),
+            }
+        }
+        "get_earnings" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: get_earnings <sp_id>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
             if let Some(sp) = app.network.lock().unwrap().storage_nodes().get(&sp_id) {
-                app.messages.push(format!("Reputation of SP {}: {}", sp_id, sp.reputation()));
+                app.messages.push(format!("Earnings of SP {}: storage {}, retrieval {}", sp_id, sp.storage_earnings(), sp.retrieval_earnings()));
             } else {
                 app.messages.push(format!("Storage provider with ID {} not found", sp_id));
             }
         }
-        "add_storage_offer" => {
+        "token_history" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: token_history <peer_id>".to_string());
+                return;
+            }
+            let Some(peer_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let network = app.network.lock().unwrap();
+            app.messages.push(format!("Token history of {}:", peer_id));
+            for record in network.token.events_for(&peer_id) {
+                app.messages.push(format!("  #{}: {:?}", record.sequence, record.event));
+            }
+        }
+        "post_request" => {
+            if parts.len() != 8 {
+                app.messages.push("Usage: post_request <client_id> <filename> <content> <replication_factor> <epochs> <max_price_per_byte_epoch> <window_secs>".to_string());
+                return;
+            }
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let filename = parts[2].to_string();
+            let content = parts[3].as_bytes().to_vec();
+            let terms = AuctionTerms {
+                replication_factor: parts[4].parse::<usize>().unwrap_or(3),
+                duration: EPOCH_DURATION * parts[5].parse::<u32>().unwrap_or(24),
+                max_price_per_byte_epoch: parts[6].parse::<TokenAmount>().unwrap_or_default(),
+                bidding_window: Duration::from_secs(parts[7].parse::<u64>().unwrap_or(60)),
+            };
+            match app.network.lock().unwrap().post_storage_request(&client_id, filename, content, terms) {
+                Ok(request_id) => app.messages.push(format!("Storage request {} is open for bids", request_id)),
+                Err(e) => app.messages.push(format!("Failed to post storage request: {}", e)),
+            }
+        }
+        "bid" => {
             if parts.len() != 4 {
-                app.messages.push("Usage: add_storage_offer <sp_id> <price_per_gb> <available_space>".to_string());
+                app.messages.push("Usage: bid <sp_id> <request_id> <price_per_byte_epoch>".to_string());
                 return;
             }
-            let sp_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
-            let price_per_gb = parts[2].parse::<u64>().unwrap();
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let request_id = parts[2].parse::<u64>().unwrap_or(0);
+            let price = parts[3].parse::<TokenAmount>().unwrap_or_default();
+            match app.network.lock().unwrap().submit_bid(request_id, &sp_id, price) {
+                Ok(_) => app.messages.push(format!("SP {} bid {} per byte-epoch on request {}", sp_id, price, request_id)),
+                Err(e) => app.messages.push(format!("Bid rejected: {}", e)),
+            }
+        }
+        "close_request" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: close_request <request_id>".to_string());
+                return;
+            }
+            let request_id = parts[1].parse::<u64>().unwrap_or(0);
+            match app.network.lock().unwrap().close_storage_request(request_id) {
+                Ok(winners) => app.messages.push(format!("Storage request {} awarded to {:?}", request_id, winners)),
+                Err(e) => app.messages.push(format!("Storage request {} not awarded: {}", request_id, e)),
+            }
+        }
+        "auction_notices" => {
+            if parts.len() != 2 {
+                app.messages.push("Usage: auction_notices <sp_id>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let network = app.network.lock().unwrap();
+            app.messages.push(format!("Auction notices for SP {}:", sp_id));
+            for notice in network.get_auction_notices(&sp_id) {
+                app.messages.push(format!("  request {}: {:?}", notice.request_id, notice.outcome));
+            }
+        }
+        "add_storage_offer" => {
+            if parts.len() != 5 {
+                app.messages.push("Usage: add_storage_offer <sp_id> <price_per_byte_epoch> <available_space> <lifetime_epochs>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let price_per_byte_epoch = parts[2].parse::<TokenAmount>().unwrap();
             let available_space = parts[3].parse::<usize>().unwrap();
-            app.network.lock().unwrap().add_storage_offer(sp_id, price_per_gb, available_space);
-            app.messages.push("Storage offer added to the marketplace".to_string());
+            let lifetime_epochs = parts[4].parse::<u64>().unwrap_or(24);
+            match app.network.lock().unwrap().add_storage_offer(sp_id, price_per_byte_epoch, available_space, lifetime_epochs) {
+                Ok(offer_id) => app.messages.push(format!("Storage offer {} added to the marketplace", offer_id)),
+                Err(e) => app.messages.push(format!("Failed to add storage offer: {}", e)),
+            }
         }
         "list_storage_offers" => {
             let network = app.network.lock().unwrap();
             let offers = network.get_storage_offers();
             app.messages.push("Storage Offers:".to_string());
-            for (i, offer) in offers.iter().enumerate() {
-                app.messages.push(format!("  {}: SP: {}, Price per GB: {}, Available Space: {} bytes", 
-                    i, offer.storage_node_id, offer.price_per_gb, offer.available_space));
+            for offer in offers {
+                app.messages.push(format!("  {}: SP: {}, Price per byte-epoch: {}, Available Space: {} bytes, Expires at epoch: {}",
+                    offer.id, offer.storage_node_id, offer.price_per_byte_epoch, offer.available_space, offer.expires_at_epoch));
             }
         }
         "accept_storage_offer" => {
-            if parts.len() != 4 {
-                app.messages.push("Usage: accept_storage_offer <client_id> <offer_index> <file_size>".to_string());
+            if parts.len() != 5 {
+                app.messages.push("Usage: accept_storage_offer <client_id> <offer_id> <filename> <content>".to_string());
                 return;
             }
-            let client_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
-            let offer_index = parts[2].parse::<usize>().unwrap();
-            let file_size = parts[3].parse::<usize>().unwrap();
-            match app.network.lock().unwrap().accept_storage_offer(&client_id, offer_index, file_size) {
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let offer_id = parts[2].parse::<u64>().unwrap();
+            let filename = parts[3].to_string();
+            let content = parts[4].as_bytes().to_vec();
+            match app.network.lock().unwrap().accept_storage_offer(&client_id, offer_id, filename, content) {
                 Ok(_) => app.messages.push("Storage offer accepted successfully".to_string()),
                 Err(e) => app.messages.push(format!("Failed to accept storage offer: {}", e)),
             }
         }
+        "cancel_storage_offer" => {
+            if parts.len() != 3 {
+                app.messages.push("Usage: cancel_storage_offer <sp_id> <offer_id>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let offer_id = parts[2].parse::<u64>().unwrap();
+            match app.network.lock().unwrap().cancel_storage_offer(&sp_id, offer_id) {
+                Ok(_) => app.messages.push(format!("Storage offer {} cancelled", offer_id)),
+                Err(e) => app.messages.push(format!("Failed to cancel storage offer: {}", e)),
+            }
+        }
+        "update_offer_price" => {
+            if parts.len() != 4 {
+                app.messages.push("Usage: update_offer_price <sp_id> <offer_id> <price_per_byte_epoch>".to_string());
+                return;
+            }
+            let Some(sp_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
+            let offer_id = parts[2].parse::<u64>().unwrap();
+            let price_per_byte_epoch = parts[3].parse::<TokenAmount>().unwrap();
+            match app.network.lock().unwrap().update_storage_offer_price(&sp_id, offer_id, price_per_byte_epoch) {
+                Ok(_) => app.messages.push(format!("Storage offer {} now costs {} per byte-epoch", offer_id, price_per_byte_epoch)),
+                Err(e) => app.messages.push(format!("Failed to update storage offer: {}", e)),
+            }
+        }
         "increase_replication" => {
             if parts.len() != 4 {
                 app.messages.push("Usage: increase_replication <client_id> <filename> <new_replication_factor>".to_string());
                 return;
             }
-            let client_id = PeerId::from_bytes(&hex::decode(parts[1]).unwrap()).unwrap();
+            let Some(client_id) = parse_peer_id(&mut app.messages, parts[1]) else { return; };
             let filename = parts[2];
             let new_replication_factor = parts[3].parse::<usize>().unwrap_or(0);
             match app.network.lock().unwrap().request_higher_replication(&client_id, filename, new_replication_factor) {
@@ -473,11 +1101,14 @@ fn run_advanced_network_tests(network: &mut Network, tx: broadcast::Sender<Strin
     
     for i in 0..100 {
         let client_id = PeerId::random();
-        network.add_client(client_id.clone());
+        network.add_client(client_id);
         tx.send(format!("Added client with PeerId: {}", client_id)).unwrap();
+        if let Err(e) = network.request_faucet_funds(&client_id) {
+            tx.send(format!("Test {}: Faucet request failed - {}", i, e)).unwrap();
+        }
         
         let sp_id = PeerId::random();
-        network.add_storage_node(sp_id.clone(), rng.gen_range(10..20));
+        network.add_storage_node(sp_id, TokenAmount::from_base_units(rng.gen_range(10..20)));
         tx.send(format!("Added storage provider (SP) with PeerId: {}", sp_id)).unwrap();
         
         let filename = format!("test_file_{}.txt", i);
@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+use crate::token_amount::TokenAmount;
+
+/// Space an SP is offering at a fixed price until `expires_at_epoch`. Clients take it
+/// in pieces; `available_space` is what is left.
+#[serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StorageOffer {
+    pub id: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub storage_node_id: PeerId,
+    pub price_per_byte_epoch: TokenAmount,
+    pub available_space: usize,
+    pub expires_at_epoch: u64,
+}
+
+impl StorageOffer {
+    pub fn is_expired_at(&self, epoch: u64) -> bool {
+        epoch >= self.expires_at_epoch
+    }
+}
+
+/// Open storage offers keyed by a stable id. Ids are never reused, so an id handed to a
+/// client stays valid (or clearly gone) however the book changes in between.
+#[derive(Clone, Debug, Default)]
+pub struct OrderBook {
+    offers: BTreeMap<u64, StorageOffer>,
+    next_offer_id: u64,
+}
+
+impl OrderBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn post(&mut self, storage_node_id: PeerId, price_per_byte_epoch: TokenAmount, available_space: usize, expires_at_epoch: u64) -> u64 {
+        let id = self.next_offer_id;
+        self.next_offer_id += 1;
+        self.offers.insert(id, StorageOffer { id, storage_node_id, price_per_byte_epoch, available_space, expires_at_epoch });
+        id
+    }
+
+    pub fn get(&self, offer_id: u64) -> Option<&StorageOffer> {
+        self.offers.get(&offer_id)
+    }
+
+    /// Open offers, cheapest first. Offers at the same price keep posting order.
+    pub fn offers(&self) -> Vec<&StorageOffer> {
+        let mut offers: Vec<&StorageOffer> = self.offers.values().collect();
+        offers.sort_by_key(|offer| (offer.price_per_byte_epoch, offer.id));
+        offers
+    }
+
+    pub fn len(&self) -> usize {
+        self.offers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offers.is_empty()
+    }
+
+    /// Takes `size` bytes from an offer, closing it once nothing is left.
+    pub fn fill(&mut self, offer_id: u64, size: usize) -> Result<(), &'static str> {
+        let offer = self.offers.get_mut(&offer_id).ok_or("Offer not found")?;
+        if size > offer.available_space {
+            return Err("Not enough space in the offer");
+        }
+        offer.available_space -= size;
+        if offer.available_space == 0 {
+            self.offers.remove(&offer_id);
+        }
+        Ok(())
+    }
+
+    /// Withdraws an offer. Only the SP that posted it may cancel it.
+    pub fn cancel(&mut self, storage_node_id: &PeerId, offer_id: u64) -> Result<StorageOffer, &'static str> {
+        self.owned_by(storage_node_id, offer_id)?;
+        Ok(self.offers.remove(&offer_id).unwrap())
+    }
+
+    /// Reprices the unfilled part of an offer. Space already taken keeps its price.
+    pub fn update_price(&mut self, storage_node_id: &PeerId, offer_id: u64, price_per_byte_epoch: TokenAmount) -> Result<(), &'static str> {
+        self.owned_by(storage_node_id, offer_id)?.price_per_byte_epoch = price_per_byte_epoch;
+        Ok(())
+    }
+
+    /// Drops every offer that has expired by `epoch` and returns them.
+    pub fn remove_expired(&mut self, epoch: u64) -> Vec<StorageOffer> {
+        let expired: Vec<u64> = self.offers.values()
+            .filter(|offer| offer.is_expired_at(epoch))
+            .map(|offer| offer.id)
+            .collect();
+        expired.into_iter().filter_map(|id| self.offers.remove(&id)).collect()
+    }
+
+    fn owned_by(&mut self, storage_node_id: &PeerId, offer_id: u64) -> Result<&mut StorageOffer, &'static str> {
+        let offer = self.offers.get_mut(&offer_id).ok_or("Offer not found")?;
+        if offer.storage_node_id != *storage_node_id {
+            return Err("Offer belongs to another storage node");
+        }
+        Ok(offer)
+    }
+}
@@ -1,22 +1,37 @@
-use crate::{StorageNode, Client, erc20::ERC20};
+use crate::{StorageNode, Client, erc20::{ERC20, Purpose, TokenError}, storage_node::content_digest, token_amount::TokenAmount};
+use crate::payment_channel::{ChannelSigner, PaymentChannel, Voucher};
+use crate::faucet::{Faucet, FaucetError};
+use crate::auction::{AuctionNotice, AuctionStatus, AuctionTerms, BidOutcome, StorageRequest};
+use crate::placement::{PlacementStrategy, RandomPlacement, UploadConstraints};
+use crate::marketplace::{OrderBook, StorageOffer};
+use crate::reputation::{ReputationBreakdown, ReputationEngine};
+use crate::pricing::{MarketSignals, PriceChange, PricingEngine, PricingPolicy};
+use crate::deal::{epochs_in, AutoRenewal, Deal, DealState, DealTerms, EXPIRING_EPOCHS};
+use crate::clock::{Clock, SystemClock, Timestamp};
+use crate::scheduler::{MaintenanceJob, Scheduler};
+use crate::rebalance::{RebalancePolicy, Rebalancer, ReplicaMove};
+use crate::protocol::{self, BlockRequest, BlockResponse, ErrorCode, StorageCodec};
 use tokio::sync::broadcast::Sender;
 use libp2p::{futures::StreamExt,
     core::{transport::MemoryTransport, upgrade},
     identity, noise, yamux,
     swarm::{Swarm, SwarmEvent},
     SwarmBuilder,
-    kad::{self, store::MemoryStore},
+    kad::{self, store::{MemoryStore, RecordStore}},
+    request_response,
     PeerId, Transport,
     tls
 };
 use std::error::Error;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::time::{Duration, Instant};
 use serde::{Serialize, Deserialize};
 use serde_with::{serde_as, DisplayFromStr};
-use rand::seq::SliceRandom;
 
-const DEAL_DURATION: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+pub const DEFAULT_DEAL_DURATION: Duration = Duration::from_secs(24 * 60 * 60); // 24 hours
+/// How long an expired deal's data is kept, waiting for a renewal, before it is deleted.
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(6 * 60 * 60);
+pub const EPOCH_DURATION: Duration = Duration::from_secs(60 * 60); // 1 hour
 
 #[derive(Debug, Clone, Copy)]
 pub enum DebugLevel {
@@ -36,60 +51,25 @@ impl DebugLevel {
 
 
 impl Network {
+    /// Raises a file's replica count to `new_replication_factor` by chain replication, so
+    /// the new replicas are paid for out of the client's funds like any other.
     pub fn request_higher_replication(&mut self, client_id: &PeerId, filename: &str, new_replication_factor: usize) -> Result<(), String> {
         self.debug_log(&format!("Requesting higher replication for file: {} from client: {} to factor: {}", filename, client_id, new_replication_factor));
 
-        let current_storage_nodes = {
-            let client = self.clients.get(client_id).ok_or_else(|| "Client not found".to_string())?;
-            client.get_file_locations(filename).ok_or_else(|| "File not found".to_string())?.clone()
-        };
-
-
+        let current_storage_nodes = self.get_file_locations(client_id, filename)?;
         if new_replication_factor <= current_storage_nodes.len() {
             return Err(format!("New replication factor must be higher than current ({}).", current_storage_nodes.len()));
         };
 
-
         let additional_replications = new_replication_factor - current_storage_nodes.len();
-        let available_nodes: Vec<PeerId> = self.storage_nodes.keys()
-            .filter(|&id| !current_storage_nodes.contains(id))
-            .cloned()
-            .collect();
-
-        if available_nodes.len() < additional_replications {
-            return Err(format!("Not enough additional storage nodes available. Required: {}, Available: {}", additional_replications, available_nodes.len()));
-        };
-
-
-        let selected_nodes: Vec<PeerId> = available_nodes.choose_multiple(&mut rand::thread_rng(), additional_replications).cloned().collect();
-
-        // Retrieve the file data from one of the existing storage nodes
-        let file_data = {
-            let existing_node_id = current_storage_nodes.first().ok_or_else(|| "No existing storage nodes".to_string())?;
-            self.storage_nodes.get(existing_node_id)
-                .ok_or_else(|| "Storage node not found".to_string())?
-                .get_file(filename)
-                .ok_or_else(|| "File not found on storage node".to_string())?
-                .clone()
-        };
-
-        // Replicate the file to the new nodes
-        for &node_id in &selected_nodes {
-            let storage_node = self.storage_nodes.get_mut(&node_id).ok_or_else(|| "Storage node not found".to_string())?;
-            storage_node.store_file(filename.to_string(), file_data.clone())?;
-        };
-
-        // Initialize with at least one storage node and one client
-        let _initial_client_id = PeerId::random();
-        let _initial_storage_node_id = PeerId::random();
-
-        // Update the client's file locations
-        if let Some(client) = self.clients.get_mut(client_id) {
-            let mut updated_locations = current_storage_nodes;
-            updated_locations.extend(selected_nodes);
-            client.add_file(filename.to_string(), updated_locations);
+        let available_nodes = self.storage_nodes.iter()
+            .filter(|(id, node)| !current_storage_nodes.contains(id) && !node.is_draining() && node.get_file(filename).is_none())
+            .count();
+        if available_nodes < additional_replications {
+            return Err(format!("Not enough additional storage nodes available. Required: {}, Available: {}", additional_replications, available_nodes));
         };
 
+        self.replicate_file(client_id, filename, additional_replications)?;
         self.debug_log(&format!("Successfully increased replication factor for file: {} to {}", filename, new_replication_factor));
         Ok(())
     }
@@ -120,66 +100,159 @@ pub struct Network {
     pub message_sender: Option<Sender<String>>,
     pub storage_nodes: HashMap<PeerId, StorageNode>,
     pub clients: HashMap<PeerId, Client>,
-    pub deals: Vec<Deal>,
-    pub marketplace: VecDeque<StorageOffer>,
+    pub deals: BTreeMap<u64, Deal>,
+    next_deal_id: u64,
+    pub marketplace: OrderBook,
     pub token: ERC20,
-    pub bids: HashMap<String, Vec<Bid>>,
+    /// Bids on each open storage request, keyed by request id.
+    pub bids: HashMap<u64, Vec<Bid>>,
     pub debug_level: DebugLevel,
+    pub current_epoch: u64,
+    pub payment_channels: HashMap<u64, PaymentChannel>,
+    pub upload_streams: HashMap<u64, UploadStream>,
+    next_stream_id: u64,
+    pub replication_payments: Vec<ReplicationPayment>,
+    pub faucet: Faucet,
+    pub storage_requests: HashMap<u64, StorageRequest>,
+    next_request_id: u64,
+    pub auction_notices: Vec<AuctionNotice>,
+    /// Replicas lost to expiry or slashing that could not be replaced yet, by client and file.
+    pub under_replicated: HashMap<(PeerId, String), usize>,
+    /// Escrow holding each SP's stake.
+    pub stakes: HashMap<PeerId, u64>,
+    /// Strategy used whenever an upload or replication does not name its own.
+    pub placement: Box<dyn PlacementStrategy>,
+    pub reputation: ReputationEngine,
+    pub pricing: PricingEngine,
+    pub rebalancer: Rebalancer,
+    /// Time source for deal terms, auctions and the faucet.
+    pub clock: Box<dyn Clock>,
+    pub grace_period: Duration,
+    pub scheduler: Scheduler,
     pub swarm: Swarm<NetworkBehaviourImpl>,
-    pub kademlia: kad::Behaviour<kad::store::MemoryStore>,
 }
 
+#[derive(Clone, Debug)]
 pub struct Bid {
     pub storage_node_id: PeerId,
-    pub price_per_gb: u64,
+    pub price_per_byte_epoch: TokenAmount,
 }
 
 impl Bid {
-    pub fn new(storage_node_id: PeerId, price_per_gb: u64) -> Self {
+    pub fn new(storage_node_id: PeerId, price_per_byte_epoch: TokenAmount) -> Self {
         Self {
             storage_node_id,
-            price_per_gb,
+            price_per_byte_epoch,
         }
     }
 }
 
+/// One payment made by an SP, out of the client's allowance, into the escrow of a
+/// downstream SP's deal.
 #[serde_as]
-#[derive(Clone, Serialize, Deserialize)]
-pub struct StorageOffer {
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ReplicationPayment {
+    #[serde_as(as = "DisplayFromStr")]
+    pub client_id: PeerId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub payer_id: PeerId,
     #[serde_as(as = "DisplayFromStr")]
     pub storage_node_id: PeerId,
-    pub price_per_gb: u64,
-    pub available_space: usize,
+    pub filename: String,
+    pub amount: TokenAmount,
 }
 
+/// A deal handed from a draining SP to its replacement.
 #[serde_as]
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Deal {
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DealMigration {
+    pub from_deal: u64,
+    pub to_deal: u64,
     #[serde_as(as = "DisplayFromStr")]
+    pub storage_node_id: PeerId,
+    /// Unpaid escrow carried over to the new deal.
+    pub value: TokenAmount,
+}
+
+/// Progress of an SP leaving the network.
+#[derive(Clone, Debug, Default)]
+pub struct DrainReport {
+    pub migrated: Vec<DealMigration>,
+    /// Live deals still on the SP because no other SP could take them yet.
+    pub pending: Vec<u64>,
+    /// Stake handed back, set once nothing is pending.
+    pub stake_released: Option<TokenAmount>,
+}
+
+impl DrainReport {
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+/// A pay-as-you-go upload in progress. Chunks enter the chain at `targets[0]` and each
+/// SP forwards them to the next; every SP is paid through its own payment channel
+/// for the bytes it has actually stored.
+pub struct UploadStream {
     client_id: PeerId,
-    #[serde_as(as = "DisplayFromStr")]
-    storage_node_id: PeerId,
     filename: String,
-    #[serde(skip)]
-    start_time: Option<Instant>,
-    #[serde(with = "serde_millis")]
+    targets: Vec<PeerId>,
+    signers: Vec<ChannelSigner>,
+    bytes_stored: usize,
     duration: Duration,
 }
 
-impl Deal {
-    pub fn new(client_id: PeerId, storage_node_id: PeerId, filename: String, duration: Duration) -> Self {
-        Self {
-            client_id,
-            storage_node_id,
-            filename,
-            start_time: Some(Instant::now()),
-            duration,
-        }
+impl UploadStream {
+    pub fn client_id(&self) -> &PeerId {
+        &self.client_id
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    pub fn targets(&self) -> &[PeerId] {
+        &self.targets
     }
 
-    pub fn start_time(&self) -> Instant {
-        self.start_time.unwrap_or_else(Instant::now)
+    pub fn bytes_stored(&self) -> usize {
+        self.bytes_stored
     }
+
+    /// How long the deals opened when the stream finishes will run.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// A chain replication in progress: whose file is forwarded down the chain, and which
+/// SP pays each downstream SP out of the client's allowance.
+#[derive(Clone, Copy)]
+struct ChainUpload<'a> {
+    client_id: &'a PeerId,
+    payer_id: &'a PeerId,
+    filename: &'a str,
+    data: &'a [u8],
+    duration: Duration,
+}
+
+fn check_deal_duration(duration: Duration) -> Result<(), String> {
+    if duration < EPOCH_DURATION {
+        return Err(format!("Deals must last at least one epoch ({}s)", EPOCH_DURATION.as_secs()));
+    }
+    Ok(())
+}
+
+/// Cost of keeping `bytes` stored for `epochs` at a per-byte-per-epoch price.
+fn storage_cost(bytes: usize, epochs: u64, price_per_byte_epoch: TokenAmount) -> Result<TokenAmount, String> {
+    price_per_byte_epoch.checked_mul(bytes as u128 * epochs as u128)
+        .ok_or_else(|| "Storage cost overflows the token supply".to_string())
+}
+
+/// Cost of serving `bytes` at a per-byte retrieval price.
+fn retrieval_cost(bytes: usize, price_per_byte: TokenAmount) -> Result<TokenAmount, String> {
+    price_per_byte.checked_mul(bytes as u128)
+        .ok_or_else(|| "Retrieval cost overflows the token supply".to_string())
 }
 
 impl Network {
@@ -188,15 +261,6 @@ impl Network {
         let local_peer_id = PeerId::from(local_key.public());
         println!("Local peer id: {:?}", local_peer_id);
 
-        let transport = MemoryTransport::default()
-            .upgrade(upgrade::Version::V1)
-            .authenticate(noise::Config::new(&local_key).expect("Failed to create noise config"))
-            .multiplex(yamux::Config::default())
-            .boxed();
-
-        let store = MemoryStore::new(local_peer_id.clone());
-        let kademlia = kad::Behaviour::new(local_peer_id.clone(), store);
-        let behaviour = NetworkBehaviourImpl { kademlia };
         let swarm = SwarmBuilder::with_existing_identity(local_key)
             .with_tokio()
             .with_tcp(
@@ -205,23 +269,51 @@ impl Network {
                 yamux::Config::default,
             )?
             .with_quic()
+            .with_other_transport(|key| Ok::<_, Box<dyn Error + Send + Sync>>(MemoryTransport::default()
+                .upgrade(upgrade::Version::V1)
+                .authenticate(noise::Config::new(key)?)
+                .multiplex(yamux::Config::default())
+                .boxed()))?
             .with_dns()?
-            .with_websocket(
-                (tls::Config::new, noise::Config::new),
-                yamux::Config::default,
-            );
+            .with_behaviour(|key| {
+                let local_peer_id = key.public().to_peer_id();
+                NetworkBehaviourImpl {
+                    kademlia: kad::Behaviour::new(local_peer_id, MemoryStore::new(local_peer_id)),
+                    storage: protocol::behaviour(),
+                }
+            })?
+            .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+            .build();
 
         let network = Network {
             message_sender: None,
             storage_nodes: HashMap::new(),
             clients: HashMap::new(),
-            deals: Vec::new(),
-            marketplace: VecDeque::new(),
-            token: ERC20::new("PioDollar".to_string(), "PIO".to_string(), 1_000_000_000), // 1 billion initial supply
+            deals: BTreeMap::new(),
+            next_deal_id: 0,
+            marketplace: OrderBook::new(),
+            token: ERC20::new("PioDollar".to_string(), "PIO".to_string(), TokenAmount::from_tokens(1_000_000_000)), // 1 billion initial supply
             bids: HashMap::new(),
             debug_level: DebugLevel::None,
+            current_epoch: 0,
+            payment_channels: HashMap::new(),
+            upload_streams: HashMap::new(),
+            next_stream_id: 0,
+            replication_payments: Vec::new(),
+            faucet: Faucet::default(),
+            storage_requests: HashMap::new(),
+            next_request_id: 0,
+            auction_notices: Vec::new(),
+            under_replicated: HashMap::new(),
+            stakes: HashMap::new(),
+            placement: Box::new(RandomPlacement),
+            reputation: ReputationEngine::default(),
+            pricing: PricingEngine::default(),
+            rebalancer: Rebalancer::default(),
+            clock: Box::new(SystemClock),
+            grace_period: DEFAULT_GRACE_PERIOD,
+            scheduler: Scheduler::default(),
             swarm,
-            kademlia: behaviour.kademlia,
         };
 
         Ok(network)
@@ -246,12 +338,77 @@ impl Network {
                 SwarmEvent::Behaviour(NetworkBehaviourImplEvent::Kademlia(kad::Event::OutboundQueryProgressed { result, .. })) => {
                     println!("Query completed: {:?}", result);
                 }
+                SwarmEvent::Behaviour(NetworkBehaviourImplEvent::Storage(event)) => self.handle_storage_event(event),
                 _ => println!("Unhandled Kademlia event: {:?}", event),
             }
         }
         Ok(())
     }
 
+    /// Serves `/pioneer/storage/1.0.0` requests from the storage node running under the
+    /// swarm's own peer id, if there is one.
+    fn handle_storage_event(&mut self, event: request_response::Event<BlockRequest, BlockResponse>) {
+        match event {
+            request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. } } => {
+                let response = self.serve_block_request(peer, request);
+                if self.swarm.behaviour_mut().storage.send_response(channel, response).is_err() {
+                    self.debug_log(&format!("Storage request from {} was dropped before the response", peer));
+                }
+            }
+            request_response::Event::Message { peer, message: request_response::Message::Response { request_id, response } } => {
+                self.debug_log(&format!("Storage response {} from {}: {:?}", request_id, peer, response));
+            }
+            request_response::Event::OutboundFailure { peer, request_id, error } => {
+                self.debug_log(&format!("Storage request {} to {} failed: {}", request_id, peer, error));
+            }
+            request_response::Event::InboundFailure { peer, request_id, error } => {
+                self.debug_log(&format!("Storage request {} from {} failed: {}", request_id, peer, error));
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    /// Answers a storage request from `peer`. Only the client of a live deal for a block
+    /// may store or delete it: a stored block must match the deal's commitment, and a
+    /// delete terminates the deal so the SP is not slashed for the missing data.
+    fn serve_block_request(&mut self, peer: PeerId, request: BlockRequest) -> BlockResponse {
+        let local_peer_id = *self.swarm.local_peer_id();
+        if !self.storage_nodes.contains_key(&local_peer_id) {
+            return BlockResponse::error(ErrorCode::NotStorageNode, "Peer is not a storage node");
+        }
+        let unauthorized = || BlockResponse::error(ErrorCode::Unauthorized, "No live deal for this block with the requesting peer");
+        match &request {
+            BlockRequest::Replicate { forward_to, .. } if !forward_to.is_empty() => {
+                return BlockResponse::error(ErrorCode::ForwardFailed, "This peer does not forward blocks");
+            }
+            BlockRequest::Store { name, data } | BlockRequest::Replicate { name, data, .. } => match self.find_live_deal(&peer, &local_peer_id, name) {
+                None => return unauthorized(),
+                Some(deal_id) if self.deals[&deal_id].commitment != content_digest(data) => {
+                    return BlockResponse::error(ErrorCode::Unauthorized, "Data does not match the deal's commitment");
+                }
+                Some(_) => {}
+            },
+            BlockRequest::Delete { name } => {
+                return match self.terminate_deal(&peer, &local_peer_id, name) {
+                    Ok(_) => BlockResponse::Deleted,
+                    Err(_) => unauthorized(),
+                };
+            }
+            _ => {}
+        }
+        protocol::handle_request(self.storage_nodes.get_mut(&local_peer_id).unwrap(), request)
+    }
+
+    /// Stores a record in the local Kademlia store.
+    pub fn put_value(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        let _ = self.swarm.behaviour_mut().kademlia.store_mut().put(kad::Record::new(key, value));
+    }
+
+    pub fn get_value(&mut self, key: Vec<u8>) -> Option<Vec<u8>> {
+        let key = kad::RecordKey::new(&key);
+        self.swarm.behaviour_mut().kademlia.store_mut().get(&key).map(|record| record.value.clone())
+    }
+
     pub fn get_network_status(&self) -> NetworkStatus {
         NetworkStatus {
             storage_nodes: self.storage_nodes.keys().map(|id| id.to_string()).collect(),
@@ -269,18 +426,151 @@ impl Network {
         }
     }
 
+    pub fn set_placement_strategy(&mut self, strategy: Box<dyn PlacementStrategy>) {
+        self.debug_log(&format!("Placement strategy set to {}", strategy.name()));
+        self.placement = strategy;
+    }
+
+    /// Replaces the network's time source, e.g. with a `ManualClock` in simulations.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    pub fn set_storage_node_region(&mut self, storage_node_id: &PeerId, region: Option<String>) -> Result<(), &'static str> {
+        let storage_node = self.storage_nodes.get_mut(storage_node_id).ok_or("Storage node not found")?;
+        storage_node.set_region(region);
+        Ok(())
+    }
+
+    /// Picks `count` of `candidates` with `strategy`, or the network's default strategy.
+    /// `existing` are the SPs that already hold the data.
+    fn place(&self, candidates: &[PeerId], existing: &[PeerId], count: usize, strategy: Option<&dyn PlacementStrategy>) -> Vec<PeerId> {
+        let lookup = |ids: &[PeerId]| ids.iter().filter_map(|id| self.storage_nodes.get(id)).collect::<Vec<&StorageNode>>();
+        let strategy = strategy.unwrap_or(self.placement.as_ref());
+        strategy.select(&lookup(candidates), &lookup(existing), count, &mut rand::thread_rng())
+    }
+
+    /// Records a deal whose data is already in place: it is proposed, accepted with its
+    /// escrow and activated at once. Returns the deal id.
+    fn open_deal(&mut self, terms: DealTerms, escrow_id: Option<u64>) -> u64 {
+        let deal_id = self.next_deal_id;
+        self.next_deal_id += 1;
+        let now = self.now();
+        let mut deal = Deal::propose(deal_id, terms, self.current_epoch, now);
+        deal.accept(escrow_id, self.current_epoch, now)
+            .and_then(|_| deal.transition(DealState::Active, self.current_epoch, now))
+            .expect("a new deal can always be activated");
+        self.deals.insert(deal_id, deal);
+        deal_id
+    }
+
+    /// Moves a deal into a final state and refunds whatever is left in its escrow.
+    /// Returns the refund.
+    fn close_deal(&mut self, deal_id: u64, state: DealState) -> Result<TokenAmount, &'static str> {
+        let deal = self.deals.get(&deal_id).ok_or("Deal not found")?;
+        if !deal.state().can_transition_to(state) {
+            return Err("Deal has already ended");
+        }
+        let refund = match deal.escrow_id {
+            Some(escrow_id) => self.token.escrow_refund(escrow_id).map_err(|_| "Failed to refund escrow")?,
+            None => TokenAmount::ZERO,
+        };
+        if let Some(renewal) = self.deals.get_mut(&deal_id).unwrap().auto_renewal.take() {
+            self.token.escrow_refund(renewal.escrow_id).map_err(|_| "Failed to refund the renewal budget")?;
+        }
+        let (epoch, now) = (self.current_epoch, self.now());
+        self.deals.get_mut(&deal_id).unwrap().transition(state, epoch, now).map_err(|_| "Deal has already ended")?;
+        Ok(refund)
+    }
+
+    fn find_live_deal(&self, client_id: &PeerId, storage_node_id: &PeerId, filename: &str) -> Option<u64> {
+        self.deals.values()
+            .find(|d| d.is_live() && d.client_id == *client_id && d.storage_node_id == *storage_node_id && d.filename == filename)
+            .map(|d| d.id())
+    }
+
+    pub fn get_deal(&self, deal_id: u64) -> Option<&Deal> {
+        self.deals.get(&deal_id)
+    }
+
+    pub fn deals_by_client(&self, client_id: &PeerId) -> Vec<&Deal> {
+        self.deals.values().filter(|deal| deal.client_id == *client_id).collect()
+    }
+
+    pub fn deals_by_storage_node(&self, storage_node_id: &PeerId) -> Vec<&Deal> {
+        self.deals.values().filter(|deal| deal.storage_node_id == *storage_node_id).collect()
+    }
+
+    pub fn deals_for_file(&self, client_id: &PeerId, filename: &str) -> Vec<&Deal> {
+        self.deals.values().filter(|deal| deal.client_id == *client_id && deal.filename == filename).collect()
+    }
+
+    pub fn deals_in_state(&self, state: DealState) -> Vec<&Deal> {
+        self.deals.values().filter(|deal| deal.state() == state).collect()
+    }
+
+    /// The score breakdown the network publishes for an SP.
+    pub fn get_reputation(&self, storage_node_id: &PeerId) -> Result<ReputationBreakdown, String> {
+        if !self.storage_nodes.contains_key(storage_node_id) {
+            return Err("Storage node not found".to_string());
+        }
+        Ok(self.reputation.breakdown(storage_node_id))
+    }
+
+    /// Copies an SP's current score onto its `StorageNode` so placement and upload
+    /// constraints see it.
+    fn refresh_reputation(&mut self, storage_node_id: &PeerId) {
+        let score = self.reputation.score(storage_node_id);
+        if let Some(storage_node) = self.storage_nodes.get_mut(storage_node_id) {
+            storage_node.set_reputation(score);
+        }
+    }
+
+    fn refresh_reputations(&mut self) {
+        let storage_node_ids: Vec<PeerId> = self.storage_nodes.keys().cloned().collect();
+        for storage_node_id in storage_node_ids {
+            self.refresh_reputation(&storage_node_id);
+        }
+    }
+
     pub fn set_debug_level(&mut self, level: DebugLevel) {
         self.debug_level = level;
     }
 
-    pub fn add_storage_node(&mut self, peer_id: PeerId, price_per_gb: u64) {
-        self.storage_nodes.insert(peer_id, StorageNode::new(peer_id, price_per_gb));
+    /// Registers an SP. With rebalancing enabled, replicas start moving to it at once.
+    pub fn add_storage_node(&mut self, peer_id: PeerId, price_per_byte_epoch: TokenAmount) {
+        self.storage_nodes.insert(peer_id, StorageNode::new(peer_id, price_per_byte_epoch));
+        if self.rebalancer.policy().enabled {
+            self.rebalance();
+        }
     }
 
+    /// Registers a client. New clients start with no tokens; they can claim from the
+    /// faucet with `request_faucet_funds`.
     pub fn add_client(&mut self, peer_id: PeerId) {
         self.clients.insert(peer_id, Client::new(peer_id));
-        // Initialize client with 1,000,000 tokens
-        self.token.mint(&peer_id, 1_000_000);
+    }
+
+    /// Pays a faucet drip from the treasury to a registered client or SP, subject to the
+    /// faucet's per-identity rate limit and lifetime cap. Returns the amount paid. Errors
+    /// are typed so callers can tell a rate limit from an empty treasury.
+    pub fn request_faucet_funds(&mut self, peer_id: &PeerId) -> Result<TokenAmount, FaucetError> {
+        if !self.clients.contains_key(peer_id) && !self.storage_nodes.contains_key(peer_id) {
+            return Err(FaucetError::UnknownPeer);
+        }
+        let now = self.now();
+        let amount = self.faucet.allowance_at(peer_id, now)?;
+        self.token.disburse(peer_id, amount, "faucet").map_err(|e| match e {
+            TokenError::InsufficientTreasury { required, available } => FaucetError::TreasuryEmpty { required, available },
+            e => FaucetError::Ledger(Box::new(e)),
+        })?;
+        self.faucet.record_claim(peer_id, amount, now)?;
+        self.debug_log(&format!("Faucet paid {} tokens to {}", amount, peer_id));
+        Ok(amount)
     }
 
     pub fn list_clients(&self) -> Vec<PeerId> {
@@ -299,27 +589,45 @@ impl Network {
         &self.clients
     }
 
-    pub fn get_balance(&self, peer_id: &PeerId) -> u64 {
+    pub fn get_balance(&self, peer_id: &PeerId) -> TokenAmount {
         self.token.balance_of(peer_id)
     }
 
     pub fn upload_file(&mut self, client_id: &PeerId, filename: String, data: Vec<u8>, replication_factor: usize) -> Result<Vec<PeerId>, String> {
-        self.debug_log(&format!("Uploading file: {} for client: {} with replication factor: {}", filename, client_id, replication_factor));
+        self.upload_file_with_constraints(client_id, filename, data, replication_factor, &UploadConstraints::default(), None)
+    }
 
-        // Select storage nodes
-        let available_nodes: Vec<PeerId> = self.storage_nodes.keys().cloned().collect();
-        if available_nodes.len() < replication_factor {
-            return Err(format!("Not enough storage nodes available. Required: {}, Available: {}", replication_factor, available_nodes.len()));
+    /// Uploads a file under deals that run for `duration` instead of the default.
+    pub fn upload_file_for(&mut self, client_id: &PeerId, filename: String, data: Vec<u8>, replication_factor: usize, duration: Duration) -> Result<Vec<PeerId>, String> {
+        let constraints = UploadConstraints { duration: Some(duration), ..Default::default() };
+        self.upload_file_with_constraints(client_id, filename, data, replication_factor, &constraints, None)
+    }
+
+    /// Uploads a file to SPs chosen only from those that satisfy `constraints`, placed by
+    /// `strategy` or the network's default strategy.
+    pub fn upload_file_with_constraints(&mut self, client_id: &PeerId, filename: String, data: Vec<u8>, replication_factor: usize, constraints: &UploadConstraints, strategy: Option<&dyn PlacementStrategy>) -> Result<Vec<PeerId>, String> {
+        self.debug_log(&format!("Uploading file: {} for client: {} with replication factor: {}", filename, client_id, replication_factor));
+        if !self.clients.contains_key(client_id) {
+            return Err("Client not found".to_string());
         }
+        let duration = constraints.duration.unwrap_or(DEFAULT_DEAL_DURATION);
+        check_deal_duration(duration)?;
+
+        // Select storage nodes among those the client's constraints allow
+        let available_nodes = constraints.eligible_nodes(&self.storage_nodes, &filename, data.len(), replication_factor)
+            .map_err(|e| e.to_string())?;
 
-        let selected_nodes: Vec<PeerId> = available_nodes.choose_multiple(&mut rand::thread_rng(), replication_factor).cloned().collect();
+        let selected_nodes = self.place(&available_nodes, &[], replication_factor, strategy);
         self.debug_log(&format!("Selected nodes for storage: {:?}", selected_nodes));
 
-        // Calculate total cost
-        let file_size_gb = (data.len() as f64 / (1024.0 * 1024.0 * 1024.0)).ceil() as u64;
-        let total_cost: u64 = selected_nodes.iter()
-            .map(|node_id| file_size_gb * self.storage_nodes.get(node_id).unwrap().price_per_gb())
-            .sum();
+        // Calculate total cost for keeping the file for the whole deal
+        let epochs = epochs_in(duration);
+        let node_costs = selected_nodes.iter()
+            .map(|node_id| storage_cost(data.len(), epochs, self.storage_nodes[node_id].price_per_byte_epoch()))
+            .collect::<Result<Vec<TokenAmount>, String>>()?;
+        let total_cost = node_costs.iter()
+            .try_fold(TokenAmount::ZERO, |total, cost| total.checked_add(*cost))
+            .ok_or_else(|| "Upload cost overflows the token supply".to_string())?;
         self.debug_log(&format!("Total cost for upload: {} tokens", total_cost));
 
         // Check if the client has enough balance
@@ -328,76 +636,402 @@ impl Network {
             return Err(format!("Insufficient balance to upload file. Required: {}, Available: {}", total_cost, client_balance));
         }
 
-        // Store file and create deals
+        // Reserve space and escrow the payment on every target before anything is stored
+        let mut reservations = Vec::new();
+        for (node_id, node_cost) in selected_nodes.iter().zip(node_costs) {
+            match self.reserve_upload(client_id, node_id, &filename, data.len(), node_cost) {
+                Ok(escrow_id) => reservations.push((*node_id, escrow_id, node_cost)),
+                Err(e) => {
+                    self.rollback_upload(&reservations, data.len());
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(self.commit_upload(client_id, &filename, &data, duration, reservations))
+    }
+
+    /// Second phase of an upload, once every target is reserved: the file is stored, deals
+    /// are created and the client's file record is written. `reserve_upload` already made
+    /// sure no target holds a file of the same name.
+    fn commit_upload(&mut self, client_id: &PeerId, filename: &str, data: &[u8], duration: Duration, reservations: Vec<(PeerId, u64, TokenAmount)>) -> Vec<PeerId> {
+        let commitment = content_digest(data);
         let mut stored_nodes = Vec::new();
-        for node_id in &selected_nodes {
-            let storage_node = self.storage_nodes.get_mut(node_id).unwrap();
-            
-            if let Err(e) = storage_node.store_file(filename.clone(), data.clone()) {
-                return Err(format!("Failed to store file on node {}: {}", node_id, e));
+        for (node_id, escrow_id, node_cost) in reservations {
+            if let Err(e) = self.storage_nodes.get_mut(&node_id).unwrap().store_reserved_file(filename.to_string(), data.to_vec()) {
+                self.debug_log(&format!("Could not store {} on {}: {}", filename, node_id, e));
+                self.rollback_upload(&[(node_id, escrow_id, node_cost)], data.len());
+                continue;
+            }
+            let deal_id = self.open_deal(DealTerms {
+                client_id: *client_id,
+                storage_node_id: node_id,
+                filename: filename.to_string(),
+                duration,
+                total_payment: node_cost,
+                commitment,
+            }, Some(escrow_id));
+            self.debug_log(&format!("Created deal {}: client {} with storage node {} for file {}", deal_id, client_id, node_id, filename));
+
+            stored_nodes.push(node_id);
+        }
+
+        // Update client's file record
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.add_file(filename.to_string(), stored_nodes.clone());
+        }
+        self.debug_log(&format!("Updated client {} file record for {}", client_id, filename));
+        stored_nodes
+    }
+
+    /// First phase of an upload to one SP: takes the space on the node and escrows its
+    /// payment. Leaves nothing behind on failure. Returns the escrow id.
+    fn reserve_upload(&mut self, client_id: &PeerId, node_id: &PeerId, filename: &str, size: usize, cost: TokenAmount) -> Result<u64, String> {
+        let storage_node = self.storage_nodes.get_mut(node_id).ok_or_else(|| format!("Storage node {} not found", node_id))?;
+        if storage_node.is_draining() {
+            return Err(format!("Storage node {} is leaving the network", node_id));
+        }
+        if storage_node.get_file(filename).is_some() {
+            return Err(format!("Storage node {} already holds a file named {}", node_id, filename));
+        }
+        storage_node.reserve_space(size).map_err(|e| format!("Failed to reserve space on node {}: {}", node_id, e))?;
+
+        match self.token.escrow_deposit(client_id, cost, Purpose::Upload) {
+            Ok(escrow_id) => {
+                self.debug_log(&format!("Reserved {} bytes on {} and escrowed {} tokens from {}", size, node_id, cost, client_id));
+                Ok(escrow_id)
             }
+            Err(e) => {
+                self.storage_nodes.get_mut(node_id).unwrap().release_space(size);
+                Err(format!("Failed to escrow tokens: {}", e))
+            }
+        }
+    }
 
-            let node_cost = file_size_gb * storage_node.price_per_gb();
-            if !self.token.transfer(client_id, node_id, node_cost) {
-                return Err("Failed to transfer tokens".to_string());
+    /// Undoes the reservations of an upload that could not reserve every target.
+    fn rollback_upload(&mut self, reservations: &[(PeerId, u64, TokenAmount)], size: usize) {
+        for (node_id, escrow_id, _) in reservations {
+            if let Some(storage_node) = self.storage_nodes.get_mut(node_id) {
+                storage_node.release_space(size);
+            }
+            if let Err(e) = self.token.escrow_refund(*escrow_id) {
+                self.debug_log(&format!("Failed to refund escrow {} during rollback: {}", escrow_id, e));
             }
-            self.debug_log(&format!("Transferred {} tokens from {} to {} for storage", node_cost, client_id, node_id));
+        }
+        self.debug_log(&format!("Rolled back upload reservations on {} nodes", reservations.len()));
+    }
 
-            self.deals.push(Deal::new(
-                *client_id,
-                *node_id,
-                filename.clone(),
-                DEAL_DURATION,
-            ));
-            self.debug_log(&format!("Created new deal: client {} with storage node {} for file {}", client_id, node_id, filename));
+    /// Opens a reverse auction for storing `data`. SPs bid a price per byte-epoch with
+    /// `submit_bid` until the terms' bidding window has passed. The most the auction can
+    /// cost, every replica at the maximum price, is escrowed until it closes. Returns the
+    /// request id.
+    pub fn post_storage_request(&mut self, client_id: &PeerId, filename: String, data: Vec<u8>, terms: AuctionTerms) -> Result<u64, String> {
+        if !self.clients.contains_key(client_id) {
+            return Err("Client not found".to_string());
+        }
+        if terms.replication_factor == 0 {
+            return Err("Replication factor must be at least 1".to_string());
+        }
+        let budget = storage_cost(data.len(), epochs_in(terms.duration), terms.max_price_per_byte_epoch)?
+            .checked_mul(terms.replication_factor as u128)
+            .ok_or_else(|| "Storage cost overflows the token supply".to_string())?;
+        let escrow_id = self.token.escrow_deposit(client_id, budget, Purpose::Upload)
+            .map_err(|e| format!("Failed to escrow the request budget: {}", e))?;
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        self.debug_log(&format!("Storage request {} opened by {} for {} ({} bytes x{}, max {} per byte-epoch)", request_id, client_id, filename, data.len(), terms.replication_factor, terms.max_price_per_byte_epoch));
+        self.storage_requests.insert(request_id, StorageRequest {
+            id: request_id,
+            client_id: *client_id,
+            filename,
+            data,
+            terms,
+            opened_at: self.now(),
+            status: AuctionStatus::Open,
+            escrow_id,
+        });
+        self.bids.insert(request_id, Vec::new());
+        Ok(request_id)
+    }
 
-            stored_nodes.push(*node_id);
+    /// Places or replaces an SP's bid on an open storage request. Bids above the
+    /// client's maximum price or from SPs without room for the file are rejected.
+    pub fn submit_bid(&mut self, request_id: u64, storage_node_id: &PeerId, price_per_byte_epoch: TokenAmount) -> Result<(), String> {
+        let request = self.storage_requests.get(&request_id).ok_or_else(|| "Storage request not found".to_string())?;
+        if !request.is_open_at(self.now()) {
+            return Err("Bidding on this storage request has closed".to_string());
+        }
+        if price_per_byte_epoch > request.terms.max_price_per_byte_epoch {
+            return Err(format!("Bid of {} exceeds the maximum price of {}", price_per_byte_epoch, request.terms.max_price_per_byte_epoch));
+        }
+        let storage_node = self.storage_nodes.get(storage_node_id).ok_or_else(|| "Storage node not found".to_string())?;
+        if storage_node.is_draining() {
+            return Err("Storage node is leaving the network".to_string());
+        }
+        if storage_node.available_space() < request.size() {
+            return Err("Storage node does not have enough space for this request".to_string());
         }
 
-        // Update client's file record
-        let client = self.clients.get_mut(client_id).ok_or_else(|| "Client not found".to_string())?;
-        client.add_file(filename.clone(), stored_nodes.clone());
-        self.debug_log(&format!("Updated client {} file record for {}", client_id, filename));
+        let bids = self.bids.entry(request_id).or_default();
+        bids.retain(|bid| bid.storage_node_id != *storage_node_id);
+        bids.push(Bid::new(*storage_node_id, price_per_byte_epoch));
+        self.debug_log(&format!("{} bid {} per byte-epoch on storage request {}", storage_node_id, price_per_byte_epoch, request_id));
+        Ok(())
+    }
+
+    /// Settles a storage request once its bidding window has passed. The budget escrowed
+    /// at posting goes back to the client, the cheapest bids that can still be reserved
+    /// win (earlier bids break ties), each winner is paid its own bid through an escrowed
+    /// deal, and every bidder gets an `AuctionNotice`. If fewer deals than requested could
+    /// be created the request is marked partially filled and the shortfall is recorded in
+    /// `under_replicated`.
+    pub fn close_storage_request(&mut self, request_id: u64) -> Result<Vec<PeerId>, String> {
+        let request = self.storage_requests.get(&request_id).ok_or_else(|| "Storage request not found".to_string())?.clone();
+        if request.status != AuctionStatus::Open {
+            return Err("Storage request is already closed".to_string());
+        }
+        if self.now() < request.closes_at() {
+            return Err("Bidding window is still open".to_string());
+        }
+
+        // Every bid is at most the maximum price, so the refund covers each winner's escrow
+        self.token.escrow_refund(request.escrow_id).map_err(|e| format!("Failed to release the request budget: {}", e))?;
+
+        // Stable sort keeps submission order among equal prices
+        let mut bids = self.bids.remove(&request_id).unwrap_or_default();
+        bids.sort_by_key(|bid| bid.price_per_byte_epoch);
+
+        let epochs = epochs_in(request.terms.duration);
+        let mut reservations = Vec::new();
+        let mut winning_prices = Vec::new();
+        for bid in &bids {
+            if reservations.len() == request.terms.replication_factor {
+                break;
+            }
+            let cost = storage_cost(request.size(), epochs, bid.price_per_byte_epoch)?;
+            match self.reserve_upload(&request.client_id, &bid.storage_node_id, &request.filename, request.size(), cost) {
+                Ok(escrow_id) => {
+                    reservations.push((bid.storage_node_id, escrow_id, cost));
+                    winning_prices.push(bid.price_per_byte_epoch);
+                }
+                Err(e) => self.debug_log(&format!("Skipping bid from {} on request {}: {}", bid.storage_node_id, request_id, e)),
+            }
+        }
+
+        let (status, result) = if reservations.len() < request.terms.replication_factor {
+            self.rollback_upload(&reservations, request.size());
+            let reason = format!("Only {} of {} required bids could be accepted", reservations.len(), request.terms.replication_factor);
+            winning_prices.clear();
+            (AuctionStatus::Failed(reason.clone()), Err(reason))
+        } else {
+            let winners = self.commit_upload(&request.client_id, &request.filename, &request.data, request.terms.duration, reservations);
+            let missing = request.terms.replication_factor - winners.len();
+            if missing == 0 {
+                (AuctionStatus::Awarded(winners.clone()), Ok(winners))
+            } else {
+                if !winners.is_empty() {
+                    *self.under_replicated.entry((request.client_id, request.filename.clone())).or_insert(0) += missing;
+                }
+                let reason = format!("Only {} of {} deals could be created", winners.len(), request.terms.replication_factor);
+                (AuctionStatus::PartiallyFilled(winners), Err(reason))
+            }
+        };
+
+        let clearing_price = winning_prices.iter().max().copied();
+        for bid in bids {
+            let won = matches!(&status, AuctionStatus::Awarded(winners) | AuctionStatus::PartiallyFilled(winners) if winners.contains(&bid.storage_node_id));
+            let outcome = if won {
+                BidOutcome::Won { price_per_byte_epoch: bid.price_per_byte_epoch }
+            } else {
+                BidOutcome::Lost { clearing_price }
+            };
+            self.debug_log(&format!("Storage request {}: bid from {} {}", request_id, bid.storage_node_id, if won { "won" } else { "lost" }));
+            self.auction_notices.push(AuctionNotice { request_id, storage_node_id: bid.storage_node_id, outcome });
+        }
 
-        Ok(stored_nodes)
+        if let Some(request) = self.storage_requests.get_mut(&request_id) {
+            request.status = status;
+        }
+        result
+    }
+
+    /// Closes every open storage request whose bidding window has passed.
+    pub fn close_expired_storage_requests(&mut self) -> Vec<(u64, Result<Vec<PeerId>, String>)> {
+        let now = self.now();
+        let mut expired: Vec<u64> = self.storage_requests.values()
+            .filter(|request| request.status == AuctionStatus::Open && now >= request.closes_at())
+            .map(|request| request.id)
+            .collect();
+        expired.sort_unstable();
+        expired.into_iter()
+            .map(|request_id| (request_id, self.close_storage_request(request_id)))
+            .collect()
     }
 
-    fn chain_upload(&mut self, source_node_id: &PeerId, filename: &str, data: &Vec<u8>, remaining_replications: usize) -> Result<(), &'static str> {
+    pub fn get_auction_notices(&self, storage_node_id: &PeerId) -> Vec<&AuctionNotice> {
+        self.auction_notices.iter()
+            .filter(|notice| notice.storage_node_id == *storage_node_id)
+            .collect()
+    }
+
+    /// Forwards the chain's data from `source_node_id` to the next SP in the chain. Once that
+    /// SP has stored it, the chain's payer escrows its deal out of the client's allowance.
+    fn chain_upload(&mut self, chain: &ChainUpload, source_node_id: &PeerId, remaining_replications: usize, stored_nodes: &mut Vec<PeerId>) -> Result<(), &'static str> {
         if remaining_replications == 0 {
             return Ok(());
         }
+        let ChainUpload { client_id, payer_id, filename, data, duration } = *chain;
 
-        let available_nodes: Vec<PeerId> = self.storage_nodes.keys()
-            .filter(|&id| id != source_node_id)
-            .cloned()
+        let available_nodes: Vec<PeerId> = self.storage_nodes.iter()
+            .filter(|(id, node)| *id != source_node_id && !node.is_draining() && node.get_file(filename).is_none())
+            .map(|(id, _)| *id)
             .collect();
 
-        if available_nodes.is_empty() {
+        let mut placed_nodes = stored_nodes.clone();
+        placed_nodes.push(*source_node_id);
+        let Some(target_node_id) = self.place(&available_nodes, &placed_nodes, 1, None).pop() else {
             return Err("No available storage nodes for replication");
-        }
-
-        let target_node_id = available_nodes[rand::random::<usize>() % available_nodes.len()];
+        };
         let target_node = self.storage_nodes.get_mut(&target_node_id).unwrap();
 
-        target_node.store_file(filename.to_string(), data.clone())?;
+        let cost = storage_cost(data.len(), epochs_in(duration), target_node.price_per_byte_epoch())
+            .map_err(|_| "Replication cost overflows the token supply")?;
+        target_node.store_file(filename.to_string(), data.to_vec())?;
+
+        let Ok(escrow_id) = self.token.escrow_deposit_from(payer_id, client_id, cost, Purpose::Replication, filename) else {
+            let _ = self.storage_nodes.get_mut(&target_node_id).unwrap().remove_file(filename);
+            return Err("Failed to pay downstream storage node");
+        };
+        self.debug_log(&format!("{} escrowed {} tokens of {}'s funds for {} to replicate {}", payer_id, cost, client_id, target_node_id, filename));
+
+        self.replication_payments.push(ReplicationPayment {
+            client_id: *client_id,
+            payer_id: *payer_id,
+            storage_node_id: target_node_id,
+            filename: filename.to_string(),
+            amount: cost,
+        });
+        self.open_deal(DealTerms {
+            client_id: *client_id,
+            storage_node_id: target_node_id,
+            filename: filename.to_string(),
+            duration,
+            total_payment: cost,
+            commitment: content_digest(data),
+        }, Some(escrow_id));
+        stored_nodes.push(target_node_id);
 
         // Recursively continue the chain upload
-        self.chain_upload(&target_node_id, filename, data, remaining_replications - 1)
+        self.chain_upload(chain, &target_node_id, remaining_replications - 1, stored_nodes)
     }
 
-    pub fn download_file(&self, client_id: &PeerId, filename: &str) -> Result<Vec<u8>, String> {
+    /// Downloads a file from the first SP that has it, paying that SP's retrieval price
+    /// for every byte served.
+    pub fn download_file(&mut self, client_id: &PeerId, filename: &str) -> Result<Vec<u8>, String> {
         let client = self.clients.get(client_id).ok_or_else(|| "Client not found".to_string())?;
-        let storage_nodes = client.get_file_locations(filename).ok_or_else(|| "File not found".to_string())?;
+        let storage_nodes = client.get_file_locations(filename).ok_or_else(|| "File not found".to_string())?.clone();
 
+        // Try each location in turn; every SP asked is scored on whether it served the file
+        let mut found = None;
         for node_id in storage_nodes {
-            if let Some(storage_node) = self.storage_nodes.get(node_id) {
-                if let Some(file_data) = storage_node.get_file(filename) {
-                    return Ok(file_data.clone());
-                }
+            // Latency is wall time, not the network clock, so it is what the SP really took
+            let started = Instant::now();
+            let file_data = self.storage_nodes.get(&node_id)
+                .and_then(|storage_node| storage_node.get_file(filename))
+                .cloned();
+            let latency = started.elapsed();
+            self.reputation.record_retrieval(&node_id, file_data.is_some(), file_data.as_ref().map(|_| latency));
+            self.refresh_reputation(&node_id);
+            if let Some(file_data) = file_data {
+                found = Some((node_id, file_data));
+                break;
+            }
+        }
+        let (node_id, file_data) = found.ok_or_else(|| "File not found on any storage node".to_string())?;
+
+        self.pay_for_retrieval(client_id, &[(node_id, file_data.len())])?;
+        Ok(file_data)
+    }
+
+    /// Downloads a file BitTorrent style: every SP holding it serves one contiguous range
+    /// and is paid its retrieval price for exactly the bytes it served.
+    pub fn download_file_split(&mut self, client_id: &PeerId, filename: &str) -> Result<Vec<u8>, String> {
+        let locations = self.get_file_locations(client_id, filename)?;
+        for node_id in &locations {
+            let holds_file = self.storage_nodes.get(node_id).is_some_and(|storage_node| storage_node.get_file(filename).is_some());
+            if !holds_file {
+                self.reputation.record_retrieval(node_id, false, None);
+                self.refresh_reputation(node_id);
+            }
+        }
+        let sources: Vec<(PeerId, &Vec<u8>)> = locations.iter()
+            .filter_map(|node_id| {
+                self.storage_nodes.get(node_id)
+                    .and_then(|storage_node| storage_node.get_file(filename))
+                    .map(|file_data| (*node_id, file_data))
+            })
+            .collect();
+        if sources.is_empty() {
+            return Err("File not found on any storage node".to_string());
+        }
+
+        let file_len = sources[0].1.len();
+        let range_len = file_len.div_ceil(sources.len());
+        let mut data = Vec::with_capacity(file_len);
+        let mut served = Vec::new();
+        for (index, (node_id, file_data)) in sources.iter().enumerate() {
+            let start = (index * range_len).min(file_len);
+            let end = (start + range_len).min(file_len);
+            let started = Instant::now();
+            let range = file_data.get(start..end).ok_or_else(|| format!("Storage node {} holds a truncated copy", node_id))?;
+            data.extend_from_slice(range);
+            served.push((*node_id, range.len(), started.elapsed()));
+        }
+        for (node_id, _, latency) in &served {
+            self.reputation.record_retrieval(node_id, true, Some(*latency));
+            self.refresh_reputation(node_id);
+        }
+        let served: Vec<(PeerId, usize)> = served.into_iter().map(|(node_id, bytes, _)| (node_id, bytes)).collect();
+
+        self.pay_for_retrieval(client_id, &served)?;
+        Ok(data)
+    }
+
+    /// Charges the client for bytes served by each SP. Nothing is paid unless the client
+    /// can cover every SP.
+    fn pay_for_retrieval(&mut self, client_id: &PeerId, served: &[(PeerId, usize)]) -> Result<TokenAmount, String> {
+        let charges = served.iter()
+            .map(|(node_id, bytes)| {
+                let price = self.storage_nodes.get(node_id).map_or(TokenAmount::ZERO, |node| node.retrieval_price_per_byte());
+                retrieval_cost(*bytes, price).map(|cost| (*node_id, cost))
+            })
+            .collect::<Result<Vec<(PeerId, TokenAmount)>, String>>()?;
+        let total = charges.iter()
+            .try_fold(TokenAmount::ZERO, |total, (_, cost)| total.checked_add(*cost))
+            .ok_or_else(|| "Retrieval cost overflows the token supply".to_string())?;
+        let client_balance = self.token.balance_of(client_id);
+        if client_balance < total {
+            return Err(format!("Insufficient balance to pay for retrieval. Required: {}, Available: {}", total, client_balance));
+        }
+
+        for (node_id, cost) in charges {
+            if cost.is_zero() {
+                continue;
             }
+            self.token.transfer_with_memo(client_id, &node_id, cost, Purpose::Retrieval, "download")
+                .map_err(|e| format!("Failed to transfer tokens: {}", e))?;
+            if let Some(storage_node) = self.storage_nodes.get_mut(&node_id) {
+                storage_node.record_retrieval_earnings(cost);
+            }
+            self.debug_log(&format!("Transferred {} tokens from {} to {} for retrieval", cost, client_id, node_id));
         }
+        Ok(total)
+    }
 
-        Err("File not found on any storage node".to_string())
+    pub fn set_retrieval_price(&mut self, storage_node_id: &PeerId, price_per_byte: TokenAmount) -> Result<(), &'static str> {
+        let storage_node = self.storage_nodes.get_mut(storage_node_id).ok_or("Storage node not found")?;
+        storage_node.set_retrieval_price_per_byte(price_per_byte);
+        Ok(())
     }
 
     pub fn get_file_locations(&self, client_id: &PeerId, filename: &str) -> Result<Vec<PeerId>, String> {
@@ -410,28 +1044,149 @@ impl Network {
         storage_node.get_file(filename).cloned().ok_or_else(|| "File not found on storage node".to_string())
     }
 
-    pub fn renew_deal(&mut self, client_id: &PeerId, storage_node_id: &PeerId, filename: &str) -> Result<(), &'static str> {
-        let deal = self.deals.iter_mut()
-            .find(|d| d.client_id == *client_id && d.storage_node_id == *storage_node_id && d.filename == filename)
-            .ok_or("Deal not found")?;
+    /// What extending a deal by `extension` costs at its SP's current price.
+    pub fn renewal_cost(&self, deal_id: u64, extension: Duration) -> Result<TokenAmount, String> {
+        let deal = self.deals.get(&deal_id).ok_or_else(|| "Deal not found".to_string())?;
+        let storage_node = self.storage_nodes.get(&deal.storage_node_id).ok_or_else(|| "Storage node not found".to_string())?;
+        let size = storage_node.get_file(&deal.filename).map(|data| data.len())
+            .ok_or_else(|| "Storage node no longer holds the file".to_string())?;
+        storage_cost(size, epochs_in(extension), storage_node.price_per_byte_epoch())
+    }
+
+    /// Extends a live deal by `extension`, charging the client the SP's current price
+    /// into the deal's escrow. Works during the grace period too. Returns the cost.
+    pub fn renew_deal(&mut self, client_id: &PeerId, storage_node_id: &PeerId, filename: &str, extension: Duration) -> Result<TokenAmount, String> {
+        let deal_id = self.find_live_deal(client_id, storage_node_id, filename).ok_or_else(|| "Deal not found".to_string())?;
+        self.extend_deal(deal_id, extension)
+    }
+
+    fn extend_deal(&mut self, deal_id: u64, extension: Duration) -> Result<TokenAmount, String> {
+        if extension < EPOCH_DURATION {
+            return Err(format!("Renewals must add at least one epoch ({}s)", EPOCH_DURATION.as_secs()));
+        }
+        let cost = self.renewal_cost(deal_id, extension)?;
+        let deal = &self.deals[&deal_id];
+        let client_id = deal.client_id;
+        let escrow_id = deal.escrow_id.ok_or_else(|| "Deal has no escrow to renew into".to_string())?;
+        // Checked before paying, so a renewal the deal cannot record is never charged
+        let mut extended = deal.clone();
+        extended.extend(extension, cost)?;
+        self.token.escrow_top_up(escrow_id, &client_id, cost).map_err(|e| format!("Failed to pay for renewal: {}", e))?;
+
+        let (epoch, now) = (self.current_epoch, self.now());
+        let deal = self.deals.get_mut(&deal_id).unwrap();
+        *deal = extended;
+        let comfortable = deal.epochs - deal.epochs_paid > EXPIRING_EPOCHS && !deal.has_run_out_at(now);
+        if deal.state() == DealState::Expiring && comfortable {
+            deal.transition(DealState::Active, epoch, now)?;
+        }
+        self.debug_log(&format!("Renewed deal {} by {}s for {} tokens", deal_id, extension.as_secs(), cost));
+        Ok(cost)
+    }
 
-        deal.start_time = Some(Instant::now());
+    /// Lets the network renew a live deal by `extension` whenever it is about to run out,
+    /// paid from `budget`, which is escrowed now. Replaces any earlier auto-renewal and
+    /// refunds its unspent budget.
+    pub fn set_auto_renewal(&mut self, client_id: &PeerId, deal_id: u64, extension: Duration, budget: TokenAmount) -> Result<(), String> {
+        let deal = self.deals.get(&deal_id).ok_or_else(|| "Deal not found".to_string())?;
+        if deal.client_id != *client_id {
+            return Err("Only the deal's client can set up auto-renewal".to_string());
+        }
+        if !deal.is_live() {
+            return Err("Deal has already ended".to_string());
+        }
+        if extension < EPOCH_DURATION {
+            return Err(format!("Renewals must add at least one epoch ({}s)", EPOCH_DURATION.as_secs()));
+        }
+        self.cancel_auto_renewal(client_id, deal_id)?;
+        let escrow_id = self.token.escrow_deposit(client_id, budget, Purpose::Upload)
+            .map_err(|e| format!("Failed to escrow the renewal budget: {}", e))?;
+        self.deals.get_mut(&deal_id).unwrap().auto_renewal = Some(AutoRenewal { extension, escrow_id });
         Ok(())
     }
 
-    pub fn check_deals(&mut self) {
-        let mut expired_deals = Vec::new();
-        for deal in &self.deals {
-            if deal.start_time().elapsed() > deal.duration {
-                expired_deals.push(deal.clone());
+    /// Stops auto-renewal and refunds the unspent budget, which is returned.
+    pub fn cancel_auto_renewal(&mut self, client_id: &PeerId, deal_id: u64) -> Result<TokenAmount, String> {
+        let deal = self.deals.get_mut(&deal_id).ok_or_else(|| "Deal not found".to_string())?;
+        if deal.client_id != *client_id {
+            return Err("Only the deal's client can cancel auto-renewal".to_string());
+        }
+        match deal.auto_renewal.take() {
+            Some(renewal) => self.token.escrow_refund(renewal.escrow_id).map_err(|e| format!("Failed to refund the renewal budget: {}", e)),
+            None => Ok(TokenAmount::ZERO),
+        }
+    }
+
+    /// Renews a deal from its auto-renewal budget if the budget still covers the SP's
+    /// current price. Returns whether the deal was renewed.
+    fn try_auto_renew(&mut self, deal_id: u64) -> bool {
+        let Some(deal) = self.deals.get(&deal_id) else { return false };
+        let Some(renewal) = deal.auto_renewal.clone() else { return false };
+        let client_id = deal.client_id;
+        let renewed = self.renewal_cost(deal_id, renewal.extension).and_then(|cost| {
+            self.token.escrow_release(renewal.escrow_id, &client_id, cost).map_err(|e| e.to_string())?;
+            let extended = self.extend_deal(deal_id, renewal.extension);
+            if extended.is_err() {
+                // The budget goes back into escrow so a failed renewal spends nothing
+                if let Err(e) = self.token.escrow_top_up(renewal.escrow_id, &client_id, cost) {
+                    self.debug_log(&format!("Failed to return {} tokens to renewal escrow {}: {}", cost, renewal.escrow_id, e));
+                }
+            }
+            extended
+        });
+        match renewed {
+            Ok(cost) => {
+                self.debug_log(&format!("Auto-renewed deal {} for {} tokens", deal_id, cost));
+                true
+            }
+            Err(e) => {
+                self.debug_log(&format!("Could not auto-renew deal {}: {}", deal_id, e));
+                false
             }
         }
+    }
+
+    /// Handles live deals whose term has ended, by the network clock or because every
+    /// epoch has been paid. Deals with an auto-renewal budget are renewed; the rest are
+    /// kept as `Expiring` for the grace period and then expired, removing only that SP's
+    /// replica.
+    pub fn check_deals(&mut self) {
+        let now = self.now();
+        let ended_deals: Vec<u64> = self.deals.values()
+            .filter(|deal| deal.is_live() && deal.term_ended_at(now).is_some())
+            .map(|deal| deal.id())
+            .collect();
 
-        for deal in expired_deals {
-            if let Err(e) = self.remove_file(&deal.client_id, &deal.filename) {
-                println!("Error removing expired file: {}", e);
+        for deal_id in ended_deals {
+            self.wind_down_deal(deal_id);
+        }
+    }
+
+    /// Renews, keeps for the grace period, or expires a deal whose term has ended.
+    fn wind_down_deal(&mut self, deal_id: u64) {
+        let now = self.now();
+        let Some(deal) = self.deals.get(&deal_id) else { return };
+        if !deal.is_live() {
+            // Already ended along with another replica of the same file
+            return;
+        }
+        if self.try_auto_renew(deal_id) && self.deals[&deal_id].term_ended_at(now).is_none() {
+            return;
+        }
+        let deal = &self.deals[&deal_id];
+        let Some(ended_at) = deal.term_ended_at(now) else { return };
+        let grace_ends = ended_at + self.grace_period;
+        if now < grace_ends {
+            if deal.state() == DealState::Active {
+                let epoch = self.current_epoch;
+                let _ = self.deals.get_mut(&deal_id).unwrap().transition(DealState::Expiring, epoch, now);
+                self.debug_log(&format!("Deal {} ran out; its data is kept until {}", deal_id, grace_ends));
             }
-            self.deals.retain(|d| d.filename != deal.filename || d.client_id != deal.client_id);
+            return;
+        }
+
+        if let Err(e) = self.end_deal(deal_id, DealState::Expired) {
+            self.debug_log(&format!("Error expiring deal {}: {}", deal_id, e));
         }
     }
 
@@ -446,10 +1201,526 @@ impl Network {
         }
 
         client.remove_file(filename);
-        self.deals.retain(|d| d.filename != filename || d.client_id != *client_id);
+        self.under_replicated.remove(&(*client_id, filename.to_string()));
+        let deal_ids: Vec<u64> = self.deals.values()
+            .filter(|d| d.filename == filename && d.client_id == *client_id && !d.state().is_final())
+            .map(|d| d.id())
+            .collect();
+        for deal_id in deal_ids {
+            if let Err(e) = self.close_deal(deal_id, DealState::Terminated) {
+                self.debug_log(&format!("Failed to terminate deal {}: {}", deal_id, e));
+            }
+        }
         Ok(())
     }
 
+    /// Advances the network by one payment epoch. Every escrowed deal whose SP can still
+    /// prove it holds the data receives that epoch's share of the escrow; deals whose
+    /// proof fails are slashed and the unpaid remainder goes back to the client.
+    pub fn advance_epoch(&mut self) {
+        self.current_epoch += 1;
+        self.debug_log(&format!("Advancing to epoch {}", self.current_epoch));
+        self.reputation.decay();
+
+        let mut failed_deals = Vec::new();
+        let mut paid_out_deals = Vec::new();
+        // Whether each audited SP answered at least one challenge this epoch
+        let mut responded: HashMap<PeerId, bool> = HashMap::new();
+        let live_deals: Vec<u64> = self.deals.values().filter(|deal| deal.is_live()).map(|deal| deal.id()).collect();
+        for deal_id in live_deals {
+            let deal = &self.deals[&deal_id];
+            let Some(escrow_id) = deal.escrow_id else { continue };
+            if deal.epochs_paid >= deal.epochs {
+                continue;
+            }
+
+            let storage_node_id = deal.storage_node_id;
+            let response = self.storage_nodes.get(&deal.storage_node_id)
+                .and_then(|node| node.get_file(&deal.filename));
+            *responded.entry(storage_node_id).or_default() |= response.is_some();
+            let proof_passed = response.is_some_and(|data| content_digest(data) == deal.commitment);
+            self.reputation.record_audit(&storage_node_id, proof_passed);
+            if !proof_passed {
+                self.debug_log(&format!("Storage proof failed for {} on {}", deal.filename, deal.storage_node_id));
+                self.reputation.record_deal_outcome(&storage_node_id, false);
+                failed_deals.push(deal_id);
+                continue;
+            }
+
+            let payout = deal.next_epoch_payout();
+            if self.token.escrow_release(escrow_id, &storage_node_id, payout).is_ok() {
+                if let Some(storage_node) = self.storage_nodes.get_mut(&storage_node_id) {
+                    storage_node.record_storage_earnings(payout);
+                }
+                self.debug_log(&format!("Paid {} tokens to {} for epoch {}", payout, storage_node_id, self.current_epoch));
+
+                let deal = self.deals.get_mut(&deal_id).unwrap();
+                deal.epochs_paid += 1;
+                // Never more than the deal's unpaid total, so this cannot saturate
+                deal.amount_paid = deal.amount_paid.saturating_add(payout);
+                let remaining = deal.epochs - deal.epochs_paid;
+                if remaining <= EXPIRING_EPOCHS && self.try_auto_renew(deal_id) {
+                    continue;
+                }
+                let deal = self.deals.get_mut(&deal_id).unwrap();
+                if remaining == 0 {
+                    deal.paid_out_at = Some(self.clock.now());
+                    self.reputation.record_deal_outcome(&storage_node_id, true);
+                    paid_out_deals.push(deal_id);
+                } else if remaining <= EXPIRING_EPOCHS && deal.state() == DealState::Active {
+                    let _ = deal.transition(DealState::Expiring, self.current_epoch, self.clock.now());
+                }
+            }
+        }
+
+        for (storage_node_id, online) in responded {
+            self.reputation.record_uptime(&storage_node_id, online);
+        }
+        for deal_id in failed_deals {
+            if let Err(e) = self.end_deal(deal_id, DealState::Slashed) {
+                self.debug_log(&format!("Error slashing failed deal {}: {}", deal_id, e));
+            }
+        }
+        for deal_id in paid_out_deals {
+            self.wind_down_deal(deal_id);
+        }
+        self.refresh_reputations();
+
+        self.expire_storage_offers();
+    }
+
+    /// Removes storage offers past their last epoch. Returns how many were removed.
+    pub fn expire_storage_offers(&mut self) -> usize {
+        let expired = self.marketplace.remove_expired(self.current_epoch);
+        for offer in &expired {
+            self.debug_log(&format!("Storage offer {} from {} expired", offer.id, offer.storage_node_id));
+        }
+        expired.len()
+    }
+
+    /// Runs every scheduled job that is due by the network clock. Returns each job run
+    /// with a summary of what it did.
+    pub fn run_due_jobs(&mut self) -> Vec<(MaintenanceJob, String)> {
+        let due = self.scheduler.due(self.now());
+        due.into_iter().map(|job| (job, self.run_job(job))).collect()
+    }
+
+    /// Runs `job` now, even if it is paused, and records the run. Returns a summary.
+    pub fn run_job(&mut self, job: MaintenanceJob) -> String {
+        let started = self.now();
+        let summary = match job {
+            MaintenanceJob::CheckDeals => {
+                let live_before = self.deals.values().filter(|deal| deal.is_live()).count();
+                self.check_deals();
+                let live_after = self.deals.values().filter(|deal| deal.is_live()).count();
+                format!("{} deals ended, {} still live", live_before.saturating_sub(live_after), live_after)
+            }
+            MaintenanceJob::AdjustPrices => format!("{} prices changed", self.adjust_prices().len()),
+            MaintenanceJob::Audits => {
+                self.advance_epoch();
+                format!("audited and paid epoch {}", self.current_epoch)
+            }
+            MaintenanceJob::RepairScan => {
+                let repaired = self.repair_under_replicated();
+                format!("{} replicas repaired, {} moved off draining SPs", repaired, self.resume_drains())
+            }
+            MaintenanceJob::ExpireOffers => format!("{} offers expired", self.expire_storage_offers()),
+            MaintenanceJob::Rebalance => format!("{} replicas moved", self.rebalance().len()),
+        };
+        self.debug_log(&format!("Ran {}: {}", job, summary));
+        self.scheduler.record_run(job, started, summary.clone());
+        summary
+    }
+
+    /// Ends a single deal early. The SP's copy is dropped, the client's file record no
+    /// longer lists the SP, and the escrowed remainder is refunded. Returns the refund.
+    pub fn terminate_deal(&mut self, client_id: &PeerId, storage_node_id: &PeerId, filename: &str) -> Result<TokenAmount, &'static str> {
+        let deal_id = self.find_live_deal(client_id, storage_node_id, filename).ok_or("Deal not found")?;
+        self.end_deal(deal_id, DealState::Terminated)
+    }
+
+    /// Closes a deal as `state` and removes the SP's replica from the client's file record.
+    fn end_deal(&mut self, deal_id: u64, state: DealState) -> Result<TokenAmount, &'static str> {
+        let refund = self.close_deal(deal_id, state)?;
+        let deal = &self.deals[&deal_id];
+        let (client_id, storage_node_id, filename) = (deal.client_id, deal.storage_node_id, deal.filename.clone());
+
+        if let Some(storage_node) = self.storage_nodes.get_mut(&storage_node_id) {
+            let _ = storage_node.remove_file(&filename);
+        }
+
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            if let Some(locations) = client.get_file_locations(&filename) {
+                let remaining: Vec<PeerId> = locations.iter().filter(|&id| *id != storage_node_id).cloned().collect();
+                if remaining.is_empty() {
+                    client.remove_file(&filename);
+                } else {
+                    client.add_file(filename.to_string(), remaining);
+                }
+            }
+        }
+
+        self.debug_log(&format!("Deal {} for {} on {} is {}, refunded {} tokens to {}", deal_id, filename, storage_node_id, state, refund, client_id));
+        if state != DealState::Terminated {
+            self.handle_lost_replica(&client_id, &filename);
+        }
+        Ok(refund)
+    }
+
+    /// Replaces a replica lost to expiry or slashing while the client still keeps the
+    /// file elsewhere. If no SP can take it, the shortfall is recorded in
+    /// `under_replicated` for a later `repair_replication`.
+    fn handle_lost_replica(&mut self, client_id: &PeerId, filename: &str) {
+        if self.remaining_term(client_id, filename).is_none() {
+            // The file is no longer kept anywhere, so there is nothing to repair
+            return;
+        }
+        *self.under_replicated.entry((*client_id, filename.to_string())).or_insert(0) += 1;
+        if let Err(e) = self.repair_replication(client_id, filename) {
+            self.debug_log(&format!("{} of {} is under-replicated: {}", filename, client_id, e));
+        }
+    }
+
+    /// Time left on the longest-running live deal for a file, or `None` if no deal for it
+    /// is still within its term.
+    fn remaining_term(&self, client_id: &PeerId, filename: &str) -> Option<Duration> {
+        let now = self.now();
+        self.deals.values()
+            .filter(|d| d.is_live() && d.client_id == *client_id && d.filename == filename && d.term_ended_at(now).is_none())
+            .filter_map(|d| d.ends_at())
+            .max()
+            .map(|ends_at| ends_at.saturating_duration_since(now))
+    }
+
+    /// How long new replicas of a file are kept: until its current deals end, or for the
+    /// default term if none is live.
+    fn replication_term(&self, client_id: &PeerId, filename: &str) -> Duration {
+        self.remaining_term(client_id, filename)
+            .unwrap_or(DEFAULT_DEAL_DURATION)
+            .max(EPOCH_DURATION)
+    }
+
+    /// Retries every file in `under_replicated`. Returns how many replicas were placed.
+    pub fn repair_under_replicated(&mut self) -> usize {
+        let files: Vec<(PeerId, String)> = self.under_replicated.keys().cloned().collect();
+        files.into_iter()
+            .map(|(client_id, filename)| match self.repair_replication(&client_id, &filename) {
+                Ok(nodes) => nodes.len(),
+                Err(e) => {
+                    self.debug_log(&format!("Repair of {} for {} failed: {}", filename, client_id, e));
+                    0
+                }
+            })
+            .sum()
+    }
+
+    /// Places the replicas recorded as missing for a file on SPs that never lost it, each
+    /// under a deal that lasts as long as the file's longest remaining deal. Returns the
+    /// new SPs.
+    pub fn repair_replication(&mut self, client_id: &PeerId, filename: &str) -> Result<Vec<PeerId>, String> {
+        let key = (*client_id, filename.to_string());
+        let missing = self.under_replicated.get(&key).copied().unwrap_or(0);
+        if missing == 0 {
+            return Ok(Vec::new());
+        }
+        let duration = self.remaining_term(client_id, filename)
+            .ok_or_else(|| "No live deal is left to repair from".to_string())?
+            .max(EPOCH_DURATION);
+        let holders = self.get_file_locations(client_id, filename)?;
+        let data = holders.iter()
+            .find_map(|node_id| self.storage_nodes.get(node_id).and_then(|node| node.get_file(filename)))
+            .cloned()
+            .ok_or_else(|| "No SP still holds the file".to_string())?;
+
+        // SPs whose deal for this file already lapsed or was slashed are not asked again
+        let lost_by: Vec<PeerId> = self.deals_for_file(client_id, filename).iter()
+            .filter(|d| matches!(d.state(), DealState::Expired | DealState::Slashed))
+            .map(|d| d.storage_node_id)
+            .collect();
+        let candidates: Vec<PeerId> = UploadConstraints::default().eligible_nodes(&self.storage_nodes, filename, data.len(), 0)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|node_id| !holders.contains(node_id) && !lost_by.contains(node_id))
+            .collect();
+        let mut repaired = Vec::new();
+        for node_id in self.place(&candidates, &holders, missing, None) {
+            let cost = storage_cost(data.len(), epochs_in(duration), self.storage_nodes[&node_id].price_per_byte_epoch())?;
+            let escrow_id = match self.reserve_upload(client_id, &node_id, filename, data.len(), cost) {
+                Ok(escrow_id) => escrow_id,
+                Err(e) => {
+                    self.debug_log(&format!("Could not repair {} on {}: {}", filename, node_id, e));
+                    continue;
+                }
+            };
+            if let Err(e) = self.storage_nodes.get_mut(&node_id).unwrap().store_reserved_file(filename.to_string(), data.clone()) {
+                self.debug_log(&format!("Could not repair {} on {}: {}", filename, node_id, e));
+                self.rollback_upload(&[(node_id, escrow_id, cost)], data.len());
+                continue;
+            }
+            let deal_id = self.open_deal(DealTerms {
+                client_id: *client_id,
+                storage_node_id: node_id,
+                filename: filename.to_string(),
+                duration,
+                total_payment: cost,
+                commitment: content_digest(&data),
+            }, Some(escrow_id));
+            if let Some(client) = self.clients.get_mut(client_id) {
+                client.add_file_location(filename, node_id);
+            }
+            self.debug_log(&format!("Repaired {} of {} on {} under deal {}", filename, client_id, node_id, deal_id));
+            repaired.push(node_id);
+        }
+
+        let still_missing = missing - repaired.len();
+        if still_missing == 0 {
+            self.under_replicated.remove(&key);
+        } else {
+            self.under_replicated.insert(key, still_missing);
+        }
+        if repaired.is_empty() {
+            return Err(format!("No SP could take a replacement replica ({} still missing)", still_missing));
+        }
+        Ok(repaired)
+    }
+
+    /// Locks `amount` of an SP's own tokens as stake. Returns the SP's total stake.
+    pub fn stake(&mut self, storage_node_id: &PeerId, amount: TokenAmount) -> Result<TokenAmount, String> {
+        let storage_node = self.storage_nodes.get(storage_node_id).ok_or_else(|| "Storage node not found".to_string())?;
+        if storage_node.is_draining() {
+            return Err("Storage node is leaving the network".to_string());
+        }
+        let staked = match self.stakes.get(storage_node_id) {
+            Some(&escrow_id) => self.token.escrow_top_up(escrow_id, storage_node_id, amount),
+            None => self.token.escrow_deposit(storage_node_id, amount, Purpose::Stake)
+                .map(|escrow_id| { self.stakes.insert(*storage_node_id, escrow_id); }),
+        };
+        staked.map_err(|e| format!("Failed to stake: {}", e))?;
+        self.debug_log(&format!("{} staked {} tokens", storage_node_id, amount));
+        Ok(self.stake_of(storage_node_id))
+    }
+
+    pub fn stake_of(&self, storage_node_id: &PeerId) -> TokenAmount {
+        self.stakes.get(storage_node_id).map_or(TokenAmount::ZERO, |&escrow_id| self.token.escrow_balance(escrow_id))
+    }
+
+    /// Takes an SP out of the network without losing client data. The SP stops taking
+    /// new deals and its offers and bids are withdrawn; each live deal it holds moves to
+    /// another SP along with its unpaid escrow. The stake is only handed back once no
+    /// live deal is left on the SP, so a drain that could not place everything can be
+    /// called again, and the repair scan retries it too.
+    pub fn drain_storage_node(&mut self, storage_node_id: &PeerId) -> Result<DrainReport, String> {
+        let storage_node = self.storage_nodes.get_mut(storage_node_id).ok_or_else(|| "Storage node not found".to_string())?;
+        if !storage_node.is_draining() {
+            storage_node.set_draining(true);
+            let offer_ids: Vec<u64> = self.marketplace.offers().iter()
+                .filter(|offer| offer.storage_node_id == *storage_node_id)
+                .map(|offer| offer.id)
+                .collect();
+            for offer_id in offer_ids {
+                let _ = self.cancel_storage_offer(storage_node_id, offer_id);
+            }
+            for bids in self.bids.values_mut() {
+                bids.retain(|bid| bid.storage_node_id != *storage_node_id);
+            }
+            self.debug_log(&format!("Storage node {} is leaving the network", storage_node_id));
+        }
+
+        let mut report = DrainReport::default();
+        let live_deals: Vec<u64> = self.deals.values()
+            .filter(|deal| deal.is_live() && deal.storage_node_id == *storage_node_id)
+            .map(|deal| deal.id())
+            .collect();
+        for deal_id in live_deals {
+            match self.migrate_deal(deal_id) {
+                Ok(migration) => report.migrated.push(migration),
+                Err(e) => {
+                    self.debug_log(&format!("Could not move deal {} off {}: {}", deal_id, storage_node_id, e));
+                    report.pending.push(deal_id);
+                }
+            }
+        }
+
+        if report.is_complete() {
+            let released = match self.stakes.remove(storage_node_id) {
+                Some(escrow_id) => self.token.escrow_refund(escrow_id).map_err(|e| format!("Failed to release stake: {}", e))?,
+                None => TokenAmount::ZERO,
+            };
+            self.debug_log(&format!("Storage node {} has drained; released {} tokens of stake", storage_node_id, released));
+            report.stake_released = Some(released);
+        }
+        Ok(report)
+    }
+
+    /// Retries every drain that still has deals left to move. Returns how many moved.
+    pub fn resume_drains(&mut self) -> usize {
+        let draining: Vec<PeerId> = self.storage_nodes.values()
+            .filter(|node| node.is_draining())
+            .map(|node| *node.peer_id())
+            .filter(|node_id| self.stakes.contains_key(node_id) || self.deals.values().any(|d| d.is_live() && d.storage_node_id == *node_id))
+            .collect();
+        draining.iter()
+            .filter_map(|node_id| self.drain_storage_node(node_id).ok())
+            .map(|report| report.migrated.len())
+            .sum()
+    }
+
+    /// Moves a live deal to an SP picked by the default placement strategy.
+    fn migrate_deal(&mut self, deal_id: u64) -> Result<DealMigration, String> {
+        let deal = self.deals.get(&deal_id).ok_or_else(|| "Deal not found".to_string())?;
+        let filename = deal.filename.clone();
+        let holders = self.get_file_locations(&deal.client_id, &filename).unwrap_or_default();
+        let size = self.storage_nodes.get(&deal.storage_node_id).and_then(|node| node.get_file(&filename)).map_or(0, |data| data.len());
+        let candidates: Vec<PeerId> = UploadConstraints::default().eligible_nodes(&self.storage_nodes, &filename, size, 0)
+            .map_err(|e| e.to_string())?;
+        let target = self.place(&candidates, &holders, 1, None).pop()
+            .ok_or_else(|| "No other SP can take the replica".to_string())?;
+        self.move_deal(deal_id, &target)
+    }
+
+    /// Copies a live deal's data to `target` and opens a deal there for the rest of the
+    /// term, funded by the old deal's unpaid escrow. Any auto-renewal moves with it. The
+    /// old deal ends as `Migrated` and its SP drops the file, so the file keeps the same
+    /// number of replicas.
+    fn move_deal(&mut self, deal_id: u64, target: &PeerId) -> Result<DealMigration, String> {
+        let deal = self.deals.get(&deal_id).ok_or_else(|| "Deal not found".to_string())?.clone();
+        if !deal.is_live() {
+            return Err("Deal has already ended".to_string());
+        }
+        let data = self.get_file_content(&deal.storage_node_id, &deal.filename)?;
+        let holders = self.get_file_locations(&deal.client_id, &deal.filename).unwrap_or_default();
+        let target = *target;
+        let target_node = self.storage_nodes.get_mut(&target).ok_or_else(|| "Storage node not found".to_string())?;
+        if target == deal.storage_node_id || target_node.get_file(&deal.filename).is_some() {
+            return Err(format!("Storage node {} already holds {}", target, deal.filename));
+        }
+        if target_node.is_draining() {
+            return Err(format!("Storage node {} is leaving the network", target));
+        }
+        let size = data.len();
+        target_node.reserve_space(size).map_err(|e| format!("Failed to reserve space on node {}: {}", target, e))?;
+        if let Err(e) = target_node.store_reserved_file(deal.filename.clone(), data) {
+            target_node.release_space(size);
+            return Err(format!("Failed to store {} on node {}: {}", deal.filename, target, e));
+        }
+
+        let now = self.now();
+        let duration = deal.ends_at().map_or(Duration::ZERO, |ends_at| ends_at.saturating_duration_since(now)).max(EPOCH_DURATION);
+        let value = deal.escrow_id.map_or(TokenAmount::ZERO, |escrow_id| self.token.escrow_balance(escrow_id));
+        let epoch = self.current_epoch;
+        let old_deal = self.deals.get_mut(&deal_id).unwrap();
+        let escrow_id = old_deal.escrow_id.take();
+        let auto_renewal = old_deal.auto_renewal.take();
+        old_deal.transition(DealState::Migrated, epoch, now)?;
+        let new_deal_id = self.open_deal(DealTerms {
+            client_id: deal.client_id,
+            storage_node_id: target,
+            filename: deal.filename.clone(),
+            duration,
+            total_payment: value,
+            commitment: deal.commitment,
+        }, escrow_id);
+        let new_deal = self.deals.get_mut(&new_deal_id).unwrap();
+        // Paid out over the epochs the old deal had left, whatever the clock says
+        new_deal.epochs = deal.epochs.saturating_sub(deal.epochs_paid).max(1);
+        new_deal.auto_renewal = auto_renewal;
+
+        if let Some(storage_node) = self.storage_nodes.get_mut(&deal.storage_node_id) {
+            let _ = storage_node.remove_file(&deal.filename);
+        }
+        if let Some(client) = self.clients.get_mut(&deal.client_id) {
+            let locations: Vec<PeerId> = holders.iter().filter(|id| **id != deal.storage_node_id).cloned().chain([target]).collect();
+            client.add_file(deal.filename.clone(), locations);
+        }
+        self.debug_log(&format!("Moved deal {} for {} from {} to {} as deal {} with {} tokens", deal_id, deal.filename, deal.storage_node_id, target, new_deal_id, value));
+        Ok(DealMigration { from_deal: deal_id, to_deal: new_deal_id, storage_node_id: target, value })
+    }
+
+    pub fn set_rebalance_policy(&mut self, policy: RebalancePolicy) -> Result<(), String> {
+        self.rebalancer.set_policy(policy)
+    }
+
+    /// Moves replicas from the fullest SPs to the emptiest ones that do not hold the file
+    /// yet, within the policy's bandwidth budgets. Each replica is copied before its old
+    /// copy is dropped, and its deal moves with it, so files never lose a replica. Does
+    /// nothing unless the policy is enabled. Returns the moves made, which are also kept
+    /// in the rebalancer's history. A replica moves at most once per run.
+    pub fn rebalance(&mut self) -> Vec<ReplicaMove> {
+        let policy = self.rebalancer.policy().clone();
+        if !policy.enabled {
+            return Vec::new();
+        }
+        let mut moved_bytes = 0;
+        let mut node_traffic: HashMap<PeerId, usize> = HashMap::new();
+        // Deals that failed to move, and the deals of replicas already moved this run
+        let mut settled_deals = HashSet::new();
+        let mut moves = Vec::new();
+        while let Some((deal_id, target, bytes)) = self.next_rebalance_move(&policy, moved_bytes, &node_traffic, &settled_deals) {
+            let from = self.deals[&deal_id].storage_node_id;
+            match self.move_deal(deal_id, &target) {
+                Ok(migration) => {
+                    moved_bytes += bytes;
+                    settled_deals.insert(migration.to_deal);
+                    *node_traffic.entry(from).or_insert(0) += bytes;
+                    *node_traffic.entry(target).or_insert(0) += bytes;
+                    let deal = &self.deals[&deal_id];
+                    let replica_move = ReplicaMove {
+                        client_id: deal.client_id,
+                        filename: deal.filename.clone(),
+                        from,
+                        to: target,
+                        from_deal: deal_id,
+                        to_deal: migration.to_deal,
+                        bytes,
+                        epoch: self.current_epoch,
+                    };
+                    self.debug_log(&format!("Rebalanced {} ({} bytes) from {} to {}", replica_move.filename, bytes, from, target));
+                    self.rebalancer.record(replica_move.clone());
+                    moves.push(replica_move);
+                }
+                Err(e) => {
+                    self.debug_log(&format!("Could not rebalance deal {}: {}", deal_id, e));
+                    settled_deals.insert(deal_id);
+                }
+            }
+        }
+        moves
+    }
+
+    /// The best replica to move next: a live deal on the fullest SP that still has
+    /// budget, going to the emptiest SP the move is worth making for.
+    fn next_rebalance_move(&self, policy: &RebalancePolicy, moved_bytes: usize, node_traffic: &HashMap<PeerId, usize>, settled_deals: &HashSet<u64>) -> Option<(u64, PeerId, usize)> {
+        let traffic_left = |node_id: &PeerId| policy.max_bytes_per_node.saturating_sub(node_traffic.get(node_id).copied().unwrap_or(0));
+        let utilisation = |node: &StorageNode| (node.used_space() as f64 / node.total_space() as f64, *node.peer_id());
+        let mut nodes: Vec<&StorageNode> = self.storage_nodes.values().collect();
+        nodes.sort_by(|a, b| utilisation(a).partial_cmp(&utilisation(b)).unwrap());
+
+        for source in nodes.iter().rev() {
+            let deals = self.deals.values()
+                .filter(|deal| deal.is_live() && deal.storage_node_id == *source.peer_id() && !settled_deals.contains(&deal.id()));
+            for deal in deals {
+                let Some(bytes) = source.get_file(&deal.filename).map(|data| data.len()) else { continue };
+                if moved_bytes + bytes > policy.max_bytes_per_run || bytes > traffic_left(source.peer_id()) {
+                    continue;
+                }
+                let target = nodes.iter().find(|target| {
+                    !target.is_draining()
+                        && target.get_file(&deal.filename).is_none()
+                        && target.available_space() >= bytes
+                        && bytes <= traffic_left(target.peer_id())
+                        && policy.worth_moving(source, target, bytes)
+                });
+                if let Some(target) = target {
+                    return Some((deal.id(), *target.peer_id(), bytes));
+                }
+            }
+        }
+        None
+    }
+
+    /// Chain-replicates a file through its first SP. The client approves that SP to spend
+    /// enough of its tokens to pay every downstream SP, and the SP funds each one's deal
+    /// escrow as it confirms storage. Every payment is recorded in `replication_payments`.
     pub fn replicate_file(&mut self, client_id: &PeerId, filename: &str, remaining_replications: usize) -> Result<(), String> {
         let client = self.clients.get(client_id).ok_or("Client not found".to_string())?;
         let storage_nodes = client.get_file_locations(filename).ok_or("File not found".to_string())?;
@@ -464,48 +1735,433 @@ impl Network {
             source_node.get_file(filename).ok_or("File not found on source node".to_string())?.clone()
         };
 
-        self.chain_upload(&source_node_id, filename, &file_data, remaining_replications)
-            .map_err(|e| e.to_string())
+        // Budget for the most expensive SPs the chain could pick, for as long as the file is kept
+        let duration = self.replication_term(client_id, filename);
+        let max_price = self.storage_nodes.values()
+            .filter(|node| node.get_file(filename).is_none())
+            .map(|node| node.price_per_byte_epoch())
+            .max()
+            .unwrap_or_default();
+        let budget = storage_cost(file_data.len(), epochs_in(duration), max_price)?
+            .checked_mul(remaining_replications as u128)
+            .ok_or_else(|| "Replication budget overflows the token supply".to_string())?;
+        let previous_allowance = self.token.allowance(client_id, &source_node_id);
+        let allowance = previous_allowance.checked_add(budget).ok_or_else(|| "Replication budget overflows the token supply".to_string())?;
+        self.token.approve(client_id, &source_node_id, allowance).map_err(|e| e.to_string())?;
+        self.debug_log(&format!("{} approved {} to spend {} tokens on replicating {}", client_id, source_node_id, budget, filename));
+
+        let mut stored_nodes = Vec::new();
+        let chain = ChainUpload { client_id, payer_id: &source_node_id, filename, data: &file_data, duration };
+        let result = self.chain_upload(&chain, &source_node_id, remaining_replications, &mut stored_nodes);
+
+        // Whatever the chain did not spend is no longer available to the SP
+        self.token.approve(client_id, &source_node_id, previous_allowance).map_err(|e| e.to_string())?;
+
+        if let Some(client) = self.clients.get_mut(client_id) {
+            let mut locations = client.get_file_locations(filename).cloned().unwrap_or_default();
+            locations.extend(stored_nodes);
+            client.add_file(filename.to_string(), locations);
+        }
+
+        result.map_err(|e| e.to_string())
     }
 
-    pub fn add_storage_offer(&mut self, storage_node_id: PeerId, price_per_gb: u64, available_space: usize) {
-        let offer = StorageOffer {
-            storage_node_id,
-            price_per_gb,
-            available_space,
-        };
-        self.marketplace.push_back(offer);
+    /// The money trail of a file's chain replication: who paid whom, out of whose funds.
+    pub fn get_replication_payments(&self, client_id: &PeerId, filename: &str) -> Vec<&ReplicationPayment> {
+        self.replication_payments.iter()
+            .filter(|payment| payment.client_id == *client_id && payment.filename == filename)
+            .collect()
+    }
+
+    /// Locks `capacity` tokens from `payer` and opens a payment channel to `payee`.
+    /// The returned signer stays with the payer and is used to issue vouchers.
+    pub fn open_payment_channel(&mut self, payer: &PeerId, payee: &PeerId, capacity: TokenAmount, purpose: Purpose) -> Result<ChannelSigner, String> {
+        let channel_id = self.token.escrow_deposit(payer, capacity, purpose)
+            .map_err(|e| format!("Failed to fund payment channel: {}", e))?;
+        let signer = ChannelSigner::new(channel_id);
+        self.payment_channels.insert(channel_id, PaymentChannel::new(channel_id, *payer, *payee, capacity, signer.public_key()));
+        self.debug_log(&format!("Opened payment channel {} from {} to {} with capacity {}", channel_id, payer, payee, capacity));
+        Ok(signer)
+    }
+
+    /// Hands a voucher to the channel's payee. Returns how much it added to the amount owed.
+    pub fn submit_voucher(&mut self, voucher: Voucher) -> Result<TokenAmount, String> {
+        let channel = self.payment_channels.get_mut(&voucher.channel_id).ok_or_else(|| "Payment channel not found".to_string())?;
+        channel.accept_voucher(voucher).map_err(|e| e.to_string())
+    }
+
+    /// Closes a channel on the ledger: the payee receives the latest voucher's amount
+    /// and the rest of the collateral returns to the payer. Returns the amount paid.
+    pub fn settle_payment_channel(&mut self, channel_id: u64) -> Result<TokenAmount, String> {
+        let channel = self.payment_channels.remove(&channel_id).ok_or_else(|| "Payment channel not found".to_string())?;
+        let amount = channel.redeemable();
+        self.token.escrow_release(channel_id, channel.payee(), amount)
+            .map_err(|e| format!("Failed to release channel funds: {}", e))?;
+        if let Some(storage_node) = self.storage_nodes.get_mut(channel.payee()) {
+            storage_node.record_storage_earnings(amount);
+        }
+        let refund = self.token.escrow_refund(channel_id).map_err(|e| e.to_string())?;
+        self.debug_log(&format!("Settled payment channel {}: {} tokens to {}, {} refunded to {}", channel_id, amount, channel.payee(), refund, channel.payer()));
+        Ok(amount)
+    }
+
+    /// Closes a channel into the escrow of a deal: the latest voucher's amount stays locked
+    /// under the channel's id, to be paid out per epoch, and the rest of the collateral
+    /// returns to the payer. Returns the amount kept.
+    fn lock_payment_channel(&mut self, channel_id: u64) -> Result<TokenAmount, String> {
+        let channel = self.payment_channels.remove(&channel_id).ok_or_else(|| "Payment channel not found".to_string())?;
+        let amount = channel.redeemable();
+        let surplus = self.token.escrow_balance(channel_id).saturating_sub(amount);
+        self.token.escrow_return(channel_id, surplus).map_err(|e| format!("Failed to return channel funds: {}", e))?;
+        self.debug_log(&format!("Locked payment channel {}: {} tokens kept for {}, {} returned to {}", channel_id, amount, channel.payee(), surplus, channel.payer()));
+        Ok(amount)
+    }
+
+    /// Starts a pay-as-you-go upload to `replication_factor` SPs. Each SP gets a channel
+    /// funded with at most `budget_per_node`; the client only spends what is streamed.
+    pub fn open_upload_stream(&mut self, client_id: &PeerId, filename: String, replication_factor: usize, budget_per_node: TokenAmount) -> Result<u64, String> {
+        self.open_upload_stream_for(client_id, filename, replication_factor, budget_per_node, DEFAULT_DEAL_DURATION)
+    }
+
+    /// Starts an upload stream whose deals run for `duration` instead of the default.
+    pub fn open_upload_stream_for(&mut self, client_id: &PeerId, filename: String, replication_factor: usize, budget_per_node: TokenAmount, duration: Duration) -> Result<u64, String> {
+        check_deal_duration(duration)?;
+        let available_nodes: Vec<PeerId> = self.storage_nodes.iter()
+            .filter(|(_, node)| !node.is_draining() && node.get_file(&filename).is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        if available_nodes.len() < replication_factor {
+            return Err(format!("Not enough storage nodes available. Required: {}, Available: {}", replication_factor, available_nodes.len()));
+        }
+        let targets = self.place(&available_nodes, &[], replication_factor, None);
+        self.open_stream_to(client_id, filename, targets, budget_per_node, duration, Purpose::Upload)
+    }
+
+    fn open_stream_to(&mut self, client_id: &PeerId, filename: String, targets: Vec<PeerId>, budget_per_node: TokenAmount, duration: Duration, purpose: Purpose) -> Result<u64, String> {
+        if !self.clients.contains_key(client_id) {
+            return Err("Client not found".to_string());
+        }
+        let required = budget_per_node.checked_mul(targets.len() as u128)
+            .ok_or_else(|| "Upload stream budget overflows the token supply".to_string())?;
+        let client_balance = self.token.balance_of(client_id);
+        if client_balance < required {
+            return Err(format!("Insufficient balance to open upload stream. Required: {}, Available: {}", required, client_balance));
+        }
+
+        let mut signers = Vec::new();
+        for node_id in &targets {
+            signers.push(self.open_payment_channel(client_id, node_id, budget_per_node, purpose)?);
+        }
+
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+        self.debug_log(&format!("Opened upload stream {} for {} through chain {:?}", stream_id, filename, targets));
+        self.upload_streams.insert(stream_id, UploadStream {
+            client_id: *client_id,
+            filename,
+            targets,
+            signers,
+            bytes_stored: 0,
+            duration,
+        });
+        Ok(stream_id)
+    }
+
+    /// Pushes the next chunk down the chain and pays every SP for the bytes it now holds.
+    /// The chunk is rejected as a whole if any SP is leaving, lacks space or its channel
+    /// lacks funds, or if the first chunk would land on a file of the same name: every SP
+    /// is checked before any of them stores it.
+    pub fn stream_chunk(&mut self, stream_id: u64, chunk: &[u8]) -> Result<(), String> {
+        let stream = self.upload_streams.get(&stream_id).ok_or_else(|| "Upload stream not found".to_string())?;
+        if chunk.is_empty() {
+            return Ok(());
+        }
+        let new_total = stream.bytes_stored + chunk.len();
+        let first_chunk = stream.bytes_stored == 0;
+
+        let mut payments = Vec::new();
+        for (node_id, signer) in stream.targets.iter().zip(&stream.signers) {
+            let storage_node = self.storage_nodes.get(node_id).ok_or_else(|| format!("Storage node {} not found", node_id))?;
+            if storage_node.is_draining() {
+                return Err(format!("Storage node {} is leaving the network", node_id));
+            }
+            if first_chunk && storage_node.get_file(&stream.filename).is_some() {
+                return Err(format!("Storage node {} already holds a file named {}", node_id, stream.filename));
+            }
+            if storage_node.available_space() < chunk.len() {
+                return Err(format!("Storage node {} has no space for the next chunk", node_id));
+            }
+            let owed = storage_cost(new_total, epochs_in(stream.duration), storage_node.price_per_byte_epoch())?;
+            let channel = self.payment_channels.get(&signer.channel_id()).ok_or_else(|| format!("Payment channel to {} not found", node_id))?;
+            if owed > channel.capacity() {
+                return Err(format!("Payment channel to {} exhausted: {} owed, {} available", node_id, owed, channel.capacity()));
+            }
+            // A price cut since the last chunk does not claw back what was already paid
+            payments.push(owed.saturating_sub(signer.cumulative_amount()));
+        }
+
+        // Vouchers are signed before anything is stored, so a failure leaves the SPs untouched
+        let stream = self.upload_streams.get_mut(&stream_id).unwrap();
+        let vouchers = stream.signers.iter_mut().zip(payments)
+            .map(|(signer, payment)| signer.pay(payment))
+            .collect::<Result<Vec<Voucher>, _>>()?;
+        for node_id in &stream.targets {
+            let storage_node = self.storage_nodes.get_mut(node_id).unwrap();
+            if first_chunk {
+                storage_node.store_file(stream.filename.clone(), chunk.to_vec())?;
+            } else {
+                storage_node.append_file_chunk(&stream.filename, chunk)?;
+            }
+        }
+        stream.bytes_stored = new_total;
+
+        for voucher in vouchers {
+            self.submit_voucher(voucher)?;
+        }
+        self.debug_log(&format!("Upload stream {} stored {} bytes on each SP", stream_id, new_total));
+        Ok(())
     }
 
-    pub fn get_storage_offers(&self) -> &VecDeque<StorageOffer> {
-        &self.marketplace
+    /// Completes a stream: each channel becomes the escrow of a deal for what was streamed,
+    /// paid out per epoch like any other deal, and the SPs are added to the client's file
+    /// locations.
+    pub fn finish_upload_stream(&mut self, stream_id: u64) -> Result<Vec<PeerId>, String> {
+        let stream = self.upload_streams.remove(&stream_id).ok_or_else(|| "Upload stream not found".to_string())?;
+
+        for (node_id, signer) in stream.targets.iter().zip(&stream.signers) {
+            let paid = self.lock_payment_channel(signer.channel_id())?;
+            let commitment = content_digest(self.storage_nodes.get(node_id)
+                .and_then(|node| node.get_file(&stream.filename))
+                .map_or(&[][..], |data| data.as_slice()));
+            self.open_deal(DealTerms {
+                client_id: stream.client_id,
+                storage_node_id: *node_id,
+                filename: stream.filename.clone(),
+                duration: stream.duration,
+                total_payment: paid,
+                commitment,
+            }, Some(signer.channel_id()));
+        }
+
+        let client = self.clients.get_mut(&stream.client_id).ok_or_else(|| "Client not found".to_string())?;
+        let mut locations = client.get_file_locations(&stream.filename).cloned().unwrap_or_default();
+        locations.extend(stream.targets.iter().cloned());
+        client.add_file(stream.filename.clone(), locations);
+        self.debug_log(&format!("Finished upload stream {} for {}", stream_id, stream.filename));
+        Ok(stream.targets)
     }
 
-    pub fn accept_storage_offer(&mut self, client_id: &PeerId, offer_index: usize, file_size: usize) -> Result<(), &'static str> {
-        let offer = self.marketplace.get(offer_index).cloned().ok_or("Offer not found")?;
-        let storage_node = self.storage_nodes.get_mut(&offer.storage_node_id).ok_or("Storage node not found")?;
+    /// Stops a stream part way. SPs are paid for what they stored, the partial copies are
+    /// dropped and the unspent collateral returns to the client. Returns the total paid.
+    pub fn abort_upload_stream(&mut self, stream_id: u64) -> Result<TokenAmount, String> {
+        let stream = self.upload_streams.remove(&stream_id).ok_or_else(|| "Upload stream not found".to_string())?;
+
+        let mut total_paid = TokenAmount::ZERO;
+        for (node_id, signer) in stream.targets.iter().zip(&stream.signers) {
+            let paid = self.settle_payment_channel(signer.channel_id())?;
+            total_paid = total_paid.checked_add(paid).ok_or_else(|| "Upload stream payments overflow the token supply".to_string())?;
+            // Before the first chunk the SP holds nothing of this stream's, so whatever it
+            // holds under the name is not ours to drop
+            if stream.bytes_stored == 0 {
+                continue;
+            }
+            if let Some(storage_node) = self.storage_nodes.get_mut(node_id) {
+                let _ = storage_node.remove_file(&stream.filename);
+            }
+        }
+        self.debug_log(&format!("Aborted upload stream {} after {} bytes, paid {} tokens", stream_id, stream.bytes_stored, total_paid));
+        Ok(total_paid)
+    }
 
-        if file_size > offer.available_space {
-            return Err("Not enough space in the offer");
+    /// Pay-as-you-go counterpart of `upload_file`: streams `data` in `chunk_size` pieces.
+    pub fn upload_file_streaming(&mut self, client_id: &PeerId, filename: String, data: Vec<u8>, replication_factor: usize, chunk_size: usize, budget_per_node: TokenAmount) -> Result<Vec<PeerId>, String> {
+        let stream_id = self.open_upload_stream(client_id, filename, replication_factor, budget_per_node)?;
+        for chunk in data.chunks(chunk_size.max(1)) {
+            if let Err(e) = self.stream_chunk(stream_id, chunk) {
+                self.abort_upload_stream(stream_id)?;
+                return Err(e);
+            }
+        }
+        self.finish_upload_stream(stream_id)
+    }
+
+    /// Pay-as-you-go counterpart of `replicate_file`: chain-uploads an existing file from
+    /// one of its current SPs to `remaining_replications` new SPs, streaming chunk by chunk.
+    pub fn replicate_file_streaming(&mut self, client_id: &PeerId, filename: &str, remaining_replications: usize, chunk_size: usize, budget_per_node: TokenAmount) -> Result<Vec<PeerId>, String> {
+        let current_locations = self.get_file_locations(client_id, filename)?;
+        let source_node_id = *current_locations.first().ok_or_else(|| "No storage nodes found for the file".to_string())?;
+        let file_data = self.get_file_content(&source_node_id, filename)?;
+
+        let available_nodes: Vec<PeerId> = self.storage_nodes.iter()
+            .filter(|(id, node)| !current_locations.contains(id) && !node.is_draining() && node.get_file(filename).is_none())
+            .map(|(id, _)| *id)
+            .collect();
+        if available_nodes.len() < remaining_replications {
+            return Err(format!("Not enough additional storage nodes available. Required: {}, Available: {}", remaining_replications, available_nodes.len()));
+        }
+        let targets = self.place(&available_nodes, &current_locations, remaining_replications, None);
+
+        let duration = self.replication_term(client_id, filename);
+        let stream_id = self.open_stream_to(client_id, filename.to_string(), targets, budget_per_node, duration, Purpose::Replication)?;
+        for chunk in file_data.chunks(chunk_size.max(1)) {
+            if let Err(e) = self.stream_chunk(stream_id, chunk) {
+                self.abort_upload_stream(stream_id)?;
+                return Err(e);
+            }
+        }
+        self.finish_upload_stream(stream_id)
+    }
+
+    /// Posts an SP's offer of `available_space` bytes at a fixed price, open for
+    /// `lifetime_epochs` epochs. Returns the offer id.
+    pub fn add_storage_offer(&mut self, storage_node_id: PeerId, price_per_byte_epoch: TokenAmount, available_space: usize, lifetime_epochs: u64) -> Result<u64, String> {
+        let storage_node = self.storage_nodes.get(&storage_node_id).ok_or("Storage node not found")?;
+        if storage_node.is_draining() {
+            return Err("Storage node is leaving the network".to_string());
         }
+        if available_space == 0 || available_space > storage_node.available_space() {
+            return Err(format!("Cannot offer {} bytes, storage node has {} available", available_space, storage_node.available_space()));
+        }
+        if lifetime_epochs == 0 {
+            return Err("Offer must stay open for at least one epoch".to_string());
+        }
+        let offer_id = self.marketplace.post(storage_node_id, price_per_byte_epoch, available_space, self.current_epoch + lifetime_epochs);
+        self.debug_log(&format!("Storage node {} posted offer {}: {} bytes at {} per byte-epoch", storage_node_id, offer_id, available_space, price_per_byte_epoch));
+        Ok(offer_id)
+    }
 
-        let price = (file_size as u64 * offer.price_per_gb) / (1024 * 1024 * 1024); // Convert to GB
-        if !self.token.transfer(client_id, &offer.storage_node_id, price) {
-            return Err("Client doesn't have enough balance");
+    /// Open offers, cheapest first.
+    pub fn get_storage_offers(&self) -> Vec<&StorageOffer> {
+        self.marketplace.offers()
+    }
+
+    /// Stores `data` with the SP behind an offer at the offer's price. Space is reserved on
+    /// the SP and the payment escrowed exactly as for `upload_file`, and the offer shrinks
+    /// by the file size.
+    pub fn accept_storage_offer(&mut self, client_id: &PeerId, offer_id: u64, filename: String, data: Vec<u8>) -> Result<(), String> {
+        self.accept_storage_offer_for(client_id, offer_id, filename, data, DEFAULT_DEAL_DURATION)
+    }
+
+    /// Takes up an offer under a deal that runs for `duration` instead of the default.
+    pub fn accept_storage_offer_for(&mut self, client_id: &PeerId, offer_id: u64, filename: String, data: Vec<u8>, duration: Duration) -> Result<(), String> {
+        check_deal_duration(duration)?;
+        if !self.clients.contains_key(client_id) {
+            return Err("Client not found".to_string());
+        }
+        let offer = self.marketplace.get(offer_id).cloned().ok_or("Offer not found")?;
+        if offer.is_expired_at(self.current_epoch) {
+            return Err(format!("Offer {} expired at epoch {}", offer_id, offer.expires_at_epoch));
+        }
+        if data.len() > offer.available_space {
+            return Err(format!("Not enough space in the offer. Required: {}, Available: {}", data.len(), offer.available_space));
         }
 
-        storage_node.reserve_space(file_size)?;
+        let cost = storage_cost(data.len(), epochs_in(duration), offer.price_per_byte_epoch)?;
+        let escrow_id = self.reserve_upload(client_id, &offer.storage_node_id, &filename, data.len(), cost)?;
+        if let Err(e) = self.storage_nodes.get_mut(&offer.storage_node_id).unwrap().store_reserved_file(filename.clone(), data.clone()) {
+            self.rollback_upload(&[(offer.storage_node_id, escrow_id, cost)], data.len());
+            return Err(e.to_string());
+        }
+        if let Err(e) = self.marketplace.fill(offer_id, data.len()) {
+            // Dropping the file gives its space back, so only the escrow is left to undo
+            let _ = self.storage_nodes.get_mut(&offer.storage_node_id).unwrap().remove_file(&filename);
+            let _ = self.token.escrow_refund(escrow_id);
+            return Err(e.to_string());
+        }
 
-        // Remove the offer and add a new one with updated available space
-        self.marketplace.remove(offer_index);
-        if offer.available_space > file_size {
-            self.add_storage_offer(offer.storage_node_id, offer.price_per_gb, offer.available_space - file_size);
+        self.open_deal(DealTerms {
+            client_id: *client_id,
+            storage_node_id: offer.storage_node_id,
+            filename: filename.clone(),
+            duration,
+            total_payment: cost,
+            commitment: content_digest(&data),
+        }, Some(escrow_id));
+        if let Some(client) = self.clients.get_mut(client_id) {
+            client.add_file_location(&filename, offer.storage_node_id);
         }
+        self.debug_log(&format!("Client {} took {} bytes of offer {} from {} for file {}", client_id, data.len(), offer_id, offer.storage_node_id, filename));
+        Ok(())
+    }
+
+    pub fn cancel_storage_offer(&mut self, storage_node_id: &PeerId, offer_id: u64) -> Result<(), &'static str> {
+        self.marketplace.cancel(storage_node_id, offer_id)?;
+        self.debug_log(&format!("Storage node {} cancelled offer {}", storage_node_id, offer_id));
+        Ok(())
+    }
 
+    pub fn update_storage_offer_price(&mut self, storage_node_id: &PeerId, offer_id: u64, price_per_byte_epoch: TokenAmount) -> Result<(), &'static str> {
+        self.marketplace.update_price(storage_node_id, offer_id, price_per_byte_epoch)?;
+        self.debug_log(&format!("Storage node {} repriced offer {} to {} per byte-epoch", storage_node_id, offer_id, price_per_byte_epoch));
         Ok(())
     }
+
+    /// Opts an SP into automatic repricing within `policy`, or out of it with `None`.
+    pub fn set_pricing_policy(&mut self, storage_node_id: &PeerId, policy: Option<PricingPolicy>) -> Result<(), String> {
+        let storage_node = self.storage_nodes.get_mut(storage_node_id).ok_or_else(|| "Storage node not found".to_string())?;
+        if let Some(policy) = &policy {
+            policy.validate()?;
+        }
+        storage_node.set_pricing_policy(policy);
+        Ok(())
+    }
+
+    /// Reprices every SP with a pricing policy from its utilisation, the cheaper supply in
+    /// the order book and the auctions it won or lost since the last run. Returns the
+    /// changes made.
+    pub fn adjust_prices(&mut self) -> Vec<PriceChange> {
+        let mut auction_results: HashMap<PeerId, (usize, usize)> = HashMap::new();
+        for notice in self.auction_notices.iter().skip(self.pricing.notices_seen()) {
+            let results = auction_results.entry(notice.storage_node_id).or_default();
+            match notice.outcome {
+                BidOutcome::Won { .. } => results.0 += 1,
+                BidOutcome::Lost { .. } => results.1 += 1,
+            }
+        }
+        self.pricing.mark_notices_seen(self.auction_notices.len());
+        let offers = self.marketplace.offers();
+        let offered_space: usize = offers.iter().map(|offer| offer.available_space).sum();
+
+        let mut changes = Vec::new();
+        for (storage_node_id, storage_node) in &self.storage_nodes {
+            let Some(policy) = storage_node.pricing_policy() else { continue };
+            let price = storage_node.price_per_byte_epoch();
+            let cheaper_space: usize = offers.iter()
+                .filter(|offer| offer.storage_node_id != *storage_node_id && offer.price_per_byte_epoch < price)
+                .map(|offer| offer.available_space)
+                .sum();
+            let (auctions_won, auctions_lost) = auction_results.get(storage_node_id).copied().unwrap_or_default();
+            let signals = MarketSignals {
+                utilisation: storage_node.used_space() as f64 / storage_node.total_space() as f64,
+                cheaper_supply: if offered_space == 0 { 0.0 } else { cheaper_space as f64 / offered_space as f64 },
+                auctions_won,
+                auctions_lost,
+            };
+            let new_price = self.pricing.next_price(policy, price, &signals);
+            if new_price != price {
+                changes.push(PriceChange {
+                    storage_node_id: *storage_node_id,
+                    epoch: self.current_epoch,
+                    old_price: price,
+                    new_price,
+                    reason: format!("utilisation {:.1}%, {:.1}% of offered space cheaper, {} auctions won, {} lost",
+                        signals.utilisation * 100.0, signals.cheaper_supply * 100.0, auctions_won, auctions_lost),
+                });
+            }
+        }
+
+        for change in &changes {
+            if let Some(storage_node) = self.storage_nodes.get_mut(&change.storage_node_id) {
+                storage_node.set_price_per_byte_epoch(change.new_price);
+            }
+            self.pricing.record(change.clone());
+            self.debug_log(&format!("Repriced {} from {} to {} per byte-epoch ({})", change.storage_node_id, change.old_price, change.new_price, change.reason));
+        }
+        changes
+    }
 }
 #[derive(libp2p::swarm::NetworkBehaviour)]
-struct NetworkBehaviourImpl {
-    kademlia: kad::Behaviour<kad::store::MemoryStore>,
+pub struct NetworkBehaviourImpl {
+    pub kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    pub storage: request_response::Behaviour<StorageCodec>,
 }
@@ -0,0 +1,139 @@
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use crate::token_amount::TokenAmount;
+
+/// A signed promise from the payer that the payee may claim `cumulative_amount`
+/// tokens from the channel. Vouchers are exchanged off-ledger; only the latest one
+/// is ever settled on the `ERC20` ledger.
+#[derive(Clone, Debug)]
+pub struct Voucher {
+    pub channel_id: u64,
+    pub nonce: u64,
+    pub cumulative_amount: TokenAmount,
+    pub signature: Vec<u8>,
+}
+
+impl Voucher {
+    fn signing_bytes(channel_id: u64, nonce: u64, cumulative_amount: TokenAmount) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(32);
+        bytes.extend_from_slice(&channel_id.to_be_bytes());
+        bytes.extend_from_slice(&nonce.to_be_bytes());
+        bytes.extend_from_slice(&cumulative_amount.base_units().to_be_bytes());
+        bytes
+    }
+}
+
+/// The payer's half of a channel. Holds the signing key and produces ever-increasing vouchers.
+pub struct ChannelSigner {
+    channel_id: u64,
+    keypair: Keypair,
+    nonce: u64,
+    cumulative_amount: TokenAmount,
+}
+
+impl ChannelSigner {
+    pub fn new(channel_id: u64) -> Self {
+        Self {
+            channel_id,
+            keypair: Keypair::generate_ed25519(),
+            nonce: 0,
+            cumulative_amount: TokenAmount::ZERO,
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.keypair.public()
+    }
+
+    pub fn channel_id(&self) -> u64 {
+        self.channel_id
+    }
+
+    pub fn cumulative_amount(&self) -> TokenAmount {
+        self.cumulative_amount
+    }
+
+    /// Signs a voucher covering everything paid so far plus `amount`.
+    pub fn pay(&mut self, amount: TokenAmount) -> Result<Voucher, &'static str> {
+        let cumulative_amount = self.cumulative_amount.checked_add(amount).ok_or("Voucher amount overflow")?;
+        let nonce = self.nonce + 1;
+        let signature = self.keypair
+            .sign(&Voucher::signing_bytes(self.channel_id, nonce, cumulative_amount))
+            .map_err(|_| "Failed to sign voucher")?;
+        self.nonce = nonce;
+        self.cumulative_amount = cumulative_amount;
+        Ok(Voucher {
+            channel_id: self.channel_id,
+            nonce,
+            cumulative_amount,
+            signature,
+        })
+    }
+}
+
+/// The payee's half of a channel. Collateral is locked in an `ERC20` escrow whose id
+/// doubles as the channel id.
+pub struct PaymentChannel {
+    id: u64,
+    payer: PeerId,
+    payee: PeerId,
+    capacity: TokenAmount,
+    payer_key: PublicKey,
+    latest_voucher: Option<Voucher>,
+}
+
+impl PaymentChannel {
+    pub fn new(id: u64, payer: PeerId, payee: PeerId, capacity: TokenAmount, payer_key: PublicKey) -> Self {
+        Self {
+            id,
+            payer,
+            payee,
+            capacity,
+            payer_key,
+            latest_voucher: None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn payer(&self) -> &PeerId {
+        &self.payer
+    }
+
+    pub fn payee(&self) -> &PeerId {
+        &self.payee
+    }
+
+    pub fn capacity(&self) -> TokenAmount {
+        self.capacity
+    }
+
+    /// Amount the payee can currently claim on settlement.
+    pub fn redeemable(&self) -> TokenAmount {
+        self.latest_voucher.as_ref().map_or(TokenAmount::ZERO, |voucher| voucher.cumulative_amount)
+    }
+
+    /// Verifies and records a voucher. Returns the increase over the previous voucher.
+    pub fn accept_voucher(&mut self, voucher: Voucher) -> Result<TokenAmount, &'static str> {
+        if voucher.channel_id != self.id {
+            return Err("Voucher is for a different channel");
+        }
+        let (last_nonce, last_amount) = self.latest_voucher.as_ref()
+            .map_or((0, TokenAmount::ZERO), |last| (last.nonce, last.cumulative_amount));
+        if voucher.nonce <= last_nonce || voucher.cumulative_amount < last_amount {
+            return Err("Stale voucher");
+        }
+        if voucher.cumulative_amount > self.capacity {
+            return Err("Voucher exceeds channel capacity");
+        }
+        let message = Voucher::signing_bytes(voucher.channel_id, voucher.nonce, voucher.cumulative_amount);
+        if !self.payer_key.verify(&message, &voucher.signature) {
+            return Err("Invalid voucher signature");
+        }
+        let increase = voucher.cumulative_amount - last_amount;
+        self.latest_voucher = Some(voucher);
+        Ok(increase)
+    }
+}
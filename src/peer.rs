@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use libp2p::{
+    core::{transport::MemoryTransport, upgrade},
+    futures::StreamExt,
+    identity,
+    multiaddr::Protocol,
+    noise,
+    request_response::{self, OutboundRequestId, ResponseChannel},
+    swarm::{dial_opts::DialOpts, NetworkBehaviour, Swarm, SwarmEvent},
+    yamux, Multiaddr, PeerId, SwarmBuilder, Transport,
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use crate::protocol::{self, BlockRequest, BlockResponse, ErrorCode, StorageCodec, MAX_BLOCK_SIZE};
+use crate::{Client, StorageNode, TokenAmount};
+
+#[derive(NetworkBehaviour)]
+pub struct PeerBehaviour {
+    storage: request_response::Behaviour<StorageCodec>,
+}
+
+/// What a peer is in the network, and the state it serves requests from.
+#[derive(Clone)]
+pub enum PeerRole {
+    Storage(Arc<Mutex<StorageNode>>),
+    Client(Arc<Mutex<Client>>),
+}
+
+enum Command {
+    Connect(PeerId, Multiaddr, oneshot::Sender<Result<(), String>>),
+    Request(PeerId, BlockRequest, oneshot::Sender<Result<BlockResponse, String>>),
+}
+
+/// A storage node or client running as its own libp2p peer, with its own identity and
+/// swarm, in a background task. Dropping the handle stops the peer.
+pub struct PeerHandle {
+    peer_id: PeerId,
+    address: Multiaddr,
+    role: PeerRole,
+    commands: mpsc::UnboundedSender<Command>,
+    task: JoinHandle<()>,
+}
+
+impl PeerHandle {
+    /// Starts a storage node peer listening on an in-memory address.
+    pub fn spawn_storage_node(keypair: identity::Keypair, price_per_byte_epoch: TokenAmount) -> Result<Self, String> {
+        let storage_node = StorageNode::new(keypair.public().to_peer_id(), price_per_byte_epoch);
+        Self::spawn(keypair, PeerRole::Storage(Arc::new(Mutex::new(storage_node))))
+    }
+
+    /// Starts a client peer listening on an in-memory address.
+    pub fn spawn_client(keypair: identity::Keypair) -> Result<Self, String> {
+        let client = Client::new(keypair.public().to_peer_id());
+        Self::spawn(keypair, PeerRole::Client(Arc::new(Mutex::new(client))))
+    }
+
+    fn spawn(keypair: identity::Keypair, role: PeerRole) -> Result<Self, String> {
+        let peer_id = keypair.public().to_peer_id();
+        let mut swarm = build_swarm(keypair)?;
+        let address = Multiaddr::empty().with(Protocol::Memory(rand::random::<u64>().max(1)));
+        swarm.listen_on(address.clone()).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+
+        let (commands, receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(PeerTask::new(swarm, role.clone()).run(receiver));
+        Ok(Self { peer_id, address, role, commands, task })
+    }
+
+    pub fn peer_id(&self) -> &PeerId {
+        &self.peer_id
+    }
+
+    pub fn address(&self) -> &Multiaddr {
+        &self.address
+    }
+
+    pub fn role(&self) -> &PeerRole {
+        &self.role
+    }
+
+    /// The storage node this peer runs, if it is one.
+    pub fn storage_node(&self) -> Option<Arc<Mutex<StorageNode>>> {
+        match &self.role {
+            PeerRole::Storage(storage_node) => Some(Arc::clone(storage_node)),
+            PeerRole::Client(_) => None,
+        }
+    }
+
+    /// The client this peer runs, if it is one.
+    pub fn client(&self) -> Option<Arc<Mutex<Client>>> {
+        match &self.role {
+            PeerRole::Client(client) => Some(Arc::clone(client)),
+            PeerRole::Storage(_) => None,
+        }
+    }
+
+    /// Dials `peer_id` at `address`, returning once the connection is up.
+    pub async fn connect(&self, peer_id: PeerId, address: Multiaddr) -> Result<(), String> {
+        let (reply, response) = oneshot::channel();
+        self.commands.send(Command::Connect(peer_id, address, reply)).map_err(|_| "Peer has stopped".to_string())?;
+        response.await.map_err(|_| "Peer has stopped".to_string())?
+    }
+
+    /// Sends `request` to a connected peer over `/pioneer/storage/1.0.0` and waits for
+    /// its response.
+    pub async fn request(&self, peer_id: PeerId, request: BlockRequest) -> Result<BlockResponse, String> {
+        let (reply, response) = oneshot::channel();
+        self.commands.send(Command::Request(peer_id, request, reply)).map_err(|_| "Peer has stopped".to_string())?;
+        response.await.map_err(|_| "Peer has stopped".to_string())?
+    }
+
+    pub async fn store(&self, peer_id: PeerId, name: &str, data: Vec<u8>) -> Result<(), String> {
+        match self.request(peer_id, BlockRequest::Store { name: name.to_string(), data }).await? {
+            BlockResponse::Stored => Ok(()),
+            other => Err(unexpected("store", other)),
+        }
+    }
+
+    /// Fetches a whole block, up to `MAX_BLOCK_SIZE`.
+    pub async fn fetch(&self, peer_id: PeerId, name: &str) -> Result<Vec<u8>, String> {
+        self.fetch_range(peer_id, name, 0, MAX_BLOCK_SIZE as u64).await
+    }
+
+    pub async fn fetch_range(&self, peer_id: PeerId, name: &str, offset: u64, length: u64) -> Result<Vec<u8>, String> {
+        match self.request(peer_id, BlockRequest::FetchRange { name: name.to_string(), offset, length }).await? {
+            BlockResponse::Data(data) => Ok(data),
+            other => Err(unexpected("fetch", other)),
+        }
+    }
+
+    /// The size of the block if the peer holds it.
+    pub async fn has_block(&self, peer_id: PeerId, name: &str) -> Result<Option<u64>, String> {
+        match self.request(peer_id, BlockRequest::HasBlock { name: name.to_string() }).await? {
+            BlockResponse::Has(size) => Ok(size),
+            other => Err(unexpected("has-block", other)),
+        }
+    }
+
+    pub async fn delete(&self, peer_id: PeerId, name: &str) -> Result<(), String> {
+        match self.request(peer_id, BlockRequest::Delete { name: name.to_string() }).await? {
+            BlockResponse::Deleted => Ok(()),
+            other => Err(unexpected("delete", other)),
+        }
+    }
+
+    /// Challenges the peer to prove it holds the block. Check the answer against
+    /// `protocol::proof_digest`.
+    pub async fn prove(&self, peer_id: PeerId, name: &str, nonce: u64) -> Result<[u8; 32], String> {
+        match self.request(peer_id, BlockRequest::ProofChallenge { name: name.to_string(), nonce }).await? {
+            BlockResponse::Proof(digest) => Ok(digest),
+            other => Err(unexpected("proof challenge", other)),
+        }
+    }
+}
+
+fn unexpected(operation: &str, response: BlockResponse) -> String {
+    match response {
+        BlockResponse::Error { message, .. } => message,
+        other => format!("Unexpected response to a {}: {:?}", operation, other),
+    }
+}
+
+impl Drop for PeerHandle {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn build_swarm(keypair: identity::Keypair) -> Result<Swarm<PeerBehaviour>, String> {
+    Ok(SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_other_transport(|key| Ok::<_, Box<dyn Error + Send + Sync>>(MemoryTransport::default()
+            .upgrade(upgrade::Version::V1)
+            .authenticate(noise::Config::new(key)?)
+            .multiplex(yamux::Config::default())
+            .boxed()))
+        .map_err(|e| format!("Failed to build transport: {}", e))?
+        .with_behaviour(|_| PeerBehaviour { storage: protocol::behaviour() })
+        .map_err(|e| format!("Failed to build behaviour: {}", e))?
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build())
+}
+
+/// A block this peer stored and passed on, and what it held under that name before.
+struct PendingForward {
+    name: String,
+    /// The block the copy replaced and its owner, if it had one, restored if the chain fails.
+    previous: Option<(Vec<u8>, Option<PeerId>)>,
+    channel: ResponseChannel<BlockResponse>,
+}
+
+/// The event loop behind a `PeerHandle`.
+struct PeerTask {
+    swarm: Swarm<PeerBehaviour>,
+    role: PeerRole,
+    pending_connects: HashMap<PeerId, Vec<oneshot::Sender<Result<(), String>>>>,
+    pending_requests: HashMap<OutboundRequestId, oneshot::Sender<Result<BlockResponse, String>>>,
+    /// Replications passed down the chain, waiting for the next peer to answer before
+    /// this one answers the peer it got the block from.
+    pending_forwards: HashMap<OutboundRequestId, PendingForward>,
+    /// The peer that stored each block, the only one that may replace or delete it.
+    owners: HashMap<String, PeerId>,
+}
+
+impl PeerTask {
+    fn new(swarm: Swarm<PeerBehaviour>, role: PeerRole) -> Self {
+        Self {
+            swarm,
+            role,
+            pending_connects: HashMap::new(),
+            pending_requests: HashMap::new(),
+            pending_forwards: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    async fn run(mut self, mut commands: mpsc::UnboundedReceiver<Command>) {
+        loop {
+            tokio::select! {
+                event = self.swarm.select_next_some() => self.handle_event(event),
+                command = commands.recv() => match command {
+                    Some(command) => self.handle_command(command),
+                    None => break,
+                },
+            }
+        }
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::Connect(peer_id, address, reply) => {
+                if self.swarm.is_connected(&peer_id) {
+                    let _ = reply.send(Ok(()));
+                    return;
+                }
+                match self.swarm.dial(DialOpts::peer_id(peer_id).addresses(vec![address]).build()) {
+                    Ok(()) => self.pending_connects.entry(peer_id).or_default().push(reply),
+                    Err(e) => {
+                        let _ = reply.send(Err(format!("Failed to dial {}: {}", peer_id, e)));
+                    }
+                }
+            }
+            Command::Request(peer_id, request, reply) => {
+                let request_id = self.swarm.behaviour_mut().storage.send_request(&peer_id, request);
+                self.pending_requests.insert(request_id, reply);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: SwarmEvent<PeerBehaviourEvent>) {
+        match event {
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => {
+                for reply in self.pending_connects.remove(&peer_id).unwrap_or_default() {
+                    let _ = reply.send(Ok(()));
+                }
+            }
+            SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } => {
+                for reply in self.pending_connects.remove(&peer_id).unwrap_or_default() {
+                    let _ = reply.send(Err(format!("Failed to connect to {}: {}", peer_id, error)));
+                }
+            }
+            SwarmEvent::Behaviour(PeerBehaviourEvent::Storage(event)) => match event {
+                request_response::Event::Message { peer, message: request_response::Message::Request { request, channel, .. } } => {
+                    match request {
+                        BlockRequest::Replicate { name, data, owner, forward_to } => self.replicate(peer, name, data, owner, forward_to, channel),
+                        request => {
+                            let response = self.serve(peer, request);
+                            let _ = self.swarm.behaviour_mut().storage.send_response(channel, response);
+                        }
+                    }
+                }
+                request_response::Event::Message { message: request_response::Message::Response { request_id, response }, .. } => {
+                    if let Some(reply) = self.pending_requests.remove(&request_id) {
+                        let _ = reply.send(Ok(response));
+                    } else if let Some(forward) = self.pending_forwards.remove(&request_id) {
+                        self.finish_forward(forward, response);
+                    }
+                }
+                request_response::Event::OutboundFailure { request_id, error, peer } => {
+                    if let Some(reply) = self.pending_requests.remove(&request_id) {
+                        let _ = reply.send(Err(format!("Request to {} failed: {}", peer, error)));
+                    } else if let Some(forward) = self.pending_forwards.remove(&request_id) {
+                        let response = BlockResponse::error(ErrorCode::ForwardFailed, format!("Forwarding to {} failed: {}", peer, error));
+                        self.finish_forward(forward, response);
+                    }
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Answers a request from `peer` out of this peer's own state. A block can only be
+    /// replaced or deleted by the peer that stored it.
+    fn serve(&mut self, peer: PeerId, request: BlockRequest) -> BlockResponse {
+        let PeerRole::Storage(storage_node) = &self.role else {
+            return BlockResponse::error(ErrorCode::NotStorageNode, "Peer is not a storage node");
+        };
+        let name = match &request {
+            BlockRequest::Store { name, .. } | BlockRequest::Replicate { name, .. } | BlockRequest::Delete { name } => Some(name.clone()),
+            _ => None,
+        };
+        let mut storage_node = storage_node.lock().unwrap();
+        if let Some(name) = &name {
+            if storage_node.get_file(name).is_some() && self.owners.get(name) != Some(&peer) {
+                return BlockResponse::error(ErrorCode::Unauthorized, "Block belongs to another peer");
+            }
+        }
+        let response = protocol::handle_request(&mut storage_node, request);
+        drop(storage_node);
+        match (&response, name) {
+            (BlockResponse::Stored, Some(name)) => {
+                self.owners.insert(name, peer);
+            }
+            (BlockResponse::Deleted, Some(name)) => {
+                self.owners.remove(&name);
+            }
+            _ => {}
+        }
+        response
+    }
+
+    /// Stores a copy of the block for `owner`, then passes it on to the next peer in the
+    /// chain. The answer to `peer` waits until the rest of the chain has answered. A copy
+    /// forwarded by another peer may only create a block, never replace one, since the
+    /// forwarding peer could name any owner.
+    fn replicate(&mut self, peer: PeerId, name: String, data: Vec<u8>, owner: PeerId, mut forward_to: Vec<PeerId>, channel: ResponseChannel<BlockResponse>) {
+        let previous = match &self.role {
+            PeerRole::Storage(storage_node) => storage_node.lock().unwrap().get_file(&name).cloned()
+                .map(|block| (block, self.owners.get(&name).copied())),
+            _ => None,
+        };
+        let stored = if peer != owner && previous.is_some() {
+            BlockResponse::error(ErrorCode::Unauthorized, "Only the owner may replace a block")
+        } else {
+            self.serve(owner, BlockRequest::Store { name: name.clone(), data: data.clone() })
+        };
+        if stored != BlockResponse::Stored || forward_to.is_empty() {
+            let _ = self.swarm.behaviour_mut().storage.send_response(channel, stored);
+            return;
+        }
+        let next = forward_to.remove(0);
+        let request_id = self.swarm.behaviour_mut().storage.send_request(&next, BlockRequest::Replicate { name: name.clone(), data, owner, forward_to });
+        self.pending_forwards.insert(request_id, PendingForward { name, previous, channel });
+    }
+
+    /// Passes the rest of the chain's answer back up. If the chain did not store the
+    /// block, this peer undoes its own copy too, so a chain is stored whole or not at all:
+    /// a block this request created is dropped and one it replaced is put back.
+    fn finish_forward(&mut self, forward: PendingForward, response: BlockResponse) {
+        let PendingForward { name, previous, channel } = forward;
+        if response != BlockResponse::Stored {
+            if let PeerRole::Storage(storage_node) = &self.role {
+                let mut storage_node = storage_node.lock().unwrap();
+                let _ = storage_node.remove_file(&name);
+                match previous {
+                    Some((block, owner)) => {
+                        // The old block fitted before this request, so it fits again once the new copy is gone
+                        let _ = storage_node.store_file(name.clone(), block);
+                        match owner {
+                            Some(owner) => self.owners.insert(name, owner),
+                            None => self.owners.remove(&name),
+                        };
+                    }
+                    None => {
+                        self.owners.remove(&name);
+                    }
+                }
+            }
+        }
+        let _ = self.swarm.behaviour_mut().storage.send_response(channel, response);
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+use libp2p::PeerId;
+use rand::RngCore;
+use rand::seq::SliceRandom;
+use crate::StorageNode;
+use crate::token_amount::TokenAmount;
+
+/// Limits a client puts on which SPs may store an upload. The default accepts every
+/// SP that has room for the file.
+#[derive(Clone, Debug, Default)]
+pub struct UploadConstraints {
+    /// Highest acceptable price per byte-epoch, in the same unit SPs quote.
+    pub max_price_per_byte_epoch: Option<TokenAmount>,
+    pub min_reputation: u64,
+    /// Free space, in bytes, an SP must still have after taking the file.
+    pub min_free_space: usize,
+    pub excluded_peers: HashSet<PeerId>,
+    /// How long the deals run; the network's default deal duration when unset.
+    pub duration: Option<Duration>,
+}
+
+/// The first constraint an SP failed, used to explain why an upload could not be placed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Constraint {
+    Draining,
+    NameTaken,
+    Excluded,
+    MaxPrice,
+    MinReputation,
+    FreeSpace,
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constraint::Draining => write!(f, "leaving the network"),
+            Constraint::NameTaken => write!(f, "already holding a file of that name"),
+            Constraint::Excluded => write!(f, "excluded by the client"),
+            Constraint::MaxPrice => write!(f, "above the maximum price"),
+            Constraint::MinReputation => write!(f, "below the minimum reputation"),
+            Constraint::FreeSpace => write!(f, "short of free space"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlacementError {
+    pub required: usize,
+    pub eligible: usize,
+    /// How many SPs each constraint ruled out.
+    pub rejected: BTreeMap<Constraint, usize>,
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not enough eligible storage nodes. Required: {}, Eligible: {}", self.required, self.eligible)?;
+        if !self.rejected.is_empty() {
+            let reasons: Vec<String> = self.rejected.iter()
+                .map(|(constraint, count)| format!("{} {}", count, constraint))
+                .collect();
+            write!(f, " (rejected: {})", reasons.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PlacementError {}
+
+impl UploadConstraints {
+    /// Checks one SP against the constraints for storing `size` bytes as `filename`.
+    pub fn check(&self, storage_node: &StorageNode, filename: &str, size: usize) -> Result<(), Constraint> {
+        if storage_node.is_draining() {
+            return Err(Constraint::Draining);
+        }
+        if storage_node.get_file(filename).is_some() {
+            return Err(Constraint::NameTaken);
+        }
+        if self.excluded_peers.contains(storage_node.peer_id()) {
+            return Err(Constraint::Excluded);
+        }
+        if self.max_price_per_byte_epoch.is_some_and(|max_price| storage_node.price_per_byte_epoch() > max_price) {
+            return Err(Constraint::MaxPrice);
+        }
+        if storage_node.reputation() < self.min_reputation {
+            return Err(Constraint::MinReputation);
+        }
+        if storage_node.available_space() < size.saturating_add(self.min_free_space) {
+            return Err(Constraint::FreeSpace);
+        }
+        Ok(())
+    }
+
+    /// Returns every SP that satisfies the constraints, or an error saying which
+    /// constraints ruled out the rest when fewer than `required` qualify.
+    pub fn eligible_nodes(&self, storage_nodes: &HashMap<PeerId, StorageNode>, filename: &str, size: usize, required: usize) -> Result<Vec<PeerId>, PlacementError> {
+        let mut eligible = Vec::new();
+        let mut rejected = BTreeMap::new();
+        for (peer_id, storage_node) in storage_nodes {
+            match self.check(storage_node, filename, size) {
+                Ok(()) => eligible.push(*peer_id),
+                Err(constraint) => *rejected.entry(constraint).or_insert(0) += 1,
+            }
+        }
+        if eligible.len() < required {
+            return Err(PlacementError { required, eligible: eligible.len(), rejected });
+        }
+        Ok(eligible)
+    }
+}
+
+/// Decides which of the eligible SPs receive the replicas of a file.
+pub trait PlacementStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Picks up to `count` distinct SPs from `candidates`. `existing` holds the SPs that
+    /// already store the file, for strategies that place relative to them.
+    fn select(&self, candidates: &[&StorageNode], existing: &[&StorageNode], count: usize, rng: &mut dyn RngCore) -> Vec<PeerId>;
+}
+
+/// Uniformly random placement.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomPlacement;
+
+impl PlacementStrategy for RandomPlacement {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn select(&self, candidates: &[&StorageNode], _existing: &[&StorageNode], count: usize, rng: &mut dyn RngCore) -> Vec<PeerId> {
+        candidates.choose_multiple(rng, count).map(|node| *node.peer_id()).collect()
+    }
+}
+
+/// The lowest storage prices win; ties are broken at random.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CheapestPlacement;
+
+impl PlacementStrategy for CheapestPlacement {
+    fn name(&self) -> &'static str {
+        "cheapest"
+    }
+
+    fn select(&self, candidates: &[&StorageNode], _existing: &[&StorageNode], count: usize, rng: &mut dyn RngCore) -> Vec<PeerId> {
+        let mut ranked = candidates.to_vec();
+        ranked.shuffle(rng);
+        ranked.sort_by_key(|node| node.price_per_byte_epoch());
+        ranked.iter().take(count).map(|node| *node.peer_id()).collect()
+    }
+}
+
+/// Random placement where an SP's chance of being picked is proportional to its reputation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReputationWeightedPlacement;
+
+impl PlacementStrategy for ReputationWeightedPlacement {
+    fn name(&self) -> &'static str {
+        "reputation"
+    }
+
+    fn select(&self, candidates: &[&StorageNode], _existing: &[&StorageNode], count: usize, rng: &mut dyn RngCore) -> Vec<PeerId> {
+        // Every SP keeps a small chance so a fresh or penalised SP can recover
+        candidates.choose_multiple_weighted(rng, count, |node| node.reputation().max(1) as f64)
+            .map(|chosen| chosen.map(|node| *node.peer_id()).collect())
+            .unwrap_or_else(|_| RandomPlacement.select(candidates, &[], count, rng))
+    }
+}
+
+/// Fills the emptiest SPs first so used space evens out across the network.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CapacityBalancedPlacement;
+
+impl PlacementStrategy for CapacityBalancedPlacement {
+    fn name(&self) -> &'static str {
+        "capacity"
+    }
+
+    fn select(&self, candidates: &[&StorageNode], _existing: &[&StorageNode], count: usize, rng: &mut dyn RngCore) -> Vec<PeerId> {
+        let mut ranked = candidates.to_vec();
+        ranked.shuffle(rng);
+        ranked.sort_by_key(|node| std::cmp::Reverse(node.available_space()));
+        ranked.iter().take(count).map(|node| *node.peer_id()).collect()
+    }
+}
+
+/// Spreads replicas over as many regions as possible, counting the regions that already
+/// hold the file. SPs without a region are treated as one shared region.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RegionDiversePlacement;
+
+impl PlacementStrategy for RegionDiversePlacement {
+    fn name(&self) -> &'static str {
+        "region"
+    }
+
+    fn select(&self, candidates: &[&StorageNode], existing: &[&StorageNode], count: usize, rng: &mut dyn RngCore) -> Vec<PeerId> {
+        let mut by_region: BTreeMap<Option<&str>, Vec<&StorageNode>> = BTreeMap::new();
+        for node in candidates {
+            by_region.entry(node.region()).or_default().push(node);
+        }
+        let mut replicas: HashMap<Option<&str>, usize> = HashMap::new();
+        for node in existing {
+            *replicas.entry(node.region()).or_insert(0) += 1;
+        }
+
+        let mut selected = Vec::new();
+        while selected.len() < count {
+            // Take from one of the least covered regions that still has candidates
+            let mut open: Vec<Option<&str>> = by_region.iter()
+                .filter(|(_, nodes)| !nodes.is_empty())
+                .map(|(region, _)| *region)
+                .collect();
+            let Some(fewest) = open.iter().map(|region| replicas.get(region).copied().unwrap_or(0)).min() else { break };
+            open.retain(|region| replicas.get(region).copied().unwrap_or(0) == fewest);
+            let region = *open.choose(rng).unwrap();
+
+            let nodes = by_region.get_mut(&region).unwrap();
+            let index = (rng.next_u32() as usize) % nodes.len();
+            selected.push(*nodes.swap_remove(index).peer_id());
+            *replicas.entry(region).or_insert(0) += 1;
+        }
+        selected
+    }
+}
+
+/// Looks up a built-in strategy by the name it reports.
+pub fn strategy_by_name(name: &str) -> Result<Box<dyn PlacementStrategy>, String> {
+    match name {
+        "random" => Ok(Box::new(RandomPlacement)),
+        "cheapest" => Ok(Box::new(CheapestPlacement)),
+        "reputation" => Ok(Box::new(ReputationWeightedPlacement)),
+        "capacity" => Ok(Box::new(CapacityBalancedPlacement)),
+        "region" => Ok(Box::new(RegionDiversePlacement)),
+        _ => Err(format!("Unknown placement strategy: {} (expected random, cheapest, reputation, capacity or region)", name)),
+    }
+}
@@ -0,0 +1,125 @@
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+use crate::token_amount::TokenAmount;
+
+const BASIS_POINTS: u64 = 10_000;
+
+/// Limits on how an SP's storage price may move. Prices are per byte per epoch.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PricingPolicy {
+    pub floor: TokenAmount,
+    pub ceiling: TokenAmount,
+    /// Largest change in one adjustment, in basis points of the current price.
+    pub max_step_bps: u64,
+    /// Utilisation, in basis points, at which demand is considered balanced.
+    pub target_utilisation_bps: u64,
+}
+
+impl Default for PricingPolicy {
+    fn default() -> Self {
+        Self {
+            floor: TokenAmount::from_base_units(1),
+            ceiling: TokenAmount::from_base_units(1_000_000),
+            max_step_bps: 1_000, // 10% per adjustment
+            target_utilisation_bps: 6_000,
+        }
+    }
+}
+
+impl PricingPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.floor > self.ceiling {
+            return Err(format!("Price floor {} is above the ceiling {}", self.floor, self.ceiling));
+        }
+        Ok(())
+    }
+}
+
+/// What the network saw about one SP's market since the last adjustment.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MarketSignals {
+    /// Share of the SP's space in use, from 0 to 1.
+    pub utilisation: f64,
+    /// Share of the open order book's space offered below this SP's price, from 0 to 1.
+    pub cheaper_supply: f64,
+    pub auctions_won: usize,
+    pub auctions_lost: usize,
+}
+
+impl MarketSignals {
+    /// Combined pressure on the price between -1 (cut as far as allowed) and 1 (raise as
+    /// far as allowed).
+    pub fn pressure(&self, policy: &PricingPolicy) -> f64 {
+        let target = (policy.target_utilisation_bps.min(BASIS_POINTS) as f64) / BASIS_POINTS as f64;
+        let utilisation = if self.utilisation >= target {
+            (self.utilisation - target) / (1.0 - target).max(f64::EPSILON)
+        } else {
+            (self.utilisation - target) / target.max(f64::EPSILON)
+        };
+        let auctions = self.auctions_won + self.auctions_lost;
+        let auction = if auctions == 0 {
+            0.0
+        } else {
+            (self.auctions_won as f64 - self.auctions_lost as f64) / auctions as f64
+        };
+        (utilisation - 0.5 * self.cheaper_supply + 0.5 * auction).clamp(-1.0, 1.0)
+    }
+}
+
+/// One price change made by the engine.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceChange {
+    #[serde_as(as = "DisplayFromStr")]
+    pub storage_node_id: PeerId,
+    pub epoch: u64,
+    pub old_price: TokenAmount,
+    pub new_price: TokenAmount,
+    pub reason: String,
+}
+
+/// Moves storage prices towards what the market will bear, for the SPs that opted in
+/// with a pricing policy.
+#[derive(Clone, Debug, Default)]
+pub struct PricingEngine {
+    history: Vec<PriceChange>,
+    /// How many auction notices earlier adjustments have already accounted for.
+    notices_seen: usize,
+}
+
+impl PricingEngine {
+    pub fn history(&self) -> &[PriceChange] {
+        &self.history
+    }
+
+    pub fn notices_seen(&self) -> usize {
+        self.notices_seen
+    }
+
+    pub fn mark_notices_seen(&mut self, count: usize) {
+        self.notices_seen = count;
+    }
+
+    /// The price `signals` call for, limited by `policy`. Prices outside the policy band
+    /// are brought back inside it even without market pressure.
+    pub fn next_price(&self, policy: &PricingPolicy, price: TokenAmount, signals: &MarketSignals) -> TokenAmount {
+        let pressure = signals.pressure(policy);
+        let step_bps = (pressure.abs() * policy.max_step_bps as f64).round() as u64;
+        let mut step = price.proportion(step_bps, BASIS_POINTS);
+        if step.is_zero() && step_bps > 0 {
+            // Tiny prices would otherwise never move
+            step = TokenAmount::from_base_units(1);
+        }
+        let moved = if pressure > 0.0 {
+            price.checked_add(step).unwrap_or(TokenAmount::MAX)
+        } else {
+            price.saturating_sub(step)
+        };
+        moved.clamp(policy.floor, policy.ceiling)
+    }
+
+    pub fn record(&mut self, change: PriceChange) {
+        self.history.push(change);
+    }
+}
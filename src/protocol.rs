@@ -0,0 +1,220 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use async_trait::async_trait;
+use bincode::Options;
+use libp2p::futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::{PeerId, StreamProtocol};
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use crate::StorageNode;
+
+pub const STORAGE_PROTOCOL: StreamProtocol = StreamProtocol::new("/pioneer/storage/1.0.0");
+/// Largest block a peer will store, and the most one fetch returns.
+pub const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+/// Room for the block name and framing on top of the block itself.
+const MESSAGE_OVERHEAD: u64 = 4 * 1024;
+pub const MAX_MESSAGE_SIZE: u64 = MAX_BLOCK_SIZE as u64 + MESSAGE_OVERHEAD;
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request to a storage peer. `Store` and `Delete` are only served for the peer the
+/// block belongs to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockRequest {
+    Store { name: String, data: Vec<u8> },
+    /// Up to `length` bytes from `offset`, cut short at the end of the block or at
+    /// `MAX_BLOCK_SIZE`.
+    FetchRange { name: String, offset: u64, length: u64 },
+    HasBlock { name: String },
+    Delete { name: String },
+    /// Asks the peer to prove it holds the block by hashing it with a fresh nonce.
+    ProofChallenge { name: String, nonce: u64 },
+    /// Stores the block for `owner` and passes it on to the first peer in `forward_to` with
+    /// the rest of the list. Answered with `Stored` once every peer in the chain holds a copy.
+    Replicate { name: String, data: Vec<u8>, owner: PeerId, forward_to: Vec<PeerId> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlockResponse {
+    Stored,
+    Data(Vec<u8>),
+    /// The block's size, if the peer holds it.
+    Has(Option<u64>),
+    Deleted,
+    Proof([u8; 32]),
+    Error { code: ErrorCode, message: String },
+}
+
+/// Why a request failed. Variants are encoded by position, so new codes go at the end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    NotFound,
+    TooLarge,
+    OutOfSpace,
+    InvalidRange,
+    NotStorageNode,
+    /// The requesting peer may not store or delete this block.
+    Unauthorized,
+    /// A peer further down a replication chain did not store the block.
+    ForwardFailed,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::NotFound => write!(f, "not found"),
+            ErrorCode::TooLarge => write!(f, "too large"),
+            ErrorCode::OutOfSpace => write!(f, "out of space"),
+            ErrorCode::InvalidRange => write!(f, "invalid range"),
+            ErrorCode::NotStorageNode => write!(f, "not a storage node"),
+            ErrorCode::Unauthorized => write!(f, "unauthorized"),
+            ErrorCode::ForwardFailed => write!(f, "forward failed"),
+        }
+    }
+}
+
+impl BlockResponse {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        BlockResponse::Error { code, message: message.into() }
+    }
+}
+
+/// What a peer holding `data` must answer to a proof challenge with `nonce`: the
+/// SHA-256 of the nonce in little-endian bytes followed by the data.
+pub fn proof_digest(data: &[u8], nonce: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(nonce.to_le_bytes());
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// Answers a request from a storage node's own state. Callers decide first whether the
+/// requesting peer may store or delete the block, and pass `Replicate` requests on
+/// themselves; only the local copy is stored here.
+pub fn handle_request(storage_node: &mut StorageNode, request: BlockRequest) -> BlockResponse {
+    match request {
+        BlockRequest::Store { name, data } | BlockRequest::Replicate { name, data, .. } => {
+            if data.len() > MAX_BLOCK_SIZE {
+                return BlockResponse::error(ErrorCode::TooLarge, format!("Blocks are limited to {} bytes", MAX_BLOCK_SIZE));
+            }
+            let previous = storage_node.get_file(&name).map_or(0, |block| block.len());
+            if data.len() > storage_node.available_space() + previous {
+                return BlockResponse::error(ErrorCode::OutOfSpace, "Not enough space to store the block");
+            }
+            // A new copy replaces the old one rather than taking its space twice
+            let _ = storage_node.remove_file(&name);
+            match storage_node.store_file(name, data) {
+                Ok(()) => BlockResponse::Stored,
+                Err(e) => BlockResponse::error(ErrorCode::OutOfSpace, e),
+            }
+        }
+        BlockRequest::FetchRange { name, offset, length } => {
+            let Some(block) = storage_node.get_file(&name) else {
+                return BlockResponse::error(ErrorCode::NotFound, "Block not found");
+            };
+            if offset > block.len() as u64 {
+                return BlockResponse::error(ErrorCode::InvalidRange, format!("Offset {} is past the end of a {} byte block", offset, block.len()));
+            }
+            let start = offset as usize;
+            let end = start + (length.min(MAX_BLOCK_SIZE as u64) as usize).min(block.len() - start);
+            BlockResponse::Data(block[start..end].to_vec())
+        }
+        BlockRequest::HasBlock { name } => BlockResponse::Has(storage_node.get_file(&name).map(|block| block.len() as u64)),
+        BlockRequest::Delete { name } => match storage_node.remove_file(&name) {
+            Ok(()) => BlockResponse::Deleted,
+            Err(_) => BlockResponse::error(ErrorCode::NotFound, "Block not found"),
+        },
+        BlockRequest::ProofChallenge { name, nonce } => match storage_node.get_file(&name) {
+            Some(block) => BlockResponse::Proof(proof_digest(block, nonce)),
+            None => BlockResponse::error(ErrorCode::NotFound, "Block not found"),
+        },
+    }
+}
+
+/// Encodes messages with bincode's variable-length integers and refuses any message
+/// over `max_message_size`, on the way in and on the way out.
+#[derive(Clone, Copy, Debug)]
+pub struct StorageCodec {
+    pub max_message_size: u64,
+}
+
+impl Default for StorageCodec {
+    fn default() -> Self {
+        Self { max_message_size: MAX_MESSAGE_SIZE }
+    }
+}
+
+impl StorageCodec {
+    fn options(&self) -> impl Options {
+        bincode::DefaultOptions::new().with_limit(self.max_message_size)
+    }
+
+    pub fn encode<M: Serialize>(&self, message: &M) -> io::Result<Vec<u8>> {
+        self.options().serialize(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    pub fn decode<M: DeserializeOwned>(&self, bytes: &[u8]) -> io::Result<M> {
+        self.options().deserialize(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    async fn read<T: AsyncRead + Unpin + Send, M: DeserializeOwned>(&self, io: &mut T) -> io::Result<M> {
+        let mut bytes = Vec::new();
+        io.take(self.max_message_size + 1).read_to_end(&mut bytes).await?;
+        if bytes.len() as u64 > self.max_message_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Message is larger than {} bytes", self.max_message_size)));
+        }
+        self.decode(&bytes)
+    }
+
+    async fn write<T: AsyncWrite + Unpin + Send, M: Serialize>(&self, io: &mut T, message: &M) -> io::Result<()> {
+        let bytes = self.encode(message)?;
+        io.write_all(&bytes).await?;
+        io.close().await
+    }
+}
+
+#[async_trait]
+impl request_response::Codec for StorageCodec {
+    type Protocol = StreamProtocol;
+    type Request = BlockRequest;
+    type Response = BlockResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<BlockRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read(io).await
+    }
+
+    async fn read_response<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<BlockResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        self.read(io).await
+    }
+
+    async fn write_request<T>(&mut self, _: &StreamProtocol, io: &mut T, request: BlockRequest) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write(io, &request).await
+    }
+
+    async fn write_response<T>(&mut self, _: &StreamProtocol, io: &mut T, response: BlockResponse) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        self.write(io, &response).await
+    }
+}
+
+/// The storage protocol behaviour, serving and sending requests with `REQUEST_TIMEOUT`.
+pub fn behaviour() -> request_response::Behaviour<StorageCodec> {
+    request_response::Behaviour::with_codec(
+        StorageCodec::default(),
+        [(STORAGE_PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT),
+    )
+}
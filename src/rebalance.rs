@@ -0,0 +1,92 @@
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+use crate::StorageNode;
+
+const BASIS_POINTS: u128 = 10_000;
+
+/// Limits on how much data one rebalancing run may move. Rebalancing is off until a
+/// policy enables it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RebalancePolicy {
+    pub enabled: bool,
+    /// Bytes one run may move across the whole network.
+    pub max_bytes_per_run: usize,
+    /// Bytes one SP may send or receive in a run.
+    pub max_bytes_per_node: usize,
+    /// Smallest gap in utilisation, in basis points, worth moving a replica across.
+    pub min_gap_bps: u64,
+}
+
+impl Default for RebalancePolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes_per_run: 100_000_000,
+            max_bytes_per_node: 25_000_000,
+            min_gap_bps: 500,
+        }
+    }
+}
+
+impl RebalancePolicy {
+    /// Whether moving `size` bytes from `source` to `target` narrows a gap of at least
+    /// `min_gap_bps`, and more than zero, without leaving the target fuller than the
+    /// source. Empty replicas are never worth moving.
+    pub fn worth_moving(&self, source: &StorageNode, target: &StorageNode, size: usize) -> bool {
+        let (source_used, source_total) = (source.used_space() as u128, source.total_space() as u128);
+        let (target_used, target_total) = (target.used_space() as u128, target.total_space() as u128);
+        let size = size as u128;
+        let gap = (source_used * target_total).saturating_sub(target_used * source_total) * BASIS_POINTS;
+        if size == 0 || size > source_used || gap == 0 || gap < self.min_gap_bps as u128 * source_total * target_total {
+            return false;
+        }
+        (target_used + size) * source_total <= (source_used - size) * target_total
+    }
+}
+
+/// One replica moved by the rebalancer.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaMove {
+    #[serde_as(as = "DisplayFromStr")]
+    pub client_id: PeerId,
+    pub filename: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub from: PeerId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub to: PeerId,
+    pub from_deal: u64,
+    pub to_deal: u64,
+    pub bytes: usize,
+    pub epoch: u64,
+}
+
+/// The rebalancing policy and every move made under it.
+#[derive(Clone, Debug, Default)]
+pub struct Rebalancer {
+    policy: RebalancePolicy,
+    history: Vec<ReplicaMove>,
+}
+
+impl Rebalancer {
+    pub fn policy(&self) -> &RebalancePolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: RebalancePolicy) -> Result<(), String> {
+        if policy.min_gap_bps > BASIS_POINTS as u64 {
+            return Err(format!("Utilisation gap of {} basis points is above 100%", policy.min_gap_bps));
+        }
+        self.policy = policy;
+        Ok(())
+    }
+
+    pub fn history(&self) -> &[ReplicaMove] {
+        &self.history
+    }
+
+    pub fn record(&mut self, replica_move: ReplicaMove) {
+        self.history.push(replica_move);
+    }
+}
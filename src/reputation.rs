@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+use serde_with::{serde_as, DisplayFromStr};
+
+/// Observations kept from earlier epochs are scaled by this much each epoch, so an
+/// SP's recent behaviour outweighs its history.
+const DEFAULT_DECAY: f64 = 0.9;
+/// Pseudo-successes every rate starts with, so a new SP begins at full score and a
+/// single failure does not sink it.
+const PRIOR_SUCCESSES: f64 = 2.0;
+/// Retrievals served at or under this latency score full marks.
+const TARGET_LATENCY: Duration = Duration::from_millis(200);
+/// Weight given to each new latency sample in the moving average.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// How much each component contributes to the overall score. They sum to 1.
+const AUDIT_WEIGHT: f64 = 0.35;
+const RETRIEVAL_WEIGHT: f64 = 0.2;
+const LATENCY_WEIGHT: f64 = 0.1;
+const UPTIME_WEIGHT: f64 = 0.15;
+const DEAL_WEIGHT: f64 = 0.2;
+
+/// Highest overall score, held by an SP with a clean record.
+pub const MAX_REPUTATION: u64 = 100;
+
+/// A success/failure tally whose weight fades every epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DecayingRate {
+    pub successes: f64,
+    pub failures: f64,
+}
+
+impl DecayingRate {
+    fn record(&mut self, success: bool) {
+        if success {
+            self.successes += 1.0;
+        } else {
+            self.failures += 1.0;
+        }
+    }
+
+    fn decay(&mut self, factor: f64) {
+        self.successes *= factor;
+        self.failures *= factor;
+    }
+
+    /// Share of successes between 0 and 1, starting from the optimistic prior.
+    pub fn rate(&self) -> f64 {
+        (self.successes + PRIOR_SUCCESSES) / (self.successes + self.failures + PRIOR_SUCCESSES)
+    }
+}
+
+/// Everything observed about one SP.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReputationRecord {
+    pub audits: DecayingRate,
+    pub retrievals: DecayingRate,
+    /// Whether the SP answered any of its storage challenges, once per audited epoch.
+    pub uptime: DecayingRate,
+    pub deals: DecayingRate,
+    /// Moving average of retrieval latency in milliseconds, if any retrieval was timed.
+    pub average_latency_ms: Option<f64>,
+}
+
+impl ReputationRecord {
+    fn latency_score(&self) -> f64 {
+        match self.average_latency_ms {
+            Some(latency) if latency > TARGET_LATENCY.as_secs_f64() * 1000.0 =>
+                TARGET_LATENCY.as_secs_f64() * 1000.0 / latency,
+            _ => 1.0,
+        }
+    }
+}
+
+/// An SP's published score and the components it was computed from, each between 0 and 1.
+#[serde_as]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReputationBreakdown {
+    #[serde_as(as = "DisplayFromStr")]
+    pub storage_node_id: PeerId,
+    pub audits: f64,
+    pub retrievals: f64,
+    pub latency: f64,
+    pub uptime: f64,
+    pub deal_completion: f64,
+    /// Weighted total, from 0 to `MAX_REPUTATION`.
+    pub score: u64,
+}
+
+/// Scores SPs from what the network observes them doing: storage audits, retrievals and
+/// their latency, uptime and whether their deals run to completion.
+#[derive(Clone, Debug)]
+pub struct ReputationEngine {
+    decay: f64,
+    records: HashMap<PeerId, ReputationRecord>,
+}
+
+impl Default for ReputationEngine {
+    fn default() -> Self {
+        Self::new(DEFAULT_DECAY)
+    }
+}
+
+impl ReputationEngine {
+    pub fn new(decay: f64) -> Self {
+        Self {
+            decay: decay.clamp(0.0, 1.0),
+            records: HashMap::new(),
+        }
+    }
+
+    pub fn record(&self, storage_node_id: &PeerId) -> Option<&ReputationRecord> {
+        self.records.get(storage_node_id)
+    }
+
+    pub fn record_audit(&mut self, storage_node_id: &PeerId, passed: bool) {
+        self.records.entry(*storage_node_id).or_default().audits.record(passed);
+    }
+
+    pub fn record_retrieval(&mut self, storage_node_id: &PeerId, success: bool, latency: Option<Duration>) {
+        let record = self.records.entry(*storage_node_id).or_default();
+        record.retrievals.record(success);
+        if let Some(latency) = latency {
+            let sample = latency.as_secs_f64() * 1000.0;
+            record.average_latency_ms = Some(match record.average_latency_ms {
+                Some(average) => average + LATENCY_SMOOTHING * (sample - average),
+                None => sample,
+            });
+        }
+    }
+
+    pub fn record_uptime(&mut self, storage_node_id: &PeerId, online: bool) {
+        self.records.entry(*storage_node_id).or_default().uptime.record(online);
+    }
+
+    pub fn record_deal_outcome(&mut self, storage_node_id: &PeerId, completed: bool) {
+        self.records.entry(*storage_node_id).or_default().deals.record(completed);
+    }
+
+    /// Fades every observation by one epoch's worth.
+    pub fn decay(&mut self) {
+        for record in self.records.values_mut() {
+            record.audits.decay(self.decay);
+            record.retrievals.decay(self.decay);
+            record.uptime.decay(self.decay);
+            record.deals.decay(self.decay);
+        }
+    }
+
+    /// Current score breakdown. SPs with no observations score full marks.
+    pub fn breakdown(&self, storage_node_id: &PeerId) -> ReputationBreakdown {
+        let record = self.records.get(storage_node_id).cloned().unwrap_or_default();
+        let audits = record.audits.rate();
+        let retrievals = record.retrievals.rate();
+        let latency = record.latency_score();
+        let uptime = record.uptime.rate();
+        let deal_completion = record.deals.rate();
+        let weighted = audits * AUDIT_WEIGHT
+            + retrievals * RETRIEVAL_WEIGHT
+            + latency * LATENCY_WEIGHT
+            + uptime * UPTIME_WEIGHT
+            + deal_completion * DEAL_WEIGHT;
+        ReputationBreakdown {
+            storage_node_id: *storage_node_id,
+            audits,
+            retrievals,
+            latency,
+            uptime,
+            deal_completion,
+            score: (weighted * MAX_REPUTATION as f64).round() as u64,
+        }
+    }
+
+    pub fn score(&self, storage_node_id: &PeerId) -> u64 {
+        self.breakdown(storage_node_id).score
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use crate::clock::Timestamp;
+use crate::network::{Network, EPOCH_DURATION};
+
+/// Periodic maintenance the network runs on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MaintenanceJob {
+    /// Renew, hold in grace or expire deals whose term has run out.
+    CheckDeals,
+    /// Reprice SPs from utilisation, offers and auction results.
+    AdjustPrices,
+    /// Audit every deal and pay out one epoch.
+    Audits,
+    /// Retry replacing replicas lost to expiry or slashing.
+    RepairScan,
+    /// Drop storage offers past their last epoch.
+    ExpireOffers,
+    /// Move replicas towards under-utilised SPs, if rebalancing is enabled.
+    Rebalance,
+}
+
+impl MaintenanceJob {
+    pub const ALL: [MaintenanceJob; 6] = [
+        MaintenanceJob::CheckDeals,
+        MaintenanceJob::AdjustPrices,
+        MaintenanceJob::Audits,
+        MaintenanceJob::RepairScan,
+        MaintenanceJob::ExpireOffers,
+        MaintenanceJob::Rebalance,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MaintenanceJob::CheckDeals => "check_deals",
+            MaintenanceJob::AdjustPrices => "adjust_prices",
+            MaintenanceJob::Audits => "audits",
+            MaintenanceJob::RepairScan => "repair_scan",
+            MaintenanceJob::ExpireOffers => "expire_offers",
+            MaintenanceJob::Rebalance => "rebalance",
+        }
+    }
+
+    pub fn default_interval(self) -> Duration {
+        match self {
+            MaintenanceJob::CheckDeals => Duration::from_secs(60),
+            MaintenanceJob::AdjustPrices => Duration::from_secs(60),
+            MaintenanceJob::Audits => EPOCH_DURATION,
+            MaintenanceJob::RepairScan => Duration::from_secs(10 * 60),
+            MaintenanceJob::ExpireOffers => Duration::from_secs(5 * 60),
+            MaintenanceJob::Rebalance => Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl fmt::Display for MaintenanceJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MaintenanceJob {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        MaintenanceJob::ALL.into_iter()
+            .find(|job| job.name() == value)
+            .ok_or_else(|| format!("Unknown job {}, expected one of: check_deals, adjust_prices, audits, repair_scan, expire_offers, rebalance", value))
+    }
+}
+
+/// How a scheduled job is configured and how its runs have gone.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub job: MaintenanceJob,
+    #[serde(with = "serde_millis")]
+    pub interval: Duration,
+    pub paused: bool,
+    pub runs: u64,
+    pub last_run: Option<Timestamp>,
+    pub last_summary: Option<String>,
+}
+
+impl JobStatus {
+    /// When the job is next due. A job that has never run is due straight away.
+    pub fn next_run(&self) -> Option<Timestamp> {
+        self.last_run.map(|last_run| last_run + self.interval)
+    }
+
+    pub fn is_due_at(&self, now: Timestamp) -> bool {
+        !self.paused && self.next_run().is_none_or(|next_run| now >= next_run)
+    }
+}
+
+/// Keeps track of the network's periodic jobs. The jobs themselves are run by
+/// `Network::run_due_jobs`, which `spawn` calls on every tick.
+#[derive(Clone, Debug)]
+pub struct Scheduler {
+    jobs: Vec<JobStatus>,
+    paused: bool,
+}
+
+impl Default for Scheduler {
+    /// Every maintenance job at its default interval.
+    fn default() -> Self {
+        let mut scheduler = Self::new();
+        for job in MaintenanceJob::ALL {
+            scheduler.register(job, job.default_interval());
+        }
+        scheduler
+    }
+}
+
+impl Scheduler {
+    /// A scheduler with no jobs.
+    pub fn new() -> Self {
+        Self { jobs: Vec::new(), paused: false }
+    }
+
+    /// Adds `job`, or changes its interval if it is already registered.
+    pub fn register(&mut self, job: MaintenanceJob, interval: Duration) {
+        match self.jobs.iter_mut().find(|status| status.job == job) {
+            Some(status) => status.interval = interval,
+            None => self.jobs.push(JobStatus { job, interval, paused: false, runs: 0, last_run: None, last_summary: None }),
+        }
+    }
+
+    pub fn unregister(&mut self, job: MaintenanceJob) -> bool {
+        let before = self.jobs.len();
+        self.jobs.retain(|status| status.job != job);
+        self.jobs.len() != before
+    }
+
+    pub fn jobs(&self) -> &[JobStatus] {
+        &self.jobs
+    }
+
+    pub fn status(&self, job: MaintenanceJob) -> Option<&JobStatus> {
+        self.jobs.iter().find(|status| status.job == job)
+    }
+
+    fn status_mut(&mut self, job: MaintenanceJob) -> Result<&mut JobStatus, String> {
+        self.jobs.iter_mut().find(|status| status.job == job).ok_or_else(|| format!("Job {} is not scheduled", job))
+    }
+
+    pub fn set_interval(&mut self, job: MaintenanceJob, interval: Duration) -> Result<(), String> {
+        if interval.is_zero() {
+            return Err("Job interval must be greater than zero".to_string());
+        }
+        self.status_mut(job)?.interval = interval;
+        Ok(())
+    }
+
+    /// Pauses or resumes one job. Paused jobs can still be triggered by hand.
+    pub fn set_job_paused(&mut self, job: MaintenanceJob, paused: bool) -> Result<(), String> {
+        self.status_mut(job)?.paused = paused;
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes every job at once, keeping each job's own setting.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Jobs due at `now`, in registration order.
+    pub fn due(&self, now: Timestamp) -> Vec<MaintenanceJob> {
+        if self.paused {
+            return Vec::new();
+        }
+        self.jobs.iter().filter(|status| status.is_due_at(now)).map(|status| status.job).collect()
+    }
+
+    pub fn record_run(&mut self, job: MaintenanceJob, at: Timestamp, summary: String) {
+        if let Ok(status) = self.status_mut(job) {
+            status.runs += 1;
+            status.last_run = Some(at);
+            status.last_summary = Some(summary);
+        }
+    }
+}
+
+/// Runs the network's due jobs every `tick` until the task is aborted. Waiting for the
+/// network's lock and running the jobs both block, so each tick happens on the blocking
+/// pool instead of tying up an async worker. Stops if a job panics.
+pub fn spawn(network: Arc<Mutex<Network>>, tick: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tick);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let network = Arc::clone(&network);
+            let run = tokio::task::spawn_blocking(move || {
+                network.lock().unwrap().run_due_jobs();
+            });
+            if run.await.is_err() {
+                break;
+            }
+        }
+    })
+}
@@ -2,9 +2,18 @@ use libp2p::PeerId;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 use serde_with::{serde_as, DisplayFromStr};
+use sha2::{Digest, Sha256};
+use crate::pricing::PricingPolicy;
+use crate::token_amount::TokenAmount;
 
 const MAX_STORAGE: usize = 1_000_000_000; // 1GB max storage
 
+/// SHA-256 digest used to check that an SP still holds the exact bytes a deal was made
+/// for. Stable across builds, so commitments in saved deals keep verifying.
+pub fn content_digest(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
 #[serde_as]
 #[derive(Clone, Serialize, Deserialize)]
 
@@ -14,28 +23,51 @@ pub struct StorageNode {
     stored_files: HashMap<String, Vec<u8>>,
     available_space: usize,
     reputation: u64,
-    price_per_gb: u64,
+    price_per_byte_epoch: TokenAmount,
+    retrieval_price_per_byte: TokenAmount,
+    storage_earnings: TokenAmount,
+    retrieval_earnings: TokenAmount,
+    /// Where the SP says it is located, used to spread replicas across failure domains.
+    #[serde(default)]
+    region: Option<String>,
+    /// Set once the SP has asked to leave; it takes no new deals from then on.
+    #[serde(default)]
+    draining: bool,
+    /// Bounds for automatic repricing. SPs without one keep the price they set.
+    #[serde(default)]
+    pricing_policy: Option<PricingPolicy>,
 }
 
 impl StorageNode {
-    pub fn new(peer_id: PeerId, price_per_gb: u64) -> Self {
+    pub fn new(peer_id: PeerId, price_per_byte_epoch: TokenAmount) -> Self {
         StorageNode {
             peer_id,
             stored_files: HashMap::new(),
             available_space: MAX_STORAGE,
             reputation: 100, // Start with a base reputation
-            price_per_gb,
+            price_per_byte_epoch,
+            retrieval_price_per_byte: TokenAmount::ZERO, // Retrievals are free until the SP publishes a price
+            storage_earnings: TokenAmount::ZERO,
+            retrieval_earnings: TokenAmount::ZERO,
+            region: None,
+            draining: false,
+            pricing_policy: None,
         }
     }
 
-    pub fn price_per_gb(&self) -> u64 {
-        self.price_per_gb
+    /// Price for storing one byte for one epoch.
+    pub fn price_per_byte_epoch(&self) -> TokenAmount {
+        self.price_per_byte_epoch
     }
 
     pub fn reputation(&self) -> u64 {
         self.reputation
     }
 
+    pub fn set_reputation(&mut self, reputation: u64) {
+        self.reputation = reputation;
+    }
+
     pub fn increase_reputation(&mut self, amount: u64) {
         self.reputation += amount;
     }
@@ -44,6 +76,30 @@ impl StorageNode {
         self.reputation = self.reputation.saturating_sub(amount);
     }
 
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    pub fn set_region(&mut self, region: Option<String>) {
+        self.region = region;
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    pub fn set_draining(&mut self, draining: bool) {
+        self.draining = draining;
+    }
+
+    pub fn pricing_policy(&self) -> Option<&PricingPolicy> {
+        self.pricing_policy.as_ref()
+    }
+
+    pub fn set_pricing_policy(&mut self, pricing_policy: Option<PricingPolicy>) {
+        self.pricing_policy = pricing_policy;
+    }
+
     pub fn peer_id(&self) -> &PeerId {
         &self.peer_id
     }
@@ -56,7 +112,11 @@ impl StorageNode {
         MAX_STORAGE
     }
 
+    /// Stores a new file. A file already stored under the same name is never replaced.
     pub fn store_file(&mut self, filename: String, data: Vec<u8>) -> Result<(), &'static str> {
+        if self.stored_files.contains_key(&filename) {
+            return Err("A file with this name is already stored");
+        }
         if data.len() > self.available_space {
             return Err("Not enough space to store the file");
         }
@@ -65,6 +125,17 @@ impl StorageNode {
         Ok(())
     }
 
+    /// Appends streamed bytes to a file whose first chunk was stored with `store_file`.
+    pub fn append_file_chunk(&mut self, filename: &str, chunk: &[u8]) -> Result<(), &'static str> {
+        let file = self.stored_files.get_mut(filename).ok_or("File not found")?;
+        if chunk.len() > self.available_space {
+            return Err("Not enough space to store the chunk");
+        }
+        self.available_space -= chunk.len();
+        file.extend_from_slice(chunk);
+        Ok(())
+    }
+
     pub fn get_file(&self, filename: &str) -> Option<&Vec<u8>> {
         self.stored_files.get(filename)
     }
@@ -95,11 +166,47 @@ impl StorageNode {
         }
     }
 
-    pub fn get_price_per_gb(&self) -> u64 {
-        self.price_per_gb
+    /// Returns space taken by `reserve_space` that will no longer be used.
+    pub fn release_space(&mut self, size: usize) {
+        self.available_space = (self.available_space + size).min(MAX_STORAGE);
+    }
+
+    /// Stores a file into space already taken with `reserve_space`. Fails, leaving the
+    /// reservation in place, if a file is already stored under the same name.
+    pub fn store_reserved_file(&mut self, filename: String, data: Vec<u8>) -> Result<(), &'static str> {
+        if self.stored_files.contains_key(&filename) {
+            return Err("A file with this name is already stored");
+        }
+        self.stored_files.insert(filename, data);
+        Ok(())
+    }
+
+    pub fn set_price_per_byte_epoch(&mut self, price: TokenAmount) {
+        self.price_per_byte_epoch = price;
+    }
+
+    /// Price for serving one byte.
+    pub fn retrieval_price_per_byte(&self) -> TokenAmount {
+        self.retrieval_price_per_byte
+    }
+
+    pub fn set_retrieval_price_per_byte(&mut self, price: TokenAmount) {
+        self.retrieval_price_per_byte = price;
+    }
+
+    pub fn storage_earnings(&self) -> TokenAmount {
+        self.storage_earnings
+    }
+
+    pub fn retrieval_earnings(&self) -> TokenAmount {
+        self.retrieval_earnings
+    }
+
+    pub fn record_storage_earnings(&mut self, amount: TokenAmount) {
+        self.storage_earnings = self.storage_earnings.saturating_add(amount);
     }
 
-    pub fn set_price_per_gb(&mut self, price: u64) {
-        self.price_per_gb = price;
+    pub fn record_retrieval_earnings(&mut self, amount: TokenAmount) {
+        self.retrieval_earnings = self.retrieval_earnings.saturating_add(amount);
     }
 }
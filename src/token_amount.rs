@@ -0,0 +1,117 @@
+use std::fmt;
+use std::ops::{Sub, SubAssign};
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+
+/// A token quantity with 18 decimal places, stored as a count of base units like an
+/// on-chain ERC20 balance. `TokenAmount::from_tokens(1)` is 10^18 base units.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TokenAmount(u128);
+
+impl TokenAmount {
+    pub const DECIMALS: u32 = 18;
+    pub const BASE_UNITS_PER_TOKEN: u128 = 10u128.pow(Self::DECIMALS);
+    pub const ZERO: TokenAmount = TokenAmount(0);
+    pub const MAX: TokenAmount = TokenAmount(u128::MAX);
+
+    pub const fn from_base_units(base_units: u128) -> Self {
+        TokenAmount(base_units)
+    }
+
+    pub const fn from_tokens(tokens: u64) -> Self {
+        TokenAmount(tokens as u128 * Self::BASE_UNITS_PER_TOKEN)
+    }
+
+    pub const fn base_units(self) -> u128 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: TokenAmount) -> Option<TokenAmount> {
+        self.0.checked_add(other.0).map(TokenAmount)
+    }
+
+    pub fn checked_sub(self, other: TokenAmount) -> Option<TokenAmount> {
+        self.0.checked_sub(other.0).map(TokenAmount)
+    }
+
+    /// For running totals that are only reported, never paid out.
+    pub fn saturating_add(self, other: TokenAmount) -> TokenAmount {
+        TokenAmount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: TokenAmount) -> TokenAmount {
+        TokenAmount(self.0.saturating_sub(other.0))
+    }
+
+    /// Multiplies by a unit count, e.g. a per-byte price by a number of bytes.
+    pub fn checked_mul(self, units: u128) -> Option<TokenAmount> {
+        self.0.checked_mul(units).map(TokenAmount)
+    }
+
+    /// `self * numerator / denominator`, rounded down. Used to split an amount into shares.
+    pub fn proportion(self, numerator: u64, denominator: u64) -> TokenAmount {
+        if denominator == 0 {
+            return TokenAmount::ZERO;
+        }
+        let (numerator, denominator) = (numerator as u128, denominator as u128);
+        // Split to avoid overflowing u128 on very large amounts
+        let whole = self.0 / denominator * numerator;
+        let remainder = self.0 % denominator * numerator / denominator;
+        TokenAmount(whole + remainder)
+    }
+}
+
+impl Sub for TokenAmount {
+    type Output = TokenAmount;
+
+    fn sub(self, other: TokenAmount) -> TokenAmount {
+        TokenAmount(self.0 - other.0)
+    }
+}
+
+impl SubAssign for TokenAmount {
+    fn sub_assign(&mut self, other: TokenAmount) {
+        self.0 -= other.0;
+    }
+}
+
+impl fmt::Display for TokenAmount {
+    /// Whole tokens with trailing zero decimals trimmed, e.g. `1.5` or `0.000000000000000312`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whole = self.0 / Self::BASE_UNITS_PER_TOKEN;
+        let fraction = self.0 % Self::BASE_UNITS_PER_TOKEN;
+        if fraction == 0 {
+            return write!(f, "{}", whole);
+        }
+        let fraction = format!("{:0width$}", fraction, width = Self::DECIMALS as usize);
+        write!(f, "{}.{}", whole, fraction.trim_end_matches('0'))
+    }
+}
+
+impl FromStr for TokenAmount {
+    type Err = String;
+
+    /// Parses a decimal token quantity such as `12`, `0.5` or `0.000000000000000001`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (whole, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+        if fraction.len() > Self::DECIMALS as usize {
+            return Err(format!("At most {} decimal places are supported", Self::DECIMALS));
+        }
+        let whole: u128 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| format!("Invalid token amount: {}", value))? };
+        let fraction: u128 = if fraction.is_empty() {
+            0
+        } else {
+            let digits: u128 = fraction.parse().map_err(|_| format!("Invalid token amount: {}", value))?;
+            digits * 10u128.pow(Self::DECIMALS - fraction.len() as u32)
+        };
+        whole.checked_mul(Self::BASE_UNITS_PER_TOKEN)
+            .and_then(|base_units| base_units.checked_add(fraction))
+            .map(TokenAmount)
+            .ok_or_else(|| format!("Token amount too large: {}", value))
+    }
+}
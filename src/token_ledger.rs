@@ -0,0 +1,35 @@
+use libp2p::PeerId;
+use crate::erc20::{ERC20, TokenError};
+use crate::token_amount::TokenAmount;
+
+/// The ERC20 surface the rest of the system needs from a token backend, whether the
+/// ledger lives in process or in a contract on an Ethereum node.
+pub trait TokenLedger {
+    fn balance_of(&self, account: &PeerId) -> Result<TokenAmount, TokenError>;
+    fn allowance(&self, owner: &PeerId, spender: &PeerId) -> Result<TokenAmount, TokenError>;
+    fn transfer(&mut self, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError>;
+    fn approve(&mut self, owner: &PeerId, spender: &PeerId, amount: TokenAmount) -> Result<(), TokenError>;
+    fn transfer_from(&mut self, spender: &PeerId, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError>;
+}
+
+impl TokenLedger for ERC20 {
+    fn balance_of(&self, account: &PeerId) -> Result<TokenAmount, TokenError> {
+        Ok(ERC20::balance_of(self, account))
+    }
+
+    fn allowance(&self, owner: &PeerId, spender: &PeerId) -> Result<TokenAmount, TokenError> {
+        Ok(ERC20::allowance(self, owner, spender))
+    }
+
+    fn transfer(&mut self, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        ERC20::transfer(self, from, to, amount)
+    }
+
+    fn approve(&mut self, owner: &PeerId, spender: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        ERC20::approve(self, owner, spender, amount)
+    }
+
+    fn transfer_from(&mut self, spender: &PeerId, from: &PeerId, to: &PeerId, amount: TokenAmount) -> Result<(), TokenError> {
+        ERC20::transfer_from(self, spender, from, to, amount)
+    }
+}
@@ -1,9 +1,12 @@
 use warp::{Filter, ws::Message, ws::WebSocket};
 use futures::{StreamExt, SinkExt};
 use tokio::sync::broadcast;
+use pioneerfs::faucet::FaucetError;
 use pioneerfs::network::Network;
 use serde_json::json;
 use std::sync::{Arc, Mutex};
+use libp2p::PeerId;
+use warp::http::StatusCode;
 
 pub async fn start_webui(network: Arc<Mutex<Network>>, tx: broadcast::Sender<String>) {
     let network_status = {
@@ -29,11 +32,37 @@ pub async fn start_webui(network: Arc<Mutex<Network>>, tx: broadcast::Sender<Str
         let tx_clone = tx.clone();
         warp::path("run_tests").map(move || {
             let mut network = network.lock().unwrap();
-            crate::run_advanced_network_tests(&mut *network, tx_clone.clone());
+            crate::run_advanced_network_tests(&mut network, tx_clone.clone());
             warp::reply::html("Advanced network tests started")
         })
     };
 
+    let faucet = {
+        let network = Arc::clone(&network);
+        warp::post().and(warp::path!("faucet" / String)).map(move |peer_id: String| {
+            let Ok(peer_id) = peer_id.parse::<PeerId>() else {
+                return warp::reply::with_status(warp::reply::json(&json!({ "error": "Invalid peer id" })), StatusCode::BAD_REQUEST);
+            };
+            match network.lock().unwrap().request_faucet_funds(&peer_id) {
+                Ok(amount) => warp::reply::with_status(warp::reply::json(&json!({ "peer_id": peer_id.to_string(), "amount": amount.to_string() })), StatusCode::OK),
+                Err(e) => warp::reply::with_status(warp::reply::json(&json!({ "error": e.to_string() })), faucet_error_status(&e)),
+            }
+        })
+    };
+
+    let reputation = {
+        let network = Arc::clone(&network);
+        warp::get().and(warp::path!("reputation" / String)).map(move |peer_id: String| {
+            let Ok(peer_id) = peer_id.parse::<PeerId>() else {
+                return warp::reply::with_status(warp::reply::json(&json!({ "error": "Invalid peer id" })), StatusCode::BAD_REQUEST);
+            };
+            match network.lock().unwrap().get_reputation(&peer_id) {
+                Ok(breakdown) => warp::reply::with_status(warp::reply::json(&breakdown), StatusCode::OK),
+                Err(e) => warp::reply::with_status(warp::reply::json(&json!({ "error": e })), StatusCode::NOT_FOUND),
+            }
+        })
+    };
+
     let ws_route = warp::path("ws")
         .and(warp::ws())
         .and(with_broadcast(tx.clone()))
@@ -41,11 +70,20 @@ pub async fn start_webui(network: Arc<Mutex<Network>>, tx: broadcast::Sender<Str
             ws.on_upgrade(move |socket| handle_socket(socket, tx))
         });
 
-    warp::serve(network_status.or(index).or(run_tests).or(ws_route))
+    warp::serve(network_status.or(index).or(run_tests).or(faucet).or(reputation).or(ws_route))
         .run(([127, 0, 0, 1], 3030))
         .await;
 }
 
+fn faucet_error_status(error: &FaucetError) -> StatusCode {
+    match error {
+        FaucetError::RateLimited { .. } | FaucetError::CapReached { .. } => StatusCode::TOO_MANY_REQUESTS,
+        FaucetError::UnknownPeer => StatusCode::NOT_FOUND,
+        FaucetError::TreasuryEmpty { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        FaucetError::Ledger(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
 fn with_broadcast(
     tx: broadcast::Sender<String>,
 ) -> impl Filter<Extract = (broadcast::Sender<String>,), Error = std::convert::Infallible> + Clone {
@@ -1,4 +1,5 @@
 use pioneerfs::network::Network;
+use pioneerfs::TokenAmount;
 use libp2p::PeerId;
 
 #[cfg(test)]
@@ -6,12 +7,15 @@ mod advanced_network_tests {
     use super::*;
 
     fn setup() -> Network {
-        let mut network = Network::new();
+        let mut network = Network::new().unwrap();
         // Add some clients and storage nodes
-        network.add_client(PeerId::random());
-        network.add_client(PeerId::random());
-        network.add_storage_node(PeerId::random(), 10); // 10 tokens per GB
-        network.add_storage_node(PeerId::random(), 15); // 15 tokens per GB
+        for _ in 0..2 {
+            let client_id = PeerId::random();
+            network.add_client(client_id);
+            network.token.disburse(&client_id, TokenAmount::from_tokens(1_000), "test funding").unwrap();
+        }
+        network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10)); // per byte per epoch
+        network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(15));
         network
     }
 
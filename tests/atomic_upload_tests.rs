@@ -0,0 +1,82 @@
+use libp2p::PeerId;
+use pioneerfs::TokenAmount;
+
+mod common;
+use common::setup;
+
+#[test]
+fn test_upload_rolls_back_when_one_target_is_full() {
+    let (mut network, client_id, nodes) = setup(3);
+    let full_node = nodes[2];
+    let nearly_all = network.storage_nodes()[&full_node].available_space() - 4;
+    network.storage_nodes.get_mut(&full_node).unwrap().reserve_space(nearly_all).unwrap();
+    let space_before: Vec<usize> = nodes.iter().map(|id| network.storage_nodes()[id].available_space()).collect();
+    let balance_before = network.get_balance(&client_id);
+
+    let result = network.upload_file(&client_id, "too_big.txt".to_string(), b"more than four bytes".to_vec(), 3);
+
+    assert!(result.is_err());
+    for (node_id, space) in nodes.iter().zip(space_before) {
+        let storage_node = &network.storage_nodes()[node_id];
+        assert!(storage_node.get_file("too_big.txt").is_none());
+        assert_eq!(storage_node.available_space(), space);
+    }
+    assert_eq!(network.get_balance(&client_id), balance_before);
+    assert!(network.deals.is_empty());
+    assert!(network.get_file_locations(&client_id, "too_big.txt").is_err());
+    assert!(network.token.audit());
+}
+
+#[test]
+fn test_upload_without_funds_touches_nothing() {
+    let (mut network, client_id, nodes) = setup(2);
+    let poor_client = PeerId::random();
+    network.add_client(poor_client);
+
+    assert!(network.upload_file(&poor_client, "unpaid.txt".to_string(), b"unpaid".to_vec(), 2).is_err());
+
+    for node_id in &nodes {
+        assert_eq!(network.storage_nodes()[node_id].used_space(), 0);
+    }
+    assert!(network.deals.is_empty());
+    assert!(network.upload_file(&client_id, "paid.txt".to_string(), b"paid".to_vec(), 2).is_ok());
+}
+
+#[test]
+fn test_committed_upload_accounts_for_reserved_space() {
+    let (mut network, client_id, nodes) = setup(2);
+    let data = b"exactly accounted".to_vec();
+
+    network.upload_file(&client_id, "file.txt".to_string(), data.clone(), 2).unwrap();
+    // Uploading the same name again is refused rather than overwriting the copy or
+    // leaking its space
+    let balance = network.get_balance(&client_id);
+    assert!(network.upload_file(&client_id, "file.txt".to_string(), b"replacement".to_vec(), 2).is_err());
+
+    for node_id in &nodes {
+        assert_eq!(network.storage_nodes()[node_id].used_space(), data.len());
+        assert_eq!(network.storage_nodes()[node_id].get_file("file.txt").unwrap(), &data);
+    }
+    assert_eq!(network.get_balance(&client_id), balance);
+    assert_eq!(network.get_file_locations(&client_id, "file.txt").unwrap().len(), 2);
+}
+
+#[test]
+fn test_clients_sharing_a_filename_get_different_nodes() {
+    let (mut network, client_id, nodes) = setup(3);
+    let other_client = PeerId::random();
+    network.add_client(other_client);
+    network.token.disburse(&other_client, TokenAmount::from_tokens(1_000), "test funding").unwrap();
+
+    let first = network.upload_file(&client_id, "report.txt".to_string(), b"first".to_vec(), 2).unwrap();
+    let second = network.upload_file(&other_client, "report.txt".to_string(), b"second".to_vec(), 1).unwrap();
+
+    assert!(!first.contains(&second[0]));
+    for node_id in &first {
+        assert_eq!(network.get_file_content(node_id, "report.txt").unwrap(), b"first");
+    }
+    assert_eq!(network.get_file_content(&second[0], "report.txt").unwrap(), b"second");
+    // Every SP now holds the name, so there is nowhere left to put a third copy
+    assert!(network.upload_file(&other_client, "report.txt".to_string(), b"third".to_vec(), 1).is_err());
+    assert_eq!(nodes.iter().map(|node_id| network.storage_nodes()[node_id].used_space()).sum::<usize>(), 5 + 5 + 6);
+}
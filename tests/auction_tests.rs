@@ -0,0 +1,127 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::auction::{AuctionStatus, AuctionTerms, BidOutcome};
+use pioneerfs::clock::{ManualClock, Timestamp};
+use pioneerfs::{Network, TokenAmount};
+
+mod common;
+use common::{setup_with_clock, units};
+
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+
+fn setup(storage_nodes: usize) -> (Network, ManualClock, PeerId, Vec<PeerId>) {
+    setup_with_clock(storage_nodes, Timestamp::from_millis(1_000))
+}
+
+fn terms(replication_factor: usize, max_price: u128) -> AuctionTerms {
+    AuctionTerms {
+        replication_factor,
+        duration: Duration::from_secs(2 * 60 * 60), // two epochs
+        max_price_per_byte_epoch: units(max_price),
+        bidding_window: WINDOW,
+    }
+}
+
+#[test]
+fn test_lowest_bids_win_and_losers_are_notified() {
+    let (mut network, clock, client_id, nodes) = setup(4);
+    let data = b"auctioned".to_vec();
+    let balance_before = network.get_balance(&client_id);
+    let request_id = network.post_storage_request(&client_id, "auction.txt".to_string(), data.clone(), terms(2, 10)).unwrap();
+
+    network.submit_bid(request_id, &nodes[0], units(7)).unwrap();
+    network.submit_bid(request_id, &nodes[1], units(3)).unwrap();
+    network.submit_bid(request_id, &nodes[2], units(5)).unwrap();
+    network.submit_bid(request_id, &nodes[3], units(9)).unwrap();
+    // An SP may lower its bid while the window is open
+    network.submit_bid(request_id, &nodes[3], units(4)).unwrap();
+
+    clock.advance(WINDOW);
+    let mut winners = network.close_storage_request(request_id).unwrap();
+    winners.sort();
+    let mut expected = vec![nodes[1], nodes[3]];
+    expected.sort();
+    assert_eq!(winners, expected);
+
+    // Each winner is paid its own bid: 9 bytes for 2 epochs
+    assert_eq!(network.get_balance(&client_id), balance_before - units(9 * 2 * (3 + 4)));
+    assert_eq!(network.deals.len(), 2);
+    for winner in &winners {
+        assert_eq!(network.get_file_content(winner, "auction.txt").unwrap(), data);
+    }
+    assert_eq!(network.get_file_locations(&client_id, "auction.txt").unwrap().len(), 2);
+
+    assert_eq!(network.get_auction_notices(&nodes[1])[0].outcome, BidOutcome::Won { price_per_byte_epoch: units(3) });
+    for loser in [nodes[0], nodes[2]] {
+        assert_eq!(network.get_auction_notices(&loser)[0].outcome, BidOutcome::Lost { clearing_price: Some(units(4)) });
+    }
+    assert!(matches!(network.storage_requests[&request_id].status, AuctionStatus::Awarded(_)));
+}
+
+#[test]
+fn test_bids_must_qualify_and_arrive_in_time() {
+    let (mut network, clock, client_id, nodes) = setup(2);
+    let request_id = network.post_storage_request(&client_id, "strict.txt".to_string(), b"strict".to_vec(), terms(1, 5)).unwrap();
+
+    assert!(network.submit_bid(request_id, &nodes[0], units(6)).is_err(), "Bid above the client's maximum");
+    assert!(network.submit_bid(request_id, &PeerId::random(), units(1)).is_err(), "Unknown SP");
+    network.submit_bid(request_id, &nodes[0], units(5)).unwrap();
+    assert!(network.close_storage_request(request_id).is_err(), "Window still open");
+
+    clock.advance(WINDOW);
+    assert!(network.submit_bid(request_id, &nodes[1], units(1)).is_err(), "Window closed");
+    assert_eq!(network.close_expired_storage_requests().len(), 1);
+    assert!(network.close_storage_request(request_id).is_err(), "Already closed");
+}
+
+#[test]
+fn test_auction_without_enough_bids_charges_nothing() {
+    let (mut network, clock, client_id, nodes) = setup(3);
+    let balance_before = network.get_balance(&client_id);
+    let request_id = network.post_storage_request(&client_id, "thin.txt".to_string(), b"thin market".to_vec(), terms(2, 10)).unwrap();
+    network.submit_bid(request_id, &nodes[0], units(2)).unwrap();
+
+    clock.advance(WINDOW);
+    assert!(network.close_storage_request(request_id).is_err());
+
+    assert_eq!(network.get_balance(&client_id), balance_before);
+    assert!(network.deals.is_empty());
+    assert_eq!(network.storage_nodes()[&nodes[0]].used_space(), 0);
+    assert_eq!(network.get_auction_notices(&nodes[0])[0].outcome, BidOutcome::Lost { clearing_price: None });
+    assert!(matches!(network.storage_requests[&request_id].status, AuctionStatus::Failed(_)));
+}
+
+#[test]
+fn test_posting_escrows_the_maximum_budget_until_close() {
+    let (mut network, clock, client_id, nodes) = setup(2);
+    let balance_before = network.get_balance(&client_id);
+    // 6 bytes for 2 epochs at up to 5 per byte-epoch, twice
+    let request_id = network.post_storage_request(&client_id, "locked.txt".to_string(), b"locked".to_vec(), terms(2, 5)).unwrap();
+    let budget = units(2 * 6 * 2 * 5);
+
+    assert_eq!(network.get_balance(&client_id), balance_before - budget);
+    assert_eq!(network.token.escrow_balance(network.storage_requests[&request_id].escrow_id), budget);
+    // The escrowed budget cannot be spent elsewhere while bids come in
+    let spare = network.get_balance(&client_id);
+    network.token.burn(&client_id, spare).unwrap();
+
+    network.submit_bid(request_id, &nodes[0], units(2)).unwrap();
+    network.submit_bid(request_id, &nodes[1], units(5)).unwrap();
+    clock.advance(WINDOW);
+    network.close_storage_request(request_id).unwrap();
+
+    // Only the winning bids stay locked; the rest of the budget is back with the client
+    assert_eq!(network.get_balance(&client_id), budget - units(6 * 2 * (2 + 5)));
+    assert_eq!(network.token.escrow_balance(network.storage_requests[&request_id].escrow_id), TokenAmount::ZERO);
+}
+
+#[test]
+fn test_request_beyond_the_clients_balance_is_refused() {
+    let (mut network, _, client_id, _) = setup(1);
+    let balance = network.get_balance(&client_id);
+    let too_expensive = AuctionTerms { max_price_per_byte_epoch: balance, ..terms(1, 0) };
+
+    assert!(network.post_storage_request(&client_id, "dear.txt".to_string(), b"dear".to_vec(), too_expensive).is_err());
+    assert!(network.storage_requests.is_empty());
+    assert_eq!(network.get_balance(&client_id), balance);
+}
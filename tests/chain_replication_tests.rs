@@ -0,0 +1,113 @@
+use libp2p::PeerId;
+use pioneerfs::{Network, TokenAmount};
+
+#[test]
+fn test_chain_replication_pays_downstream_sps_from_client_allowance() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    for _ in 0..5 {
+        network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+    }
+
+    let filename = "chained.txt";
+    let first = network.upload_file(&client_id, filename.to_string(), b"chain me".to_vec(), 1).unwrap()[0];
+    let balance_after_upload = network.get_balance(&client_id);
+    // 8 bytes for 24 epochs at 10 base units per byte-epoch
+    let replica_cost = TokenAmount::from_base_units(8 * 24 * 10);
+
+    network.replicate_file(&client_id, filename, 3).unwrap();
+
+    let payments: Vec<_> = network.get_replication_payments(&client_id, filename).into_iter().cloned().collect();
+    assert_eq!(payments.len(), 3);
+    for payment in &payments {
+        assert_eq!(payment.payer_id, first, "The first SP pays every downstream SP");
+        assert_eq!(payment.amount, replica_cost);
+        // The payment is escrowed and released to the SP one epoch at a time
+        let deal = network.deals_by_storage_node(&payment.storage_node_id)[0];
+        assert_eq!(network.token.escrow_balance(deal.escrow_id().unwrap()), replica_cost);
+        assert_eq!(network.get_balance(&payment.storage_node_id), TokenAmount::ZERO);
+    }
+    assert_eq!(network.get_balance(&client_id), balance_after_upload - TokenAmount::from_base_units(3 * 8 * 24 * 10));
+    assert_eq!(network.get_balance(&first), TokenAmount::ZERO, "The first SP only spends the client's money");
+    assert_eq!(network.token.allowance(&client_id, &first), TokenAmount::ZERO);
+    assert_eq!(network.get_file_locations(&client_id, filename).unwrap().len(), 4);
+
+    network.advance_epoch();
+    for payment in &payments {
+        assert_eq!(network.get_balance(&payment.storage_node_id), replica_cost.proportion(1, 24));
+    }
+}
+
+#[test]
+fn test_chain_replication_stops_when_client_cannot_pay() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+    network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+    network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+
+    let filename = "underfunded.txt";
+    network.upload_file(&client_id, filename.to_string(), b"data".to_vec(), 1).unwrap();
+    // Leave the client with enough for exactly one downstream replica
+    let spare = network.get_balance(&client_id) - TokenAmount::from_base_units(4 * 24 * 10);
+    network.token.burn(&client_id, spare).unwrap();
+
+    assert!(network.replicate_file(&client_id, filename, 2).is_err());
+
+    assert_eq!(network.get_replication_payments(&client_id, filename).len(), 1);
+    assert_eq!(network.get_file_locations(&client_id, filename).unwrap().len(), 2);
+    assert_eq!(network.get_balance(&client_id), TokenAmount::ZERO);
+}
+
+#[test]
+fn test_chain_replicas_are_audited_and_refunded_when_slashed() {
+    use pioneerfs::deal::DealState;
+
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+    network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+
+    let filename = "audited.txt";
+    network.upload_file(&client_id, filename.to_string(), b"data".to_vec(), 1).unwrap();
+    network.replicate_file(&client_id, filename, 1).unwrap();
+    let replica = network.get_replication_payments(&client_id, filename)[0].storage_node_id;
+    let deal_id = network.deals_by_storage_node(&replica)[0].id();
+    let balance_before = network.get_balance(&client_id);
+
+    network.storage_nodes.get_mut(&replica).unwrap().remove_file(filename).unwrap();
+    network.advance_epoch();
+
+    assert_eq!(network.get_deal(deal_id).unwrap().state(), DealState::Slashed);
+    assert_eq!(network.get_balance(&replica), TokenAmount::ZERO);
+    assert_eq!(network.get_balance(&client_id), balance_before.checked_add(TokenAmount::from_base_units(4 * 24 * 10)).unwrap());
+}
+
+#[test]
+fn test_higher_replication_is_paid_through_the_chain() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    for _ in 0..3 {
+        network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+    }
+
+    let filename = "raised.txt";
+    network.upload_file(&client_id, filename.to_string(), b"data".to_vec(), 1).unwrap();
+    let balance_before = network.get_balance(&client_id);
+
+    network.request_higher_replication(&client_id, filename, 3).unwrap();
+
+    let payments = network.get_replication_payments(&client_id, filename);
+    assert_eq!(payments.len(), 2);
+    assert_eq!(network.deals_for_file(&client_id, filename).len(), 3);
+    assert_eq!(network.get_balance(&client_id), balance_before - TokenAmount::from_base_units(2 * 4 * 24 * 10));
+    assert!(network.request_higher_replication(&client_id, filename, 4).is_err(), "every SP already holds the file");
+}
@@ -0,0 +1,75 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::Network;
+use pioneerfs::clock::{Clock, ManualClock, Timestamp};
+use pioneerfs::deal::{Deal, DealState};
+
+mod common;
+use common::setup_with_clock;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn setup(storage_nodes: usize) -> (Network, ManualClock, PeerId) {
+    let (network, clock, client_id, _) = setup_with_clock(storage_nodes, Timestamp::from_millis(1_700_000_000_000));
+    (network, clock, client_id)
+}
+
+#[test]
+fn test_deals_expire_when_the_clock_passes_their_term() {
+    let (mut network, clock, client_id) = setup(1);
+    network.grace_period = Duration::ZERO;
+    network.upload_file(&client_id, "daily.txt".to_string(), b"daily".to_vec(), 1).unwrap();
+    let balance_before = network.get_balance(&client_id);
+
+    clock.advance(DAY - Duration::from_secs(1));
+    network.check_deals();
+    assert_eq!(network.deals_in_state(DealState::Active).len(), 1);
+    assert!(network.download_file(&client_id, "daily.txt").is_ok());
+
+    clock.advance(Duration::from_secs(1));
+    network.check_deals();
+    assert_eq!(network.deals_in_state(DealState::Expired).len(), 1);
+    assert!(network.get_file_locations(&client_id, "daily.txt").is_err());
+    // Nothing was paid out by epoch, so the whole escrow comes back
+    assert!(network.get_balance(&client_id) > balance_before);
+    assert_eq!(network.deals[&0].entered(DealState::Expired).unwrap().at, clock.now());
+}
+
+#[test]
+fn test_renewal_extends_the_term() {
+    let (mut network, clock, client_id) = setup(1);
+    network.grace_period = Duration::ZERO;
+    let sp_id = network.upload_file(&client_id, "renewed.txt".to_string(), b"renewed".to_vec(), 1).unwrap()[0];
+    let started = clock.now();
+
+    clock.advance(DAY / 2);
+    network.renew_deal(&client_id, &sp_id, "renewed.txt", DAY / 2).unwrap();
+    let deal = &network.deals_for_file(&client_id, "renewed.txt")[0];
+    assert_eq!(deal.start_time(), Some(started));
+    assert_eq!(deal.ends_at(), Some(started + DAY + DAY / 2));
+
+    clock.advance(DAY * 3 / 4);
+    network.check_deals();
+    assert_eq!(network.deals_in_state(DealState::Active).len(), 1, "a renewed deal outlives its first term");
+
+    clock.advance(DAY / 4);
+    network.check_deals();
+    assert_eq!(network.deals_in_state(DealState::Expired).len(), 1);
+}
+
+#[test]
+fn test_deal_times_survive_serialisation() {
+    let (mut network, clock, client_id) = setup(1);
+    network.upload_file(&client_id, "saved.txt".to_string(), b"saved".to_vec(), 1).unwrap();
+    let started = clock.now();
+    clock.advance(DAY / 3);
+
+    let json = serde_json::to_string(&network.deals[&0]).unwrap();
+    let restored: Deal = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(restored.start_time(), Some(started));
+    assert_eq!(restored.ends_at(), Some(started + DAY));
+    assert!(!restored.has_run_out_at(clock.now()), "a restored deal keeps its original term");
+    assert!(restored.has_run_out_at(started + DAY));
+    assert_eq!(restored.history().last().unwrap().at, started);
+}
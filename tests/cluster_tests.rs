@@ -0,0 +1,84 @@
+use pioneerfs::cluster::TestCluster;
+use pioneerfs::protocol::{BlockRequest, BlockResponse, ErrorCode};
+
+#[tokio::test]
+async fn test_every_peer_has_its_own_identity() {
+    let cluster = TestCluster::new(4, 2).await.unwrap();
+    let mut peer_ids = cluster.storage_node_ids();
+    peer_ids.extend(cluster.clients.iter().map(|client| *client.peer_id()));
+    peer_ids.sort();
+    peer_ids.dedup();
+    assert_eq!(peer_ids.len(), 6);
+    for node in &cluster.storage_nodes {
+        assert_eq!(node.storage_node().unwrap().lock().unwrap().peer_id(), node.peer_id());
+    }
+}
+
+#[tokio::test]
+async fn test_peers_answer_requests_from_their_own_state() {
+    let cluster = TestCluster::new(2, 1).await.unwrap();
+    let holder = cluster.upload(0, "one.txt", b"one copy".to_vec(), 1).await.unwrap()[0];
+    let other = *cluster.storage_node_ids().iter().find(|node_id| **node_id != holder).unwrap();
+    let client = &cluster.clients[0];
+
+    assert_eq!(client.fetch(holder, "one.txt").await.unwrap(), b"one copy");
+    assert_eq!(client.fetch(other, "one.txt").await.unwrap_err(), "Block not found");
+
+    // Clients do not store data for others
+    let response = cluster.storage_nodes[0].request(*client.peer_id(), BlockRequest::HasBlock { name: "one.txt".to_string() }).await;
+    assert_eq!(response, Ok(BlockResponse::error(ErrorCode::NotStorageNode, "Peer is not a storage node")));
+}
+
+#[tokio::test]
+async fn test_uploads_are_forwarded_from_node_to_node() {
+    let cluster = TestCluster::new(3, 1).await.unwrap();
+    let chain = cluster.upload(0, "chained.txt", b"passed along".to_vec(), 3).await.unwrap();
+    let client = &cluster.clients[0];
+
+    for node_id in &chain {
+        assert_eq!(client.fetch(*node_id, "chained.txt").await.unwrap(), b"passed along");
+    }
+    // The client only sent the file to the head of the chain, but every copy is its own
+    let response = cluster.storage_nodes.iter().find(|node| node.peer_id() == &chain[0]).unwrap()
+        .request(chain[1], BlockRequest::Delete { name: "chained.txt".to_string() }).await.unwrap();
+    assert_eq!(response, BlockResponse::error(ErrorCode::Unauthorized, "Block belongs to another peer"));
+    for node_id in &chain {
+        client.delete(*node_id, "chained.txt").await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_a_failing_node_unwinds_the_whole_chain() {
+    let cluster = TestCluster::new(3, 1).await.unwrap();
+    let full = cluster.storage_nodes[2].storage_node().unwrap();
+    let space = full.lock().unwrap().available_space();
+    full.lock().unwrap().reserve_space(space).unwrap();
+
+    assert!(cluster.upload(0, "unwound.txt", b"all or nothing".to_vec(), 3).await.is_err());
+    assert!(cluster.stored_on("unwound.txt").is_empty());
+}
+
+#[tokio::test]
+async fn test_an_unwound_chain_keeps_blocks_it_did_not_create() {
+    let cluster = TestCluster::new(3, 1).await.unwrap();
+    let client = &cluster.clients[0];
+    let nodes = cluster.storage_node_ids();
+    let (head, holder, full) = (nodes[0], nodes[1], nodes[2]);
+    client.store(head, "kept.txt", b"old".to_vec()).await.unwrap();
+    client.store(holder, "kept.txt", b"old".to_vec()).await.unwrap();
+    let full_node = cluster.storage_nodes[2].storage_node().unwrap();
+    let space = full_node.lock().unwrap().available_space();
+    full_node.lock().unwrap().reserve_space(space).unwrap();
+
+    let replicate = |forward_to| BlockRequest::Replicate { name: "kept.txt".to_string(), data: b"new".to_vec(), owner: *client.peer_id(), forward_to };
+    // The head replaces its copy, then the chain fails further down
+    assert!(matches!(client.request(head, replicate(vec![full])).await.unwrap(), BlockResponse::Error { .. }));
+    // A forwarded copy may not replace the client's block
+    let response = client.request(head, replicate(vec![holder])).await.unwrap();
+    assert_eq!(response, BlockResponse::error(ErrorCode::Unauthorized, "Only the owner may replace a block"));
+
+    for node_id in [head, holder] {
+        assert_eq!(client.fetch(node_id, "kept.txt").await.unwrap(), b"old");
+    }
+    client.delete(head, "kept.txt").await.unwrap();
+}
@@ -0,0 +1,48 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use libp2p::PeerId;
+use pioneerfs::clock::{ManualClock, Timestamp};
+use pioneerfs::{Network, TokenAmount};
+
+pub fn units(base_units: u128) -> TokenAmount {
+    TokenAmount::from_base_units(base_units)
+}
+
+pub fn pio(tokens: u64) -> TokenAmount {
+    TokenAmount::from_tokens(tokens)
+}
+
+/// What `setup` funds the client with.
+pub fn initial_balance() -> TokenAmount {
+    pio(1_000)
+}
+
+pub fn add_storage_node(network: &mut Network, price_per_byte_epoch: TokenAmount) -> PeerId {
+    let sp_id = PeerId::random();
+    network.add_storage_node(sp_id, price_per_byte_epoch);
+    sp_id
+}
+
+/// A network with one funded client and one SP per price, in base units per byte-epoch.
+pub fn setup_with_prices(prices: &[u128]) -> (Network, PeerId, Vec<PeerId>) {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, initial_balance(), "test funding").unwrap();
+    let nodes = prices.iter().map(|&price| add_storage_node(&mut network, units(price))).collect();
+    (network, client_id, nodes)
+}
+
+/// A network with one funded client and `storage_nodes` SPs charging one base unit per byte-epoch.
+pub fn setup(storage_nodes: usize) -> (Network, PeerId, Vec<PeerId>) {
+    setup_with_prices(&vec![1; storage_nodes])
+}
+
+/// Like `setup`, on a manual clock starting at `start`.
+pub fn setup_with_clock(storage_nodes: usize, start: Timestamp) -> (Network, ManualClock, PeerId, Vec<PeerId>) {
+    let (mut network, client_id, nodes) = setup(storage_nodes);
+    let clock = ManualClock::new(start);
+    network.set_clock(Box::new(clock.clone()));
+    (network, clock, client_id, nodes)
+}
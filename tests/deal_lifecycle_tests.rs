@@ -0,0 +1,114 @@
+use libp2p::PeerId;
+use pioneerfs::TokenAmount;
+use pioneerfs::clock::{ManualClock, Timestamp};
+use pioneerfs::deal::{Deal, DealState, DealTerms, EXPIRING_EPOCHS};
+use pioneerfs::network::EPOCH_DURATION;
+
+mod common;
+use common::setup;
+
+#[test]
+fn test_only_valid_transitions_are_allowed() {
+    let terms = DealTerms {
+        client_id: PeerId::random(),
+        storage_node_id: PeerId::random(),
+        filename: "terms.txt".to_string(),
+        duration: EPOCH_DURATION * 4,
+        total_payment: TokenAmount::from_base_units(40),
+        commitment: [0; 32],
+    };
+    let now = Timestamp::from_millis(0);
+    let mut deal = Deal::propose(7, terms, 3, now);
+
+    assert!(deal.transition(DealState::Active, 3, now).is_err(), "a deal must be accepted before it is active");
+    deal.accept(Some(1), 3, now).unwrap();
+    deal.transition(DealState::Active, 4, now).unwrap();
+    deal.transition(DealState::Expiring, 5, now).unwrap();
+    deal.transition(DealState::Slashed, 6, now).unwrap();
+    assert!(deal.transition(DealState::Active, 7, now).is_err(), "a slashed deal stays slashed");
+
+    let states: Vec<(DealState, u64)> = deal.history().iter().map(|transition| (transition.state, transition.epoch)).collect();
+    assert_eq!(states, vec![
+        (DealState::Proposed, 3),
+        (DealState::Accepted, 3),
+        (DealState::Active, 4),
+        (DealState::Expiring, 5),
+        (DealState::Slashed, 6),
+    ]);
+    assert_eq!(deal.entered(DealState::Expiring).unwrap().epoch, 5);
+}
+
+#[test]
+fn test_paid_out_deals_expire_through_expiring() {
+    let (mut network, client_id, nodes) = setup(1);
+    let clock = ManualClock::new(Timestamp::from_millis(0));
+    network.set_clock(Box::new(clock.clone()));
+    network.upload_file(&client_id, "term.txt".to_string(), b"term".to_vec(), 1).unwrap();
+    let deal_id = network.deals_by_client(&client_id)[0].id();
+    let epochs = network.get_deal(deal_id).unwrap().epochs();
+
+    for epoch in 1..epochs {
+        network.advance_epoch();
+        let expected = if epochs - epoch <= EXPIRING_EPOCHS { DealState::Expiring } else { DealState::Active };
+        assert_eq!(network.get_deal(deal_id).unwrap().state(), expected, "epoch {}", epoch);
+    }
+    network.advance_epoch();
+
+    // Fully paid, but the data is kept for the grace period like any other ended term
+    assert_eq!(network.get_deal(deal_id).unwrap().state(), DealState::Expiring);
+    assert!(network.get_file_content(&nodes[0], "term.txt").is_ok());
+    network.check_deals();
+    assert_eq!(network.get_deal(deal_id).unwrap().state(), DealState::Expiring);
+
+    clock.advance(network.grace_period);
+    network.check_deals();
+    let deal = network.get_deal(deal_id).unwrap();
+    assert_eq!(deal.state(), DealState::Expired);
+    assert_eq!(deal.entered(DealState::Expiring).unwrap().epoch, epochs - EXPIRING_EPOCHS);
+    assert_eq!(deal.entered(DealState::Expired).unwrap().epoch, epochs);
+    assert!(network.get_file_content(&nodes[0], "term.txt").is_err(), "the SP's replica is removed");
+    assert!(network.get_file_locations(&client_id, "term.txt").is_err(), "the client no longer lists the SP");
+}
+
+#[test]
+fn test_deals_can_be_queried() {
+    let (mut network, client_id, nodes) = setup(3);
+    let other_client = PeerId::random();
+    network.add_client(other_client);
+    network.token.disburse(&other_client, TokenAmount::from_tokens(1_000), "test funding").unwrap();
+
+    // The other client's a.txt can only go to the SP that does not hold one yet
+    network.upload_file(&client_id, "a.txt".to_string(), b"a".to_vec(), 2).unwrap();
+    network.upload_file(&client_id, "b.txt".to_string(), b"b".to_vec(), 2).unwrap();
+    network.upload_file(&other_client, "a.txt".to_string(), b"a".to_vec(), 1).unwrap();
+
+    assert_eq!(network.deals_by_client(&client_id).len(), 4);
+    assert_eq!(network.deals_for_file(&client_id, "a.txt").len(), 2);
+    assert_eq!(network.deals_for_file(&other_client, "a.txt").len(), 1);
+    let per_node: usize = nodes.iter().map(|node_id| network.deals_by_storage_node(node_id).len()).sum();
+    assert_eq!(per_node, 5);
+
+    let ids: Vec<u64> = network.deals.keys().copied().collect();
+    assert_eq!(ids, (0..5).collect::<Vec<u64>>());
+
+    let sp_id = *network.deals_for_file(&client_id, "b.txt")[0].storage_node_id();
+    network.terminate_deal(&client_id, &sp_id, "b.txt").unwrap();
+    assert_eq!(network.deals_in_state(DealState::Terminated).len(), 1);
+    assert_eq!(network.deals_in_state(DealState::Active).len(), 4);
+    assert!(network.terminate_deal(&client_id, &sp_id, "b.txt").is_err(), "a terminated deal cannot be terminated again");
+}
+
+#[test]
+fn test_commitments_are_stable_sha256_digests() {
+    use pioneerfs::storage_node::content_digest;
+
+    let digest = content_digest(b"abc");
+    assert_eq!(hex::encode(digest), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+    // A saved deal keeps its commitment, so it still verifies after a reload
+    let (mut network, client_id, _) = setup(1);
+    network.upload_file(&client_id, "kept.txt".to_string(), b"abc".to_vec(), 1).unwrap();
+    let saved = serde_json::to_string(network.get_deal(0).unwrap()).unwrap();
+    let reloaded: Deal = serde_json::from_str(&saved).unwrap();
+    assert_eq!(reloaded.commitment(), digest);
+}
@@ -0,0 +1,186 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::Network;
+use pioneerfs::clock::{ManualClock, Timestamp};
+use pioneerfs::deal::DealState;
+use pioneerfs::network::EPOCH_DURATION;
+
+mod common;
+use common::{setup_with_clock, units};
+
+const DATA: &[u8] = b"ten bytes!";
+
+fn setup() -> (Network, ManualClock, PeerId, PeerId) {
+    let (network, clock, client_id, nodes) = setup_with_clock(1, Timestamp::from_millis(0));
+    (network, clock, client_id, nodes[0])
+}
+
+#[test]
+fn test_clients_choose_the_duration() {
+    let (mut network, _, client_id, sp_id) = setup();
+    let balance_before = network.get_balance(&client_id);
+
+    network.upload_file_for(&client_id, "short.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION * 6).unwrap();
+
+    let deal = &network.deals_by_client(&client_id)[0];
+    assert_eq!(deal.epochs(), 6);
+    assert_eq!(deal.total_payment(), units(10 * 6));
+    assert_eq!(network.get_balance(&client_id), balance_before - units(10 * 6));
+    assert!(network.upload_file_for(&client_id, "shorter.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION / 2).is_err());
+    assert_eq!(network.storage_nodes()[&sp_id].used_space(), DATA.len());
+}
+
+#[test]
+fn test_renewals_charge_the_current_price_and_are_paid_out_in_full() {
+    let (mut network, _, client_id, sp_id) = setup();
+    // Paid-out deals expire as soon as their grace period is over
+    network.grace_period = Duration::ZERO;
+    network.upload_file_for(&client_id, "renewed.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION * 4).unwrap();
+    network.advance_epoch();
+    network.advance_epoch();
+    assert_eq!(network.get_deal(0).unwrap().state(), DealState::Expiring);
+
+    network.storage_nodes.get_mut(&sp_id).unwrap().set_price_per_byte_epoch(units(3));
+    let balance_before = network.get_balance(&client_id);
+    let cost = network.renew_deal(&client_id, &sp_id, "renewed.txt", EPOCH_DURATION * 4).unwrap();
+
+    assert_eq!(cost, units(10 * 4 * 3));
+    assert_eq!(network.get_balance(&client_id), balance_before - cost);
+    let deal = network.get_deal(0).unwrap();
+    assert_eq!(deal.state(), DealState::Active);
+    assert_eq!(deal.epochs(), 8);
+    assert_eq!(deal.total_payment(), units(40).checked_add(cost).unwrap());
+
+    for _ in 0..6 {
+        network.advance_epoch();
+    }
+    let deal = network.get_deal(0).unwrap();
+    assert_eq!(deal.state(), DealState::Expired);
+    assert_eq!(deal.amount_paid(), units(40).checked_add(cost).unwrap());
+    assert_eq!(network.storage_nodes()[&sp_id].storage_earnings(), units(40).checked_add(cost).unwrap());
+}
+
+#[test]
+fn test_auto_renewal_draws_on_its_budget_until_it_runs_out() {
+    let (mut network, _, client_id, sp_id) = setup();
+    // Paid-out deals expire as soon as their grace period is over
+    network.grace_period = Duration::ZERO;
+    network.upload_file_for(&client_id, "auto.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION * 4).unwrap();
+    // Enough for one renewal and a bit
+    network.set_auto_renewal(&client_id, 0, EPOCH_DURATION * 4, units(50)).unwrap();
+    assert!(network.set_auto_renewal(&PeerId::random(), 0, EPOCH_DURATION * 4, units(50)).is_err());
+
+    network.advance_epoch();
+    network.advance_epoch();
+    let deal = network.get_deal(0).unwrap();
+    assert_eq!(deal.epochs(), 8, "renewed as it started expiring");
+    assert_eq!(deal.state(), DealState::Active);
+
+    for _ in 0..4 {
+        network.advance_epoch();
+    }
+    assert_eq!(network.get_deal(0).unwrap().state(), DealState::Expiring, "the budget no longer covers a renewal");
+
+    // Ending the deal hands back what is left of the budget
+    let balance_before = network.get_balance(&client_id);
+    network.advance_epoch();
+    network.advance_epoch();
+    assert_eq!(network.get_deal(0).unwrap().state(), DealState::Expired);
+    assert!(network.get_deal(0).unwrap().auto_renewal().is_none());
+    assert_eq!(network.get_balance(&client_id), balance_before.checked_add(units(10)).unwrap());
+    assert_eq!(network.storage_nodes()[&sp_id].storage_earnings(), units(80));
+}
+
+#[test]
+fn test_data_survives_the_grace_period_only() {
+    let (mut network, clock, client_id, sp_id) = setup();
+    network.upload_file_for(&client_id, "grace.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION).unwrap();
+    network.upload_file_for(&client_id, "lapsed.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION).unwrap();
+
+    clock.advance(EPOCH_DURATION);
+    network.check_deals();
+    assert_eq!(network.deals_in_state(DealState::Expiring).len(), 2);
+    assert_eq!(network.download_file(&client_id, "lapsed.txt").unwrap(), DATA);
+
+    // A renewal during the grace period rescues the data
+    network.renew_deal(&client_id, &sp_id, "grace.txt", EPOCH_DURATION * 24).unwrap();
+    assert_eq!(network.deals_for_file(&client_id, "grace.txt")[0].state(), DealState::Active);
+
+    clock.advance(network.grace_period - Duration::from_secs(1));
+    network.check_deals();
+    assert!(network.download_file(&client_id, "lapsed.txt").is_ok());
+
+    clock.advance(Duration::from_secs(1));
+    network.check_deals();
+    assert_eq!(network.deals_for_file(&client_id, "lapsed.txt")[0].state(), DealState::Expired);
+    assert!(network.download_file(&client_id, "lapsed.txt").is_err());
+    assert_eq!(network.download_file(&client_id, "grace.txt").unwrap(), DATA);
+}
+
+#[test]
+fn test_replicas_streams_and_offers_use_the_requested_duration() {
+    let (mut network, _, client_id, sp_id) = setup();
+    for _ in 0..3 {
+        network.add_storage_node(PeerId::random(), units(1));
+    }
+    let six_epochs = EPOCH_DURATION * 6;
+    let epochs_of = |network: &Network, filename: &str| -> Vec<u64> {
+        network.deals_for_file(&client_id, filename).iter().map(|deal| deal.epochs()).collect()
+    };
+
+    // Chain replicas are paid for, and kept, only as long as the original deal runs
+    network.upload_file_for(&client_id, "chained.txt".to_string(), DATA.to_vec(), 1, six_epochs).unwrap();
+    network.replicate_file(&client_id, "chained.txt", 1).unwrap();
+    assert_eq!(epochs_of(&network, "chained.txt"), vec![6, 6]);
+    let replication = network.get_replication_payments(&client_id, "chained.txt");
+    assert_eq!(replication[0].amount, units(10 * 6));
+
+    let stream_id = network.open_upload_stream_for(&client_id, "streamed.txt".to_string(), 1, units(100), six_epochs).unwrap();
+    assert_eq!(network.upload_streams[&stream_id].duration(), six_epochs);
+    network.stream_chunk(stream_id, DATA).unwrap();
+    network.finish_upload_stream(stream_id).unwrap();
+    assert_eq!(epochs_of(&network, "streamed.txt"), vec![6]);
+    assert_eq!(network.deals_for_file(&client_id, "streamed.txt")[0].total_payment(), units(10 * 6));
+
+    let offer_id = network.add_storage_offer(sp_id, units(2), 100, 10).unwrap();
+    network.accept_storage_offer_for(&client_id, offer_id, "offered.txt".to_string(), DATA.to_vec(), six_epochs).unwrap();
+    assert_eq!(epochs_of(&network, "offered.txt"), vec![6]);
+    assert_eq!(network.deals_for_file(&client_id, "offered.txt")[0].total_payment(), units(10 * 6 * 2));
+
+    assert!(network.open_upload_stream_for(&client_id, "brief.txt".to_string(), 1, units(100), EPOCH_DURATION / 2).is_err());
+    assert!(network.accept_storage_offer_for(&client_id, offer_id, "brief.txt".to_string(), DATA.to_vec(), EPOCH_DURATION / 2).is_err());
+}
+
+#[test]
+fn test_part_epochs_are_charged_in_full() {
+    let (mut network, _, client_id, sp_id) = setup();
+    network.upload_file_for(&client_id, "part.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION * 3 / 2).unwrap();
+    assert_eq!(network.get_deal(0).unwrap().epochs(), 2);
+    assert_eq!(network.get_deal(0).unwrap().total_payment(), units(10 * 2));
+
+    let cost = network.renew_deal(&client_id, &sp_id, "part.txt", EPOCH_DURATION * 5 / 2).unwrap();
+    assert_eq!(cost, units(10 * 3));
+    assert_eq!(network.get_deal(0).unwrap().epochs(), 5);
+}
+
+#[test]
+fn test_failed_auto_renewal_keeps_its_budget() {
+    let (mut network, clock, client_id, _) = setup();
+    network.upload_file_for(&client_id, "replica.txt".to_string(), DATA.to_vec(), 1, EPOCH_DURATION * 4).unwrap();
+    let replica = 0;
+    network.set_auto_renewal(&client_id, replica, EPOCH_DURATION * 4, units(100)).unwrap();
+    // With its escrow gone from the ledger, the deal has nothing to renew into
+    let deal_escrow = network.get_deal(replica).unwrap().escrow_id().unwrap();
+    network.token.escrow_refund(deal_escrow).unwrap();
+    let escrow_id = network.get_deal(replica).unwrap().auto_renewal().unwrap().escrow_id;
+    let balance_before = network.get_balance(&client_id);
+
+    clock.advance(EPOCH_DURATION * 4);
+    network.check_deals();
+
+    let deal = network.get_deal(replica).unwrap();
+    assert_eq!(deal.state(), DealState::Expiring);
+    assert_eq!(deal.epochs(), 4);
+    assert_eq!(network.token.escrow_balance(escrow_id), units(100));
+    assert_eq!(network.get_balance(&client_id), balance_before);
+}
@@ -0,0 +1,99 @@
+use libp2p::PeerId;
+use pioneerfs::{Network, TokenAmount};
+use pioneerfs::clock::Timestamp;
+use pioneerfs::deal::DealState;
+use pioneerfs::network::EPOCH_DURATION;
+use pioneerfs::scheduler::MaintenanceJob;
+
+mod common;
+use common::{add_storage_node, setup_with_clock, units};
+
+const DATA: &[u8] = b"ten bytes!";
+
+fn setup(storage_nodes: usize) -> (Network, PeerId, Vec<PeerId>) {
+    let (mut network, _, client_id, _) = setup_with_clock(0, Timestamp::from_millis(0));
+    let nodes = (0..storage_nodes).map(|_| add_staked_node(&mut network)).collect();
+    (network, client_id, nodes)
+}
+
+fn add_staked_node(network: &mut Network) -> PeerId {
+    let sp_id = add_storage_node(network, units(1));
+    network.token.disburse(&sp_id, units(500), "test funding").unwrap();
+    network.stake(&sp_id, units(300)).unwrap();
+    sp_id
+}
+
+#[test]
+fn test_draining_moves_deals_and_releases_the_stake() {
+    let (mut network, client_id, nodes) = setup(3);
+    let holders = network.upload_file_for(&client_id, "moved.txt".to_string(), DATA.to_vec(), 2, EPOCH_DURATION * 4).unwrap();
+    let (leaving, staying) = (holders[0], holders[1]);
+    let spare = *nodes.iter().find(|node_id| !holders.contains(node_id)).unwrap();
+    network.advance_epoch();
+    let old_deal = network.deals_by_storage_node(&leaving)[0].clone();
+    let unpaid = network.token.escrow_balance(old_deal.escrow_id().unwrap());
+
+    let report = network.drain_storage_node(&leaving).unwrap();
+
+    assert!(report.is_complete());
+    assert_eq!(report.stake_released, Some(units(300)));
+    assert_eq!(network.get_balance(&leaving), units(500).checked_add(units(10)).unwrap());
+    assert_eq!(network.stake_of(&leaving), TokenAmount::ZERO);
+    assert_eq!(report.migrated.len(), 1);
+    let migration = &report.migrated[0];
+    assert_eq!(migration.storage_node_id, spare);
+    assert_eq!(migration.value, unpaid);
+    assert_eq!(network.get_deal(old_deal.id()).unwrap().state(), DealState::Migrated);
+    let new_deal = network.get_deal(migration.to_deal).unwrap();
+    assert_eq!(new_deal.escrow_id(), old_deal.escrow_id());
+    assert_eq!(new_deal.total_payment(), unpaid);
+    assert_eq!(new_deal.ends_at(), old_deal.ends_at());
+
+    let mut locations = network.get_file_locations(&client_id, "moved.txt").unwrap();
+    locations.sort();
+    let mut expected = vec![staying, spare];
+    expected.sort();
+    assert_eq!(locations, expected);
+    assert!(network.get_file_content(&leaving, "moved.txt").is_err());
+    assert_eq!(network.get_file_content(&spare, "moved.txt").unwrap(), DATA);
+
+    // The replacement is paid exactly what the leaving SP had not yet earned
+    for _ in 0..3 {
+        network.advance_epoch();
+    }
+    assert_eq!(network.storage_nodes()[&leaving].storage_earnings().checked_add(network.storage_nodes()[&spare].storage_earnings()).unwrap(), old_deal.total_payment());
+}
+
+#[test]
+fn test_draining_sps_take_no_new_deals() {
+    let (mut network, client_id, nodes) = setup(2);
+    network.add_storage_offer(nodes[0], units(1), 1_000, 10).unwrap();
+    network.drain_storage_node(&nodes[0]).unwrap();
+
+    assert!(network.get_storage_offers().is_empty());
+    assert!(network.add_storage_offer(nodes[0], units(1), 1_000, 10).is_err());
+    assert!(network.stake(&nodes[0], units(1)).is_err());
+    let err = network.upload_file(&client_id, "new.txt".to_string(), DATA.to_vec(), 2).unwrap_err();
+    assert!(err.contains("1 leaving the network"), "{}", err);
+    assert_eq!(network.upload_file(&client_id, "new.txt".to_string(), DATA.to_vec(), 1).unwrap(), vec![nodes[1]]);
+}
+
+#[test]
+fn test_stake_is_held_until_every_replica_is_rehomed() {
+    let (mut network, client_id, nodes) = setup(2);
+    network.upload_file(&client_id, "stuck.txt".to_string(), DATA.to_vec(), 2).unwrap();
+
+    let report = network.drain_storage_node(&nodes[0]).unwrap();
+    assert_eq!(report.pending.len(), 1);
+    assert_eq!(report.stake_released, None);
+    assert_eq!(network.stake_of(&nodes[0]), units(300));
+    assert!(network.get_file_content(&nodes[0], "stuck.txt").is_ok(), "the data stays put until it has somewhere to go");
+
+    let newcomer = add_staked_node(&mut network);
+    network.run_job(MaintenanceJob::RepairScan);
+
+    assert!(!network.stakes.contains_key(&nodes[0]));
+    assert_eq!(network.get_balance(&nodes[0]), units(500));
+    assert_eq!(network.get_file_content(&newcomer, "stuck.txt").unwrap(), DATA);
+    assert_eq!(network.deals_in_state(DealState::Migrated).len(), 1);
+}
@@ -1,26 +1,29 @@
-use pioneerfs::{Network, DebugLevel};
+use pioneerfs::{Network, DebugLevel, TokenAmount};
+use pioneerfs::cluster::TestCluster;
 use libp2p::PeerId;
 use rand::Rng;
 
 #[test]
 fn test_end_to_end_storage() {
-    let mut network = Network::new();
+    let mut network = Network::new().unwrap();
     network.set_debug_level(DebugLevel::Low);
 
     // Spawn 10 nodes
     for _ in 0..10 {
         let sp_id = PeerId::random();
-        network.add_storage_node(sp_id, rand::thread_rng().gen_range(10..20));
+        network.add_storage_node(sp_id, TokenAmount::from_base_units(rand::thread_rng().gen_range(10..20)));
     }
 
     let client_id = PeerId::random();
     network.add_client(client_id);
+    network.request_faucet_funds(&client_id).unwrap();
 
-    let filename = "end_to_end_test_file.txt".to_string();
     let data = b"End-to-end test data".to_vec();
 
-    // Test replication on 3, 5, 7, 9, and 10 nodes
+    // Test replication on 3, 5, 7, 9, and 10 nodes. An SP holds one file per name, so
+    // each upload gets its own name
     for &replication_factor in &[3, 5, 7, 9, 10] {
+        let filename = format!("end_to_end_test_file_{}.txt", replication_factor);
         network.upload_file(&client_id, filename.clone(), data.clone(), replication_factor)
             .unwrap_or_else(|e| panic!("Failed to upload file with replication factor {}: {}", replication_factor, e));
 
@@ -30,17 +33,18 @@ fn test_end_to_end_storage() {
 }
 #[test]
 fn test_poss_retrieval() {
-    let mut network = Network::new();
+    let mut network = Network::new().unwrap();
     network.set_debug_level(DebugLevel::Low);
 
     // Spawn 10 nodes
     for _ in 0..10 {
         let sp_id = PeerId::random();
-        network.add_storage_node(sp_id, rand::thread_rng().gen_range(10..20));
+        network.add_storage_node(sp_id, TokenAmount::from_base_units(rand::thread_rng().gen_range(10..20)));
     }
 
     let client_id = PeerId::random();
     network.add_client(client_id);
+    network.request_faucet_funds(&client_id).unwrap();
 
     let filename = "poss_retrieval_test_file.txt".to_string();
     let data = b"PoSS retrieval test data".to_vec();
@@ -55,17 +59,18 @@ fn test_poss_retrieval() {
 }
 #[test]
 fn test_split_retrieval() {
-    let mut network = Network::new();
+    let mut network = Network::new().unwrap();
     network.set_debug_level(DebugLevel::Low);
 
     // Spawn 10 nodes
     for _ in 0..10 {
         let sp_id = PeerId::random();
-        network.add_storage_node(sp_id, rand::thread_rng().gen_range(10..20));
+        network.add_storage_node(sp_id, TokenAmount::from_base_units(rand::thread_rng().gen_range(10..20)));
     }
 
     let client_id = PeerId::random();
     network.add_client(client_id);
+    network.request_faucet_funds(&client_id).unwrap();
 
     let filename = "split_retrieval_test_file.txt".to_string();
     let data = b"Split retrieval test data".to_vec();
@@ -93,3 +98,52 @@ fn test_split_retrieval() {
     assert_eq!(retrieved_parts, data.len(), "All parts of the data should be retrieved");
     assert_eq!(data, retrieved_data, "Retrieved data should match the original data");
 }
+/// Uploads through a cluster of 10 peers and checks that exactly the chosen peers
+/// stored the file and that it downloads intact.
+async fn replicate_over_libp2p(replication_factor: usize) {
+    let cluster = TestCluster::new(10, 1).await.unwrap();
+    let filename = format!("replicated_{}.txt", replication_factor);
+    let data = b"End-to-end test data".to_vec();
+
+    let mut locations = cluster.upload(0, &filename, data.clone(), replication_factor).await
+        .unwrap_or_else(|e| panic!("Failed to upload file with replication factor {}: {}", replication_factor, e));
+
+    locations.sort();
+    let mut stored_on = cluster.stored_on(&filename);
+    stored_on.sort();
+    assert_eq!(locations.len(), replication_factor, "File should be replicated on {} nodes", replication_factor);
+    assert_eq!(stored_on, locations, "Each chosen peer stored its own copy");
+    assert_eq!(cluster.download(0, &filename).await.unwrap(), data);
+}
+
+#[tokio::test]
+async fn test_replicates_to_3_of_10_nodes_over_libp2p() {
+    replicate_over_libp2p(3).await;
+}
+
+#[tokio::test]
+async fn test_replicates_to_5_of_10_nodes_over_libp2p() {
+    replicate_over_libp2p(5).await;
+}
+
+#[tokio::test]
+async fn test_replicates_to_7_of_10_nodes_over_libp2p() {
+    replicate_over_libp2p(7).await;
+}
+
+#[tokio::test]
+async fn test_replicates_to_9_of_10_nodes_over_libp2p() {
+    replicate_over_libp2p(9).await;
+}
+
+#[tokio::test]
+async fn test_replicates_to_10_of_10_nodes_over_libp2p() {
+    replicate_over_libp2p(10).await;
+}
+
+#[tokio::test]
+async fn test_replicating_to_more_nodes_than_exist_fails_over_libp2p() {
+    let cluster = TestCluster::new(10, 1).await.unwrap();
+    assert!(cluster.upload(0, "too_many.txt", b"End-to-end test data".to_vec(), 11).await.is_err());
+    assert!(cluster.stored_on("too_many.txt").is_empty());
+}
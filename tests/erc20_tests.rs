@@ -0,0 +1,130 @@
+use libp2p::PeerId;
+use pioneerfs::erc20::{Account, BatchTransfer, Purpose, TokenError, TokenEvent, ERC20};
+use pioneerfs::{Network, TokenAmount};
+
+mod common;
+use common::pio;
+
+#[test]
+fn test_every_operation_emits_a_sequenced_event() {
+    let mut token = ERC20::new("PioDollar".to_string(), "PIO".to_string(), TokenAmount::ZERO);
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+
+    token.mint(&alice, pio(100)).unwrap();
+    token.transfer_with_memo(&alice, &bob, pio(30), Purpose::Retrieval, "download").unwrap();
+    token.approve(&alice, &bob, pio(20)).unwrap();
+    token.transfer_from(&bob, &alice, &bob, pio(5)).unwrap();
+    token.burn(&bob, pio(10)).unwrap();
+
+    let events = token.events();
+    assert_eq!(events.len(), 5);
+    for (index, record) in events.iter().enumerate() {
+        assert_eq!(record.sequence, index as u64);
+    }
+    assert_eq!(events[1].event, TokenEvent::Transfer {
+        from: Some(Account::Peer(alice)),
+        to: Some(Account::Peer(bob)),
+        amount: pio(30),
+        purpose: Purpose::Retrieval,
+        memo: "download".to_string(),
+    });
+    assert_eq!(events[2].event, TokenEvent::Approval { owner: alice, spender: bob, amount: pio(20) });
+    assert!(matches!(events[4].event, TokenEvent::Transfer { to: None, amount, .. } if amount == pio(10)));
+    assert_eq!(token.events_since(3).len(), 2);
+}
+
+#[test]
+fn test_failed_operations_emit_nothing() {
+    let mut token = ERC20::new("PioDollar".to_string(), "PIO".to_string(), TokenAmount::ZERO);
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+
+    assert!(token.transfer(&alice, &bob, pio(1)).is_err());
+    assert!(token.burn(&alice, pio(1)).is_err());
+    assert!(token.events().is_empty());
+}
+
+#[test]
+fn test_mutating_calls_return_typed_errors() {
+    let mut token = ERC20::new("PioDollar".to_string(), "PIO".to_string(), TokenAmount::ZERO);
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+    token.mint(&alice, pio(10)).unwrap();
+
+    assert_eq!(token.transfer(&alice, &bob, pio(11)), Err(TokenError::InsufficientBalance { account: alice, required: pio(11), available: pio(10) }));
+    // No allowance entry at all must be an error, not a panic
+    assert_eq!(token.transfer_from(&bob, &alice, &bob, TokenAmount::ZERO), Ok(()));
+    assert_eq!(token.transfer_from(&bob, &alice, &bob, pio(1)), Err(TokenError::InsufficientAllowance { required: pio(1), available: TokenAmount::ZERO }));
+    assert_eq!(token.mint(&alice, TokenAmount::MAX), Err(TokenError::Overflow));
+    assert_eq!(token.escrow_refund(42), Err(TokenError::EscrowNotFound(42)));
+    assert_eq!(token.burn(&bob, pio(1)), Err(TokenError::InsufficientBalance { account: bob, required: pio(1), available: TokenAmount::ZERO }));
+    assert_eq!(token.total_supply(), pio(10));
+    assert!(token.supply_is_consistent());
+}
+
+#[test]
+fn test_supply_invariant_holds_across_escrow_and_burn() {
+    let mut token = ERC20::new("PioDollar".to_string(), "PIO".to_string(), pio(1_000));
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+    token.mint(&alice, pio(500)).unwrap();
+
+    let escrow_id = token.escrow_deposit(&alice, pio(200), Purpose::Upload).unwrap();
+    token.escrow_release(escrow_id, &bob, pio(50)).unwrap();
+    assert!(token.supply_is_consistent());
+    token.burn(&bob, pio(50)).unwrap();
+    token.escrow_refund(escrow_id).unwrap();
+
+    assert_eq!(token.total_supply(), pio(1_450));
+    assert_eq!(token.treasury_balance(), pio(1_000));
+    assert!(token.supply_is_consistent());
+    assert!(token.audit());
+}
+
+#[test]
+fn test_batch_transfer_is_all_or_nothing() {
+    let mut token = ERC20::new("PioDollar".to_string(), "PIO".to_string(), TokenAmount::ZERO);
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+    let carol = PeerId::random();
+    token.mint(&alice, pio(100)).unwrap();
+    let leg = |from, to, amount| BatchTransfer { from, to, amount: pio(amount), purpose: Purpose::Replication, memo: String::new() };
+
+    let events_before = token.events().len();
+    let result = token.transfer_batch(&[leg(alice, bob, 60), leg(bob, carol, 20), leg(alice, carol, 50)]);
+    assert!(matches!(result, Err(TokenError::InsufficientBalance { .. })));
+    assert_eq!(token.balance_of(&alice), pio(100));
+    assert_eq!(token.balance_of(&bob), TokenAmount::ZERO);
+    assert_eq!(token.events().len(), events_before);
+
+    token.transfer_batch(&[leg(alice, bob, 60), leg(bob, carol, 20), leg(alice, carol, 40)]).unwrap();
+    assert_eq!(token.balance_of(&alice), TokenAmount::ZERO);
+    assert_eq!(token.balance_of(&bob), pio(40));
+    assert_eq!(token.balance_of(&carol), pio(60));
+    assert_eq!(token.events().len(), events_before + 3);
+}
+
+#[test]
+fn test_history_replays_to_current_balances() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, pio(1_000_000), "test funding").unwrap();
+    for _ in 0..4 {
+        network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(10));
+    }
+
+    network.upload_file(&client_id, "audited.txt".to_string(), b"audit me".to_vec(), 1).unwrap();
+    network.advance_epoch();
+    network.replicate_file(&client_id, "audited.txt", 2).unwrap();
+    network.download_file(&client_id, "audited.txt").unwrap();
+
+    assert!(network.token.audit());
+    let replayed = ERC20::replay(network.token.events());
+    assert_eq!(replayed[&Account::Peer(client_id)], network.get_balance(&client_id));
+
+    assert_eq!(network.token.events_with_purpose(Purpose::Replication).len(), 2);
+    assert!(!network.token.events_with_purpose(Purpose::Upload).is_empty());
+    assert!(network.token.events_for(&client_id).len() >= 4);
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::clock::Timestamp;
+use pioneerfs::erc20::TokenError;
+use pioneerfs::faucet::{Faucet, FaucetError};
+use pioneerfs::{Network, TokenAmount};
+
+mod common;
+use common::pio;
+
+#[test]
+fn test_new_clients_start_empty_and_draw_from_the_treasury() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    let supply = network.token.total_supply();
+    let treasury = network.token.treasury_balance();
+
+    network.add_client(client_id);
+    assert_eq!(network.get_balance(&client_id), TokenAmount::ZERO);
+
+    let paid = network.request_faucet_funds(&client_id).unwrap();
+    assert_eq!(paid, network.faucet.drip_amount());
+    assert_eq!(network.get_balance(&client_id), paid);
+    assert_eq!(network.token.treasury_balance(), treasury - paid);
+    assert_eq!(network.token.total_supply(), supply, "The faucet must not mint");
+}
+
+#[test]
+fn test_faucet_is_rate_limited_per_identity() {
+    let mut network = Network::new().unwrap();
+    let alice = PeerId::random();
+    let bob = PeerId::random();
+    network.add_client(alice);
+    network.add_client(bob);
+
+    network.request_faucet_funds(&alice).unwrap();
+    assert!(network.request_faucet_funds(&alice).is_err());
+    // Another identity is unaffected by alice's limit
+    assert!(network.request_faucet_funds(&bob).is_ok());
+    // Unknown identities cannot claim at all
+    assert!(network.request_faucet_funds(&PeerId::random()).is_err());
+}
+
+#[test]
+fn test_cooldown_and_lifetime_cap() {
+    let mut faucet = Faucet::new(pio(40), Duration::from_secs(60), pio(100));
+    let peer_id = PeerId::random();
+    let start = Timestamp::from_millis(0);
+
+    assert_eq!(faucet.allowance_at(&peer_id, start), Ok(pio(40)));
+    faucet.record_claim(&peer_id, pio(40), start).unwrap();
+    assert_eq!(faucet.allowance_at(&peer_id, start + Duration::from_secs(45)), Err(FaucetError::RateLimited { retry_in: Duration::from_secs(15) }));
+
+    let later = start + Duration::from_secs(60);
+    assert_eq!(faucet.allowance_at(&peer_id, later), Ok(pio(40)));
+    faucet.record_claim(&peer_id, pio(40), later).unwrap();
+
+    // The last drip is trimmed to what is left under the cap
+    let last = later + Duration::from_secs(60);
+    assert_eq!(faucet.allowance_at(&peer_id, last), Ok(pio(20)));
+    faucet.record_claim(&peer_id, pio(20), last).unwrap();
+    assert_eq!(faucet.allowance_at(&peer_id, last + Duration::from_secs(3600)), Err(FaucetError::CapReached { claimed: pio(100), cap: pio(100) }));
+}
+
+#[test]
+fn test_claim_totals_never_wrap() {
+    let mut faucet = Faucet::new(TokenAmount::MAX, Duration::ZERO, TokenAmount::MAX);
+    let peer_id = PeerId::random();
+    let now = Timestamp::from_millis(0);
+
+    faucet.record_claim(&peer_id, TokenAmount::MAX, now).unwrap();
+    assert_eq!(faucet.record_claim(&peer_id, pio(1), now), Err(FaucetError::Ledger(Box::new(TokenError::Overflow))));
+    assert_eq!(faucet.claim_of(&peer_id).unwrap().total_claimed, TokenAmount::MAX);
+}
+
+#[test]
+fn test_faucet_errors_say_why_the_claim_failed() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+
+    assert_eq!(network.request_faucet_funds(&PeerId::random()), Err(FaucetError::UnknownPeer));
+
+    let everything = network.token.treasury_balance();
+    network.token.disburse(&PeerId::random(), everything, "drain treasury").unwrap();
+    assert_eq!(
+        network.request_faucet_funds(&client_id),
+        Err(FaucetError::TreasuryEmpty { required: network.faucet.drip_amount(), available: TokenAmount::ZERO })
+    );
+    // A failed payout is not counted against the cooldown
+    assert!(network.faucet.claim_of(&client_id).is_none());
+}
@@ -1,9 +1,10 @@
 use libp2p::PeerId;
-use pioneerfs::Network;
+use pioneerfs::{Network, TokenAmount};
+use pioneerfs::deal::DealState;
 
 #[test]
 fn test_network_operations() {
-    let mut network = Network::new();
+    let mut network = Network::new().unwrap();
 
     // Add clients and storage nodes
     let client1_id = PeerId::random();
@@ -13,10 +14,12 @@ fn test_network_operations() {
     let storage_node3_id = PeerId::random();
 
     network.add_client(client1_id);
+    network.token.disburse(&client1_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
     network.add_client(client2_id);
-    network.add_storage_node(storage_node1_id, 10); // 10 PIO per GB
-    network.add_storage_node(storage_node2_id, 12); // 12 PIO per GB
-    network.add_storage_node(storage_node3_id, 11); // 11 PIO per GB
+    network.token.disburse(&client2_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(storage_node1_id, TokenAmount::from_base_units(10)); // per byte per epoch
+    network.add_storage_node(storage_node2_id, TokenAmount::from_base_units(12));
+    network.add_storage_node(storage_node3_id, TokenAmount::from_base_units(11));
 
     // Upload a file
     let filename = "test.txt".to_string();
@@ -42,36 +45,113 @@ fn test_network_operations() {
     // Check deals
     assert_eq!(network.deals.len(), 3); // Assuming REPLICATION_FACTOR is 3
 
-    // Check balances
+    // Check balances: payment sits in escrow until the SPs prove storage each epoch
     for &node_id in &storage_nodes {
-        assert!(network.get_balance(&node_id) > 0);
+        assert_eq!(network.get_balance(&node_id), TokenAmount::ZERO);
     }
-    assert!(network.get_balance(&client1_id) < 1_000_000);
+    assert!(network.get_balance(&client1_id) < TokenAmount::from_tokens(1_000_000));
+}
+
+#[test]
+fn test_escrowed_deal_payouts() {
+    let mut network = Network::new().unwrap();
+
+    let client_id = PeerId::random();
+    let sp_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(sp_id, TokenAmount::from_base_units(1));
+
+    // 8 bytes for 24 epochs at 1 base unit per byte-epoch
+    network.upload_file(&client_id, "escrow.txt".to_string(), b"escrowed".to_vec(), 1).unwrap();
+    assert_eq!(network.get_balance(&client_id), TokenAmount::from_tokens(1_000_000) - TokenAmount::from_base_units(192));
+    assert_eq!(network.get_balance(&sp_id), TokenAmount::ZERO);
+
+    // Each epoch releases an even share to the SP while its proof passes
+    network.advance_epoch();
+    assert_eq!(network.get_balance(&sp_id), TokenAmount::from_base_units(8));
+    let epochs = network.deals[&0].epochs();
+    for _ in 1..epochs {
+        network.advance_epoch();
+    }
+    assert_eq!(network.get_balance(&sp_id), TokenAmount::from_base_units(192));
+    assert_eq!(network.token.escrow_balance(network.deals[&0].escrow_id().unwrap()), TokenAmount::ZERO);
+}
+
+#[test]
+fn test_failed_proof_refunds_client() {
+    let mut network = Network::new().unwrap();
+
+    let client_id = PeerId::random();
+    let sp_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(sp_id, TokenAmount::from_base_units(1));
+
+    let filename = "dropped.txt";
+    network.upload_file(&client_id, filename.to_string(), b"drop me".to_vec(), 1).unwrap();
+    network.advance_epoch();
+
+    // The SP silently drops the data; the next epoch's proof fails
+    network.storage_nodes.get_mut(&sp_id).unwrap().remove_file(filename).unwrap();
+    network.advance_epoch();
+
+    // Only the first epoch of 7 bytes was paid out
+    assert_eq!(network.get_balance(&sp_id), TokenAmount::from_base_units(7));
+    assert_eq!(network.get_balance(&client_id), TokenAmount::from_tokens(1_000_000) - TokenAmount::from_base_units(7));
+    assert_eq!(network.deals[&0].state(), DealState::Slashed);
+    assert!(network.get_file_locations(&client_id, filename).is_err());
+}
+
+#[test]
+fn test_early_termination_refunds_remainder() {
+    let mut network = Network::new().unwrap();
+
+    let client_id = PeerId::random();
+    let sp_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(sp_id, TokenAmount::from_base_units(1));
+
+    let filename = "short_lived.txt";
+    network.upload_file(&client_id, filename.to_string(), b"short lived".to_vec(), 1).unwrap();
+    for _ in 0..6 {
+        network.advance_epoch();
+    }
+
+    let refund = network.terminate_deal(&client_id, &sp_id, filename).unwrap();
+    // 11 bytes: 6 of 24 epochs paid, the other 18 refunded
+    assert_eq!(refund, TokenAmount::from_base_units(18 * 11));
+    assert_eq!(network.get_balance(&sp_id), TokenAmount::from_base_units(6 * 11));
+    assert_eq!(network.get_balance(&client_id), TokenAmount::from_tokens(1_000_000) - TokenAmount::from_base_units(6 * 11));
+    assert!(network.storage_nodes().get(&sp_id).unwrap().get_file(filename).is_none());
 }
 
 #[test]
 fn test_marketplace() {
-    let mut network = Network::new();
+    let mut network = Network::new().unwrap();
 
     let client_id = PeerId::random();
     let sp_id = PeerId::random();
 
     network.add_client(client_id);
-    network.add_storage_node(sp_id, 10);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000_000), "test funding").unwrap();
+    network.add_storage_node(sp_id, TokenAmount::from_base_units(10));
 
     // Add a storage offer
-    network.add_storage_offer(sp_id, 10, 1_000_000_000);
+    let offer_id = network.add_storage_offer(sp_id, TokenAmount::from_base_units(10), 1_000, 24).unwrap();
 
     // List storage offers
     let offers = network.get_storage_offers();
     assert_eq!(offers.len(), 1);
 
     // Accept storage offer
-    assert!(network.accept_storage_offer(&client_id, 0, 500_000_000).is_ok());
+    assert!(network.accept_storage_offer(&client_id, offer_id, "offer.txt".to_string(), vec![7; 400]).is_ok());
 
-    // Check if the offer was updated
+    // Check if the offer was updated in place
     let updated_offers = network.get_storage_offers();
     assert_eq!(updated_offers.len(), 1);
-    assert_eq!(updated_offers[0].available_space, 500_000_000);
+    assert_eq!(updated_offers[0].id, offer_id);
+    assert_eq!(updated_offers[0].available_space, 600);
+    assert_eq!(network.get_file_locations(&client_id, "offer.txt").unwrap(), vec![sp_id]);
 }
-
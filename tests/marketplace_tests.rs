@@ -0,0 +1,61 @@
+use pioneerfs::TokenAmount;
+
+mod common;
+use common::setup;
+
+#[test]
+fn test_offer_ids_survive_other_fills() {
+    let (mut network, client_id, nodes) = setup(2);
+    let pricey = network.add_storage_offer(nodes[0], TokenAmount::from_base_units(3), 100, 10).unwrap();
+    let cheap = network.add_storage_offer(nodes[1], TokenAmount::from_base_units(2), 10, 10).unwrap();
+    let ids: Vec<u64> = network.get_storage_offers().iter().map(|offer| offer.id).collect();
+    assert_eq!(ids, vec![cheap, pricey]);
+
+    // Filling the cheap offer completely closes it without disturbing the other id
+    network.accept_storage_offer(&client_id, cheap, "small.txt".to_string(), vec![1; 10]).unwrap();
+    network.accept_storage_offer(&client_id, pricey, "large.txt".to_string(), vec![2; 60]).unwrap();
+
+    let offers = network.get_storage_offers();
+    assert_eq!(offers.len(), 1);
+    assert_eq!((offers[0].id, offers[0].available_space), (pricey, 40));
+    assert!(network.accept_storage_offer(&client_id, cheap, "again.txt".to_string(), vec![3; 1]).is_err());
+
+    // Each acceptance is a real deal paid into escrow at the offer's price for 24 epochs
+    assert_eq!(network.deals.len(), 2);
+    assert_eq!(network.storage_nodes()[&nodes[0]].get_file("large.txt"), Some(&vec![2; 60]));
+    assert_eq!(network.get_balance(&client_id), TokenAmount::from_tokens(1_000) - TokenAmount::from_base_units(24 * (10 * 2 + 60 * 3)));
+    network.advance_epoch();
+    assert_eq!(network.get_balance(&nodes[0]), TokenAmount::from_base_units(60 * 3));
+}
+
+#[test]
+fn test_only_the_owner_can_cancel_or_reprice() {
+    let (mut network, client_id, nodes) = setup(2);
+    let offer_id = network.add_storage_offer(nodes[0], TokenAmount::from_base_units(5), 100, 10).unwrap();
+
+    assert!(network.update_storage_offer_price(&nodes[1], offer_id, TokenAmount::from_base_units(1)).is_err());
+    network.update_storage_offer_price(&nodes[0], offer_id, TokenAmount::from_base_units(4)).unwrap();
+    network.accept_storage_offer(&client_id, offer_id, "repriced.txt".to_string(), vec![0; 10]).unwrap();
+    assert_eq!(network.get_balance(&client_id), TokenAmount::from_tokens(1_000) - TokenAmount::from_base_units(24 * 10 * 4));
+
+    assert!(network.cancel_storage_offer(&nodes[1], offer_id).is_err());
+    network.cancel_storage_offer(&nodes[0], offer_id).unwrap();
+    assert!(network.get_storage_offers().is_empty());
+    assert!(network.accept_storage_offer(&client_id, offer_id, "late.txt".to_string(), vec![0; 10]).is_err());
+}
+
+#[test]
+fn test_offers_expire_with_epochs() {
+    let (mut network, client_id, nodes) = setup(1);
+    let offer_id = network.add_storage_offer(nodes[0], TokenAmount::from_base_units(1), 100, 2).unwrap();
+
+    network.advance_epoch();
+    assert_eq!(network.get_storage_offers().len(), 1);
+    network.advance_epoch();
+    assert!(network.get_storage_offers().is_empty());
+
+    let balance_before = network.get_balance(&client_id);
+    assert!(network.accept_storage_offer(&client_id, offer_id, "expired.txt".to_string(), vec![0; 10]).is_err());
+    assert_eq!(network.get_balance(&client_id), balance_before);
+    assert_eq!(network.storage_nodes()[&nodes[0]].used_space(), 0);
+}
@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use libp2p::PeerId;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use pioneerfs::{Network, StorageNode, TokenAmount};
+use pioneerfs::placement::{
+    strategy_by_name, CapacityBalancedPlacement, CheapestPlacement, PlacementStrategy, RandomPlacement,
+    RegionDiversePlacement, ReputationWeightedPlacement,
+};
+
+const TRIALS: usize = 2_000;
+
+fn nodes(count: usize) -> Vec<StorageNode> {
+    (0..count).map(|i| StorageNode::new(PeerId::random(), TokenAmount::from_base_units(10 + i as u128))).collect()
+}
+
+/// How often each SP was picked over `TRIALS` placements of `count` replicas.
+fn pick_counts(strategy: &dyn PlacementStrategy, candidates: &[StorageNode], count: usize) -> HashMap<PeerId, usize> {
+    let candidates: Vec<&StorageNode> = candidates.iter().collect();
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut picks = HashMap::new();
+    for _ in 0..TRIALS {
+        let selected = strategy.select(&candidates, &[], count, &mut rng);
+        assert_eq!(selected.len(), count);
+        assert_eq!(selected.iter().collect::<HashSet<_>>().len(), count, "{} picked an SP twice", strategy.name());
+        for peer_id in selected {
+            *picks.entry(peer_id).or_insert(0) += 1;
+        }
+    }
+    picks
+}
+
+#[test]
+fn test_random_placement_is_roughly_uniform() {
+    let candidates = nodes(10);
+    let picks = pick_counts(&RandomPlacement, &candidates, 3);
+
+    // Each SP is expected 600 times; allow a generous margin
+    for node in &candidates {
+        let count = picks.get(node.peer_id()).copied().unwrap_or(0);
+        assert!((450..=750).contains(&count), "SP picked {} times", count);
+    }
+}
+
+#[test]
+fn test_cheapest_placement_always_takes_lowest_prices() {
+    let candidates = nodes(10);
+    let picks = pick_counts(&CheapestPlacement, &candidates, 3);
+
+    let cheapest: HashSet<&PeerId> = candidates[..3].iter().map(|node| node.peer_id()).collect();
+    assert_eq!(picks.keys().collect::<HashSet<_>>(), cheapest);
+}
+
+#[test]
+fn test_reputation_weighted_placement_favours_reputable_nodes() {
+    let mut candidates = nodes(4);
+    candidates[0].increase_reputation(300);
+    candidates[3].decrease_reputation(90);
+    let picks = pick_counts(&ReputationWeightedPlacement, &candidates, 1);
+
+    let count = |index: usize| picks.get(candidates[index].peer_id()).copied().unwrap_or(0);
+    assert!(count(0) > 2 * count(1), "reputable SP picked {} times, average SP {}", count(0), count(1));
+    assert!(count(1) > 3 * count(3), "average SP picked {} times, penalised SP {}", count(1), count(3));
+    // Low reputation lowers the odds without excluding the SP
+    assert!(count(3) > 0);
+}
+
+#[test]
+fn test_capacity_balanced_placement_evens_out_usage() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000), "test funding").unwrap();
+    for _ in 0..6 {
+        network.add_storage_node(PeerId::random(), TokenAmount::from_base_units(1));
+    }
+    network.set_placement_strategy(Box::new(CapacityBalancedPlacement));
+
+    for i in 0..30 {
+        network.upload_file(&client_id, format!("file_{}.txt", i), vec![0; 100], 2).unwrap();
+    }
+
+    // 30 uploads of 2 replicas over 6 SPs is exactly 10 files each
+    for storage_node in network.storage_nodes().values() {
+        assert_eq!(storage_node.used_space(), 1_000);
+    }
+}
+
+#[test]
+fn test_region_diverse_placement_covers_every_region() {
+    let mut candidates = nodes(9);
+    for (i, node) in candidates.iter_mut().enumerate() {
+        // Most SPs sit in one region so random placement would usually cluster there
+        let region = match i { 0 => "eu", 1 => "apac", _ => "us" };
+        node.set_region(Some(region.to_string()));
+    }
+    let refs: Vec<&StorageNode> = candidates.iter().collect();
+    let region_of: HashMap<&PeerId, Option<&str>> = candidates.iter().map(|node| (node.peer_id(), node.region())).collect();
+    let mut rng = StdRng::seed_from_u64(7);
+
+    for _ in 0..TRIALS {
+        let selected = RegionDiversePlacement.select(&refs, &[], 3, &mut rng);
+        let regions: HashSet<Option<&str>> = selected.iter().map(|id| region_of[id]).collect();
+        assert_eq!(regions.len(), 3);
+    }
+
+    // Replicas that already exist count towards their region
+    let existing = [&candidates[0], &candidates[1]];
+    let remaining: Vec<&StorageNode> = candidates[2..].iter().chain(std::iter::once(&candidates[0])).collect();
+    let selected = RegionDiversePlacement.select(&remaining, &existing, 1, &mut rng);
+    assert_eq!(region_of[&selected[0]], Some("us"));
+}
+
+#[test]
+fn test_strategies_are_selectable_by_name() {
+    for name in ["random", "cheapest", "reputation", "capacity", "region"] {
+        assert_eq!(strategy_by_name(name).unwrap().name(), name);
+    }
+    assert!(strategy_by_name("fastest").is_err());
+
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000), "test funding").unwrap();
+    let mut cheapest = Vec::new();
+    for price in [5, 1, 4, 2, 3] {
+        let sp_id = PeerId::random();
+        network.add_storage_node(sp_id, TokenAmount::from_base_units(price));
+        if price <= 2 {
+            cheapest.push(sp_id);
+        }
+    }
+
+    // A per-upload strategy overrides the network default
+    let strategy = strategy_by_name("cheapest").unwrap();
+    let mut stored = network.upload_file_with_constraints(&client_id, "cheap.txt".to_string(), b"cheap".to_vec(), 2, &Default::default(), Some(strategy.as_ref())).unwrap();
+    stored.sort();
+    cheapest.sort();
+    assert_eq!(stored, cheapest);
+}
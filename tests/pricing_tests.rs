@@ -0,0 +1,100 @@
+use libp2p::PeerId;
+use pioneerfs::Network;
+use pioneerfs::auction::{AuctionNotice, BidOutcome};
+use pioneerfs::pricing::{MarketSignals, PricingEngine, PricingPolicy};
+
+mod common;
+use common::units;
+
+#[test]
+fn test_policy_limits_each_step() {
+    let engine = PricingEngine::default();
+    let policy = PricingPolicy { floor: units(900), ceiling: units(1_050), max_step_bps: 1_000, target_utilisation_bps: 5_000 };
+    let full = MarketSignals { utilisation: 1.0, ..Default::default() };
+    let idle = MarketSignals { utilisation: 0.0, ..Default::default() };
+
+    // Full pressure moves 10%, then the ceiling and floor hold
+    assert_eq!(engine.next_price(&policy, units(950), &full), units(1_045));
+    assert_eq!(engine.next_price(&policy, units(1_045), &full), units(1_050));
+    assert_eq!(engine.next_price(&policy, units(1_000), &idle), units(900));
+    assert_eq!(engine.next_price(&policy, units(900), &idle), units(900));
+
+    // Balanced demand leaves the price alone
+    let balanced = MarketSignals { utilisation: 0.5, ..Default::default() };
+    assert_eq!(engine.next_price(&policy, units(1_000), &balanced), units(1_000));
+
+    assert!(PricingPolicy { floor: units(2), ceiling: units(1), ..Default::default() }.validate().is_err());
+}
+
+#[test]
+fn test_prices_follow_utilisation() {
+    let mut network = Network::new().unwrap();
+    let busy = PeerId::random();
+    let idle = PeerId::random();
+    network.add_storage_node(busy, units(1_000));
+    network.add_storage_node(idle, units(1_000));
+    network.set_pricing_policy(&busy, Some(PricingPolicy::default())).unwrap();
+    network.set_pricing_policy(&idle, Some(PricingPolicy::default())).unwrap();
+    let nearly_full = network.storage_nodes()[&busy].total_space() * 9 / 10;
+    network.storage_nodes.get_mut(&busy).unwrap().reserve_space(nearly_full).unwrap();
+
+    let changes = network.adjust_prices();
+
+    assert_eq!(changes.len(), 2);
+    assert!(network.storage_nodes()[&busy].price_per_byte_epoch() > units(1_000));
+    assert_eq!(network.storage_nodes()[&idle].price_per_byte_epoch(), units(900));
+    assert_eq!(network.pricing.history().len(), 2);
+}
+
+#[test]
+fn test_nodes_without_a_policy_keep_their_price() {
+    let mut network = Network::new().unwrap();
+    let fixed = PeerId::random();
+    let dynamic = PeerId::random();
+    network.add_storage_node(fixed, units(1_000));
+    network.add_storage_node(dynamic, units(1_000));
+    network.set_pricing_policy(&dynamic, Some(PricingPolicy::default())).unwrap();
+
+    let changes = network.adjust_prices();
+
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].storage_node_id, dynamic);
+    assert_eq!(network.storage_nodes()[&fixed].price_per_byte_epoch(), units(1_000));
+
+    // Opting out stops further changes
+    network.set_pricing_policy(&dynamic, None).unwrap();
+    let price = network.storage_nodes()[&dynamic].price_per_byte_epoch();
+    assert!(network.adjust_prices().is_empty());
+    assert_eq!(network.storage_nodes()[&dynamic].price_per_byte_epoch(), price);
+    assert!(network.set_pricing_policy(&fixed, Some(PricingPolicy { floor: units(2), ceiling: units(1), ..Default::default() })).is_err());
+}
+
+#[test]
+fn test_cheaper_offers_and_lost_auctions_push_prices_down() {
+    let mut network = Network::new().unwrap();
+    let pricey = PeerId::random();
+    let cheap = PeerId::random();
+    network.add_storage_node(pricey, units(1_000));
+    network.add_storage_node(cheap, units(100));
+    network.set_pricing_policy(&pricey, Some(PricingPolicy { target_utilisation_bps: 0, ..Default::default() })).unwrap();
+    network.set_pricing_policy(&cheap, Some(PricingPolicy { target_utilisation_bps: 0, ..Default::default() })).unwrap();
+
+    // With no utilisation target, only the order book moves prices
+    network.add_storage_offer(cheap, units(100), 1_000, 10).unwrap();
+    network.adjust_prices();
+    assert_eq!(network.storage_nodes()[&pricey].price_per_byte_epoch(), units(950));
+    assert_eq!(network.storage_nodes()[&cheap].price_per_byte_epoch(), units(100));
+
+    // Losing an auction since the last run cuts the price further
+    network.auction_notices.push(AuctionNotice {
+        request_id: 0,
+        storage_node_id: pricey,
+        outcome: BidOutcome::Lost { clearing_price: Some(units(100)) },
+    });
+    network.adjust_prices();
+    assert_eq!(network.storage_nodes()[&pricey].price_per_byte_epoch(), units(855));
+
+    // Each notice is only counted once
+    network.adjust_prices();
+    assert_eq!(network.storage_nodes()[&pricey].price_per_byte_epoch(), units(855) - units(855).proportion(500, 10_000));
+}
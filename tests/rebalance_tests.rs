@@ -0,0 +1,110 @@
+use libp2p::PeerId;
+use pioneerfs::{Network, TokenAmount};
+use pioneerfs::deal::DealState;
+use pioneerfs::rebalance::RebalancePolicy;
+use pioneerfs::scheduler::MaintenanceJob;
+
+mod common;
+use common::{add_storage_node, units};
+
+const DATA: &[u8] = b"ten bytes!";
+const FILES: usize = 4;
+
+/// Two SPs holding both replicas of every file.
+fn setup() -> (Network, PeerId, Vec<PeerId>) {
+    let (mut network, client_id, nodes) = common::setup(2);
+    for i in 0..FILES {
+        network.upload_file(&client_id, format!("file{}.txt", i), DATA.to_vec(), 2).unwrap();
+    }
+    (network, client_id, nodes)
+}
+
+fn enable(network: &mut Network, max_bytes_per_node: usize) {
+    network.set_rebalance_policy(RebalancePolicy {
+        enabled: true,
+        max_bytes_per_run: 1_000,
+        max_bytes_per_node,
+        min_gap_bps: 0,
+    }).unwrap();
+}
+
+fn assert_fully_replicated(network: &Network, client_id: &PeerId) {
+    for i in 0..FILES {
+        let filename = format!("file{}.txt", i);
+        let locations = network.get_file_locations(client_id, &filename).unwrap();
+        assert_eq!(locations.len(), 2, "{} keeps both replicas", filename);
+        assert_ne!(locations[0], locations[1]);
+        for node_id in &locations {
+            assert_eq!(network.get_file_content(node_id, &filename).unwrap(), DATA);
+        }
+    }
+    assert_eq!(network.deals.values().filter(|deal| deal.is_live()).count(), FILES * 2);
+}
+
+#[test]
+fn test_rebalancing_is_opt_in() {
+    let (mut network, client_id, _) = setup();
+    let newcomer = add_storage_node(&mut network, units(1));
+
+    assert!(network.rebalance().is_empty());
+    network.run_job(MaintenanceJob::Rebalance);
+    assert_eq!(network.storage_nodes()[&newcomer].used_space(), 0);
+    assert!(network.rebalancer.history().is_empty());
+    assert_fully_replicated(&network, &client_id);
+}
+
+#[test]
+fn test_new_nodes_receive_replicas_without_losing_any() {
+    let (mut network, client_id, nodes) = setup();
+    enable(&mut network, 1_000);
+
+    let first = add_storage_node(&mut network, units(1));
+    let second = add_storage_node(&mut network, units(1));
+
+    assert_fully_replicated(&network, &client_id);
+    let used: Vec<usize> = [nodes[0], nodes[1], first, second].iter()
+        .map(|node_id| network.storage_nodes()[node_id].used_space())
+        .collect();
+    assert_eq!(used.iter().sum::<usize>(), FILES * 2 * DATA.len());
+    assert!(used.iter().all(|&bytes| bytes == 2 * DATA.len()), "spread evenly: {:?}", used);
+
+    let history = network.rebalancer.history();
+    assert_eq!(history.len(), 4);
+    assert_eq!(network.deals_in_state(DealState::Migrated).len(), history.len());
+    for replica_move in history {
+        assert!([first, second].contains(&replica_move.to));
+        assert_eq!(network.get_deal(replica_move.to_deal).unwrap().storage_node_id(), &replica_move.to);
+        assert_eq!(replica_move.bytes, DATA.len());
+    }
+}
+
+#[test]
+fn test_moves_stay_within_the_bandwidth_budget() {
+    let (mut network, client_id, _) = setup();
+    enable(&mut network, DATA.len());
+
+    let newcomer = add_storage_node(&mut network, units(1));
+    assert_eq!(network.storage_nodes()[&newcomer].used_space(), DATA.len());
+    assert_eq!(network.rebalancer.history().len(), 1);
+
+    // Each run has a fresh budget, until the SPs are as even as whole files allow
+    assert_eq!(network.run_job(MaintenanceJob::Rebalance), "1 replicas moved");
+    assert_eq!(network.storage_nodes()[&newcomer].used_space(), 2 * DATA.len());
+    assert_eq!(network.run_job(MaintenanceJob::Rebalance), "0 replicas moved");
+    assert_fully_replicated(&network, &client_id);
+}
+
+#[test]
+fn test_empty_replicas_are_not_moved() {
+    let mut network = Network::new().unwrap();
+    let client_id = PeerId::random();
+    network.add_client(client_id);
+    network.token.disburse(&client_id, TokenAmount::from_tokens(1_000), "test funding").unwrap();
+    let holder = add_storage_node(&mut network, units(1));
+    network.upload_file(&client_id, "empty.txt".to_string(), Vec::new(), 1).unwrap();
+    enable(&mut network, 1_000);
+
+    add_storage_node(&mut network, units(1));
+    assert!(network.rebalance().is_empty());
+    assert_eq!(network.get_file_locations(&client_id, "empty.txt").unwrap(), vec![holder]);
+}
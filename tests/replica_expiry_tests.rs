@@ -0,0 +1,94 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::{Network, TokenAmount};
+use pioneerfs::clock::{Clock, ManualClock, Timestamp};
+use pioneerfs::deal::DealState;
+
+mod common;
+use common::setup_with_clock;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn setup(storage_nodes: usize) -> (Network, ManualClock, PeerId, Vec<PeerId>) {
+    let (mut network, clock, client_id, nodes) = setup_with_clock(storage_nodes, Timestamp::from_millis(0));
+    network.grace_period = Duration::ZERO;
+    (network, clock, client_id, nodes)
+}
+
+/// State of the latest deal `storage_node_id` has for the file.
+fn state_on(network: &Network, client_id: &PeerId, storage_node_id: &PeerId, filename: &str) -> Option<DealState> {
+    network.deals_for_file(client_id, filename).into_iter()
+        .filter(|deal| deal.storage_node_id() == storage_node_id)
+        .map(|deal| deal.state())
+        .next_back()
+}
+
+/// Uploads to two SPs and renews the first one's deal, so only the second runs out after a day.
+fn upload_with_one_renewed(network: &mut Network, client_id: &PeerId, filename: &str) -> (PeerId, PeerId) {
+    let locations = network.upload_file(client_id, filename.to_string(), b"replicated".to_vec(), 2).unwrap();
+    network.renew_deal(client_id, &locations[0], filename, DAY).unwrap();
+    (locations[0], locations[1])
+}
+
+#[test]
+fn test_expiry_removes_only_that_replica() {
+    let (mut network, clock, client_id, _) = setup(2);
+    let (kept, expired) = upload_with_one_renewed(&mut network, &client_id, "pair.txt");
+
+    clock.advance(DAY);
+    network.check_deals();
+
+    assert_eq!(network.deals_for_file(&client_id, "pair.txt").iter().filter(|d| d.state() == DealState::Expired).count(), 1);
+    assert_eq!(state_on(&network, &client_id, &kept, "pair.txt"), Some(DealState::Active));
+    assert!(network.get_file_content(&expired, "pair.txt").is_err());
+    assert!(network.get_file_content(&kept, "pair.txt").is_ok());
+    assert_eq!(network.get_file_locations(&client_id, "pair.txt").unwrap(), vec![kept]);
+    assert_eq!(network.download_file(&client_id, "pair.txt").unwrap(), b"replicated");
+
+    // No spare SP, so the lost replica waits for a repair
+    assert_eq!(network.under_replicated[&(client_id, "pair.txt".to_string())], 1);
+    let spare = PeerId::random();
+    network.add_storage_node(spare, TokenAmount::from_base_units(1));
+    assert_eq!(network.repair_replication(&client_id, "pair.txt").unwrap(), vec![spare]);
+    assert!(network.under_replicated.is_empty());
+    let repair = network.deals_by_storage_node(&spare)[0];
+    assert_eq!(repair.ends_at(), Some(clock.now() + DAY), "the replacement runs as long as the surviving deal");
+}
+
+#[test]
+fn test_lost_replicas_are_replaced_on_a_spare_sp() {
+    let (mut network, clock, client_id, nodes) = setup(3);
+    let (kept, expired) = upload_with_one_renewed(&mut network, &client_id, "repaired.txt");
+    let spare = *nodes.iter().find(|node_id| **node_id != kept && **node_id != expired).unwrap();
+
+    clock.advance(DAY);
+    network.check_deals();
+
+    let mut locations = network.get_file_locations(&client_id, "repaired.txt").unwrap();
+    locations.sort();
+    let mut expected = vec![kept, spare];
+    expected.sort();
+    assert_eq!(locations, expected);
+    assert_eq!(network.get_file_content(&spare, "repaired.txt").unwrap(), b"replicated");
+    assert_eq!(state_on(&network, &client_id, &spare, "repaired.txt"), Some(DealState::Active));
+    assert!(network.under_replicated.is_empty());
+    // The client pays for the replacement into a fresh escrow
+    let repair = network.deals_by_storage_node(&spare)[0];
+    assert_eq!(network.token.escrow_balance(repair.escrow_id().unwrap()), repair.total_payment());
+    assert!(!repair.total_payment().is_zero());
+}
+
+#[test]
+fn test_files_whose_deals_all_expire_are_not_repaired() {
+    let (mut network, clock, client_id, nodes) = setup(3);
+    network.upload_file(&client_id, "lapsed.txt".to_string(), b"lapsed".to_vec(), 2).unwrap();
+
+    clock.advance(DAY);
+    network.check_deals();
+
+    assert_eq!(network.deals_in_state(DealState::Expired).len(), 2);
+    assert!(network.get_file_locations(&client_id, "lapsed.txt").is_err());
+    assert!(nodes.iter().all(|node_id| network.get_file_content(node_id, "lapsed.txt").is_err()));
+    assert!(network.under_replicated.is_empty());
+    assert_eq!(network.deals.len(), 2);
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::reputation::{ReputationEngine, MAX_REPUTATION};
+
+mod common;
+use common::setup;
+
+#[test]
+fn test_failed_audits_lower_reputation() {
+    let (mut network, client_id, nodes) = setup(2);
+    let (honest, dishonest) = (nodes[0], nodes[1]);
+    assert_eq!(network.get_reputation(&honest).unwrap().score, MAX_REPUTATION);
+
+    network.upload_file(&client_id, "audited.txt".to_string(), b"audited".to_vec(), 2).unwrap();
+    network.storage_nodes.get_mut(&dishonest).unwrap().remove_file("audited.txt").unwrap();
+    network.advance_epoch();
+
+    let honest_breakdown = network.get_reputation(&honest).unwrap();
+    let dishonest_breakdown = network.get_reputation(&dishonest).unwrap();
+    assert_eq!(honest_breakdown.score, MAX_REPUTATION);
+    assert!(dishonest_breakdown.audits < 1.0);
+    assert!(dishonest_breakdown.deal_completion < 1.0);
+    assert!(dishonest_breakdown.score < honest_breakdown.score);
+    // The published score is what placement and upload constraints see
+    assert_eq!(network.storage_nodes()[&dishonest].reputation(), dishonest_breakdown.score);
+}
+
+#[test]
+fn test_missing_replicas_count_as_failed_retrievals() {
+    let (mut network, client_id, _) = setup(2);
+    let locations = network.upload_file(&client_id, "served.txt".to_string(), b"served".to_vec(), 2).unwrap();
+    let (lost, kept) = (locations[0], locations[1]);
+    network.storage_nodes.get_mut(&lost).unwrap().remove_file("served.txt").unwrap();
+
+    network.download_file(&client_id, "served.txt").unwrap();
+
+    assert!(network.get_reputation(&lost).unwrap().retrievals < 1.0);
+    assert_eq!(network.get_reputation(&kept).unwrap().retrievals, 1.0);
+    // Only the SP that served the file was timed
+    assert!(network.reputation.record(&kept).unwrap().average_latency_ms.is_some());
+    assert!(network.reputation.record(&lost).unwrap().average_latency_ms.is_none());
+}
+
+#[test]
+fn test_uptime_follows_answered_audits() {
+    let (mut network, client_id, nodes) = setup(3);
+    let (silent, partial, healthy) = (nodes[0], nodes[1], nodes[2]);
+    network.upload_file(&client_id, "one.txt".to_string(), b"one".to_vec(), 3).unwrap();
+    network.upload_file(&client_id, "two.txt".to_string(), b"two".to_vec(), 3).unwrap();
+    for filename in ["one.txt", "two.txt"] {
+        network.storage_nodes.get_mut(&silent).unwrap().remove_file(filename).unwrap();
+    }
+    network.storage_nodes.get_mut(&partial).unwrap().remove_file("one.txt").unwrap();
+
+    network.advance_epoch();
+
+    assert!(network.get_reputation(&silent).unwrap().uptime < 1.0, "answered nothing");
+    // Answering any challenge shows the SP is up, even if another proof failed
+    assert_eq!(network.get_reputation(&partial).unwrap().uptime, 1.0);
+    assert!(network.get_reputation(&partial).unwrap().audits < 1.0);
+    assert_eq!(network.get_reputation(&healthy).unwrap().uptime, 1.0);
+}
+
+#[test]
+fn test_slow_retrievals_lower_the_latency_component() {
+    let sp_id = PeerId::random();
+    let mut engine = ReputationEngine::default();
+    engine.record_retrieval(&sp_id, true, Some(Duration::from_millis(50)));
+    assert_eq!(engine.breakdown(&sp_id).latency, 1.0);
+
+    for _ in 0..20 {
+        engine.record_retrieval(&sp_id, true, Some(Duration::from_millis(800)));
+    }
+    let breakdown = engine.breakdown(&sp_id);
+    assert!(breakdown.latency < 0.3, "latency score {}", breakdown.latency);
+    assert_eq!(breakdown.retrievals, 1.0);
+}
+
+#[test]
+fn test_failed_retrievals_lower_the_retrieval_component() {
+    let sp_id = PeerId::random();
+    let mut engine = ReputationEngine::default();
+    for _ in 0..20 {
+        engine.record_retrieval(&sp_id, false, None);
+    }
+    let breakdown = engine.breakdown(&sp_id);
+    assert!(breakdown.retrievals < 0.3, "retrieval score {}", breakdown.retrievals);
+    assert_eq!(breakdown.latency, 1.0, "failed retrievals are not timed");
+    assert!(breakdown.score < MAX_REPUTATION);
+}
+
+#[test]
+fn test_old_failures_decay() {
+    let sp_id = PeerId::random();
+    let mut engine = ReputationEngine::new(0.5);
+    for _ in 0..5 {
+        engine.record_uptime(&sp_id, false);
+    }
+    let after_outage = engine.score(&sp_id);
+    assert!(after_outage < MAX_REPUTATION);
+
+    for _ in 0..10 {
+        engine.decay();
+        engine.record_uptime(&sp_id, true);
+    }
+    let recovered = engine.breakdown(&sp_id);
+    assert!(recovered.score > after_outage);
+    assert!(recovered.uptime > 0.99, "uptime {}", recovered.uptime);
+}
@@ -0,0 +1,75 @@
+use libp2p::PeerId;
+use pioneerfs::{Network, TokenAmount};
+
+mod common;
+use common::setup_with_prices;
+
+// One base unit per byte served keeps the arithmetic in these tests readable
+const PRICE_PER_BYTE: TokenAmount = TokenAmount::from_base_units(1);
+
+fn setup(storage_nodes: usize) -> (Network, PeerId) {
+    let (mut network, client_id, nodes) = setup_with_prices(&vec![10; storage_nodes]);
+    for sp_id in &nodes {
+        network.set_retrieval_price(sp_id, PRICE_PER_BYTE).unwrap();
+    }
+    (network, client_id)
+}
+
+#[test]
+fn test_download_charges_per_byte_served() {
+    let (mut network, client_id) = setup(1);
+    let data = vec![5u8; 40];
+    let nodes = network.upload_file(&client_id, "paid.bin".to_string(), data.clone(), 1).unwrap();
+    let balance_after_upload = network.get_balance(&client_id);
+
+    assert_eq!(network.download_file(&client_id, "paid.bin").unwrap(), data);
+
+    assert_eq!(network.get_balance(&client_id), balance_after_upload - TokenAmount::from_base_units(40));
+    let sp = network.storage_nodes().get(&nodes[0]).unwrap();
+    assert_eq!(sp.retrieval_earnings(), TokenAmount::from_base_units(40));
+    assert_eq!(sp.storage_earnings(), TokenAmount::ZERO);
+}
+
+#[test]
+fn test_split_download_pays_each_sp_for_its_share() {
+    let (mut network, client_id) = setup(4);
+    let data: Vec<u8> = (0..100).collect();
+    let nodes = network.upload_file(&client_id, "split.bin".to_string(), data.clone(), 4).unwrap();
+    let balance_after_upload = network.get_balance(&client_id);
+
+    assert_eq!(network.download_file_split(&client_id, "split.bin").unwrap(), data);
+
+    assert_eq!(network.get_balance(&client_id), balance_after_upload - TokenAmount::from_base_units(100));
+    for node_id in &nodes {
+        assert_eq!(network.storage_nodes().get(node_id).unwrap().retrieval_earnings(), TokenAmount::from_base_units(25));
+    }
+}
+
+#[test]
+fn test_download_fails_without_funds() {
+    let (mut network, client_id) = setup(1);
+    network.upload_file(&client_id, "pricey.bin".to_string(), vec![1u8; 10], 1).unwrap();
+    let balance = network.get_balance(&client_id);
+    let sp_id = network.list_storage_nodes()[0];
+    network.set_retrieval_price(&sp_id, TokenAmount::from_tokens(1_000_000)).unwrap();
+
+    assert!(network.download_file(&client_id, "pricey.bin").is_err());
+    assert_eq!(network.get_balance(&client_id), balance);
+}
+
+#[test]
+fn test_storage_earnings_tracked_separately() {
+    let (mut network, client_id) = setup(1);
+    let nodes = network.upload_file(&client_id, "both.bin".to_string(), vec![9u8; 8], 1).unwrap();
+    let epochs = network.deals[&0].epochs();
+    for _ in 0..epochs {
+        network.advance_epoch();
+    }
+    network.download_file(&client_id, "both.bin").unwrap();
+
+    let sp = network.storage_nodes().get(&nodes[0]).unwrap();
+    // 8 bytes stored for 24 epochs at 10 per byte-epoch, then 8 bytes served
+    assert_eq!(sp.storage_earnings(), TokenAmount::from_base_units(8 * 24 * 10));
+    assert_eq!(sp.retrieval_earnings(), TokenAmount::from_base_units(8));
+    assert_eq!(network.get_balance(&nodes[0]), TokenAmount::from_base_units(8 * 24 * 10 + 8));
+}
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use libp2p::PeerId;
+use pioneerfs::Network;
+use pioneerfs::clock::{Clock, ManualClock, Timestamp};
+use pioneerfs::deal::DealState;
+use pioneerfs::scheduler::{self, MaintenanceJob};
+
+mod common;
+use common::setup_with_clock;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn setup() -> (Network, ManualClock, PeerId) {
+    let (mut network, clock, client_id, _) = setup_with_clock(1, Timestamp::from_millis(0));
+    network.grace_period = Duration::ZERO;
+    (network, clock, client_id)
+}
+
+fn ran(runs: &[(MaintenanceJob, String)]) -> Vec<MaintenanceJob> {
+    runs.iter().map(|(job, _)| *job).collect()
+}
+
+#[test]
+fn test_jobs_run_once_per_interval() {
+    let (mut network, clock, _) = setup();
+
+    assert_eq!(ran(&network.run_due_jobs()), MaintenanceJob::ALL.to_vec(), "every job is due on start");
+    assert!(network.run_due_jobs().is_empty());
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(ran(&network.run_due_jobs()), vec![MaintenanceJob::CheckDeals, MaintenanceJob::AdjustPrices]);
+
+    network.scheduler.set_interval(MaintenanceJob::ExpireOffers, Duration::from_secs(90)).unwrap();
+    assert!(network.scheduler.set_interval(MaintenanceJob::ExpireOffers, Duration::ZERO).is_err());
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(ran(&network.run_due_jobs()), vec![MaintenanceJob::ExpireOffers]);
+
+    let status = network.scheduler.status(MaintenanceJob::ExpireOffers).unwrap();
+    assert_eq!(status.runs, 2);
+    assert_eq!(status.last_run, Some(clock.now()));
+    assert_eq!(status.next_run(), Some(clock.now() + Duration::from_secs(90)));
+    assert_eq!(status.last_summary.as_deref(), Some("0 offers expired"));
+}
+
+#[test]
+fn test_paused_jobs_only_run_when_triggered() {
+    let (mut network, clock, _) = setup();
+    network.scheduler.set_job_paused(MaintenanceJob::Audits, true).unwrap();
+
+    let runs = ran(&network.run_due_jobs());
+    assert!(!runs.contains(&MaintenanceJob::Audits));
+    assert_eq!(network.current_epoch, 0);
+
+    network.run_job(MaintenanceJob::Audits);
+    assert_eq!(network.current_epoch, 1);
+    assert_eq!(network.scheduler.status(MaintenanceJob::Audits).unwrap().runs, 1);
+
+    network.scheduler.set_paused(true);
+    clock.advance(DAY);
+    assert!(network.run_due_jobs().is_empty());
+    network.scheduler.set_paused(false);
+    assert_eq!(ran(&network.run_due_jobs()), vec![
+        MaintenanceJob::CheckDeals,
+        MaintenanceJob::AdjustPrices,
+        MaintenanceJob::RepairScan,
+        MaintenanceJob::ExpireOffers,
+        MaintenanceJob::Rebalance,
+    ]);
+}
+
+#[test]
+fn test_deals_expire_without_a_manual_check() {
+    let (mut network, clock, client_id) = setup();
+    network.upload_file(&client_id, "scheduled.txt".to_string(), b"scheduled".to_vec(), 1).unwrap();
+    network.scheduler.set_job_paused(MaintenanceJob::Audits, true).unwrap();
+    network.run_due_jobs();
+
+    clock.advance(DAY);
+    network.run_due_jobs();
+
+    assert_eq!(network.deals_in_state(DealState::Expired).len(), 1);
+    assert!(network.download_file(&client_id, "scheduled.txt").is_err());
+    let status = network.scheduler.status(MaintenanceJob::CheckDeals).unwrap();
+    assert_eq!(status.last_summary.as_deref(), Some("1 deals ended, 0 still live"));
+}
+
+#[tokio::test]
+async fn test_spawned_scheduler_runs_due_jobs() {
+    let (network, _, _) = setup();
+    let network = Arc::new(Mutex::new(network));
+
+    let handle = scheduler::spawn(Arc::clone(&network), Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    handle.abort();
+
+    let network = network.lock().unwrap();
+    assert!(network.scheduler.jobs().iter().all(|status| status.runs == 1));
+    assert_eq!(network.current_epoch, 1);
+}
+
+#[tokio::test]
+#[allow(clippy::await_holding_lock)]
+async fn test_scheduler_waits_for_the_lock_off_the_async_runtime() {
+    let (network, _, _) = setup();
+    let network = Arc::new(Mutex::new(network));
+
+    let handle = scheduler::spawn(Arc::clone(&network), Duration::from_millis(10));
+    {
+        // On this single-threaded runtime, a loop that locked in place would never give the thread back
+        let guard = network.lock().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(guard.current_epoch, 0);
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    handle.abort();
+
+    assert_eq!(network.lock().unwrap().current_epoch, 1);
+}
@@ -0,0 +1,151 @@
+use libp2p::futures::io::Cursor;
+use libp2p::request_response::Codec;
+use libp2p::{identity, multiaddr::Protocol, Multiaddr, PeerId};
+use pioneerfs::cluster::TestCluster;
+use pioneerfs::peer::PeerHandle;
+use pioneerfs::protocol::{self, BlockRequest, BlockResponse, ErrorCode, StorageCodec, MAX_BLOCK_SIZE, STORAGE_PROTOCOL};
+use pioneerfs::{Network, StorageNode, TokenAmount};
+
+fn store(name: &str, data: &[u8]) -> BlockRequest {
+    BlockRequest::Store { name: name.to_string(), data: data.to_vec() }
+}
+
+fn error_code(response: BlockResponse) -> Option<ErrorCode> {
+    match response {
+        BlockResponse::Error { code, .. } => Some(code),
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn test_codec_round_trips_compactly() {
+    let mut codec = StorageCodec::default();
+    let request = BlockRequest::FetchRange { name: "block".to_string(), offset: 5, length: 10 };
+
+    let mut wire = Cursor::new(Vec::new());
+    codec.write_request(&STORAGE_PROTOCOL, &mut wire, request.clone()).await.unwrap();
+    // Variant, name length, name, then one byte each for the small integers
+    assert_eq!(wire.get_ref().len(), 1 + 1 + 5 + 1 + 1);
+    wire.set_position(0);
+    assert_eq!(codec.read_request(&STORAGE_PROTOCOL, &mut wire).await.unwrap(), request);
+
+    let response = BlockResponse::error(ErrorCode::InvalidRange, "bad range");
+    let mut wire = Cursor::new(Vec::new());
+    codec.write_response(&STORAGE_PROTOCOL, &mut wire, response.clone()).await.unwrap();
+    wire.set_position(0);
+    assert_eq!(codec.read_response(&STORAGE_PROTOCOL, &mut wire).await.unwrap(), response);
+}
+
+#[tokio::test]
+async fn test_codec_enforces_the_size_limit() {
+    let mut codec = StorageCodec { max_message_size: 64 };
+
+    let mut wire = Cursor::new(Vec::new());
+    assert!(codec.write_request(&STORAGE_PROTOCOL, &mut wire, store("big", &[0; 100])).await.is_err());
+
+    let oversized = StorageCodec::default().encode(&store("big", &[0; 100])).unwrap();
+    assert!(codec.read_request(&STORAGE_PROTOCOL, &mut Cursor::new(oversized)).await.is_err());
+    let garbage = vec![0xff; 16];
+    assert!(codec.read_request(&STORAGE_PROTOCOL, &mut Cursor::new(garbage)).await.is_err());
+}
+
+#[test]
+fn test_requests_are_answered_with_error_codes() {
+    let mut storage_node = StorageNode::new(PeerId::random(), TokenAmount::from_base_units(1));
+    let fetch = |offset, length| BlockRequest::FetchRange { name: "block".to_string(), offset, length };
+
+    assert_eq!(protocol::handle_request(&mut storage_node, store("block", b"0123456789")), BlockResponse::Stored);
+    assert_eq!(protocol::handle_request(&mut storage_node, fetch(2, 3)), BlockResponse::Data(b"234".to_vec()));
+    assert_eq!(protocol::handle_request(&mut storage_node, fetch(8, 100)), BlockResponse::Data(b"89".to_vec()));
+    assert_eq!(error_code(protocol::handle_request(&mut storage_node, fetch(11, 1))), Some(ErrorCode::InvalidRange));
+
+    // Storing a block again replaces it
+    protocol::handle_request(&mut storage_node, store("block", b"abc"));
+    assert_eq!(storage_node.used_space(), 3);
+    assert_eq!(protocol::handle_request(&mut storage_node, BlockRequest::HasBlock { name: "block".to_string() }), BlockResponse::Has(Some(3)));
+
+    let too_large = store("huge", &vec![0; MAX_BLOCK_SIZE + 1]);
+    assert_eq!(error_code(protocol::handle_request(&mut storage_node, too_large)), Some(ErrorCode::TooLarge));
+
+    assert_eq!(protocol::handle_request(&mut storage_node, BlockRequest::Delete { name: "block".to_string() }), BlockResponse::Deleted);
+    for request in [
+        fetch(0, 1),
+        BlockRequest::Delete { name: "block".to_string() },
+        BlockRequest::ProofChallenge { name: "block".to_string(), nonce: 1 },
+    ] {
+        assert_eq!(error_code(protocol::handle_request(&mut storage_node, request)), Some(ErrorCode::NotFound));
+    }
+    assert_eq!(protocol::handle_request(&mut storage_node, BlockRequest::HasBlock { name: "block".to_string() }), BlockResponse::Has(None));
+}
+
+#[tokio::test]
+async fn test_every_operation_over_the_wire() {
+    let cluster = TestCluster::new(1, 2).await.unwrap();
+    let client = &cluster.clients[0];
+    let node_id = cluster.storage_node_ids()[0];
+    let data = b"a block sent over libp2p".to_vec();
+
+    client.store(node_id, "block", data.clone()).await.unwrap();
+    assert_eq!(client.has_block(node_id, "block").await.unwrap(), Some(data.len() as u64));
+    assert_eq!(client.fetch(node_id, "block").await.unwrap(), data);
+    assert_eq!(client.fetch_range(node_id, "block", 2, 5).await.unwrap(), b"block");
+
+    let digest = client.prove(node_id, "block", 42).await.unwrap();
+    assert_eq!(digest, protocol::proof_digest(&data, 42));
+    assert_ne!(client.prove(node_id, "block", 43).await.unwrap(), digest, "a new nonce needs a new proof");
+
+    let large = vec![7; MAX_BLOCK_SIZE];
+    client.store(node_id, "large", large.clone()).await.unwrap();
+    assert_eq!(client.fetch(node_id, "large").await.unwrap(), large);
+
+    let other = &cluster.clients[1];
+    let response = other.request(node_id, BlockRequest::Delete { name: "block".to_string() }).await.unwrap();
+    assert_eq!(error_code(response), Some(ErrorCode::Unauthorized), "only the peer that stored a block may delete it");
+    assert!(other.store(node_id, "block", b"overwrite".to_vec()).await.is_err());
+
+    client.delete(node_id, "block").await.unwrap();
+    assert_eq!(client.has_block(node_id, "block").await.unwrap(), None);
+    assert_eq!(client.fetch(node_id, "block").await.unwrap_err(), "Block not found");
+    assert!(cluster.stored_on("block").is_empty());
+}
+
+#[tokio::test]
+async fn test_network_hosted_storage_node_serves_requests() {
+    let owner = PeerHandle::spawn_client(identity::Keypair::generate_ed25519()).unwrap();
+    let stranger = PeerHandle::spawn_client(identity::Keypair::generate_ed25519()).unwrap();
+    let mut network = Network::new().unwrap();
+    let node_id = *network.swarm.local_peer_id();
+    network.add_storage_node(node_id, TokenAmount::from_base_units(1));
+    network.add_client(*owner.peer_id());
+    network.token.disburse(owner.peer_id(), TokenAmount::from_tokens(1_000), "test funding").unwrap();
+    let data = b"held by the network's own node".to_vec();
+    network.upload_file(owner.peer_id(), "hosted.txt".to_string(), data.clone(), 1).unwrap();
+
+    let address = Multiaddr::empty().with(Protocol::Memory(rand::random::<u64>().max(1)));
+    network.swarm.listen_on(address.clone()).unwrap();
+    let server = tokio::spawn(async move {
+        let _ = network.run().await;
+    });
+
+    for client in [&owner, &stranger] {
+        client.connect(node_id, address.clone()).await.unwrap();
+        assert_eq!(client.has_block(node_id, "hosted.txt").await.unwrap(), Some(data.len() as u64));
+        assert_eq!(client.fetch(node_id, "hosted.txt").await.unwrap(), data);
+        assert_eq!(client.prove(node_id, "hosted.txt", 7).await.unwrap(), protocol::proof_digest(&data, 7));
+    }
+    assert_eq!(owner.fetch(node_id, "missing.txt").await.unwrap_err(), "Block not found");
+
+    // Only the client of a live deal may write or delete, and only the data it committed to
+    let unauthorized = |response: BlockResponse| error_code(response) == Some(ErrorCode::Unauthorized);
+    assert!(unauthorized(stranger.request(node_id, store("hosted.txt", b"overwritten")).await.unwrap()));
+    assert!(unauthorized(stranger.request(node_id, store("free.txt", b"unpaid")).await.unwrap()));
+    assert!(unauthorized(stranger.request(node_id, BlockRequest::Delete { name: "hosted.txt".to_string() }).await.unwrap()));
+    assert!(unauthorized(owner.request(node_id, store("hosted.txt", b"something else")).await.unwrap()));
+    owner.store(node_id, "hosted.txt", data.clone()).await.unwrap();
+    assert_eq!(stranger.fetch(node_id, "hosted.txt").await.unwrap(), data);
+
+    owner.delete(node_id, "hosted.txt").await.unwrap();
+    assert_eq!(owner.has_block(node_id, "hosted.txt").await.unwrap(), None);
+    assert!(unauthorized(owner.request(node_id, BlockRequest::Delete { name: "hosted.txt".to_string() }).await.unwrap()), "the deal has ended");
+    server.abort();
+}
@@ -0,0 +1,172 @@
+use libp2p::PeerId;
+use pioneerfs::TokenAmount;
+
+mod common;
+use common::{initial_balance, setup, units};
+
+// SPs from `setup` charge one base unit per byte-epoch, so storing a byte for a whole deal costs DEAL_EPOCHS
+const DEAL_EPOCHS: u128 = 24;
+
+#[test]
+fn test_streaming_upload_pays_per_byte() {
+    let (mut network, client_id, _) = setup(3);
+    let data = vec![7u8; 100];
+
+    let nodes = network.upload_file_streaming(&client_id, "stream.bin".to_string(), data.clone(), 3, 10, units(1_000 * DEAL_EPOCHS)).unwrap();
+
+    assert_eq!(nodes.len(), 3);
+    for node_id in &nodes {
+        assert_eq!(network.get_file_content(node_id, "stream.bin").unwrap(), data);
+        // What was streamed stays escrowed in the deal until the SP proves storage
+        let deal = network.deals_by_storage_node(node_id)[0];
+        assert_eq!(network.token.escrow_balance(deal.escrow_id().unwrap()), units(100 * DEAL_EPOCHS));
+        assert_eq!(network.get_balance(node_id), TokenAmount::ZERO);
+    }
+    assert_eq!(network.get_balance(&client_id), initial_balance() - units(300 * DEAL_EPOCHS));
+    assert!(network.payment_channels.is_empty());
+    assert_eq!(network.download_file(&client_id, "stream.bin").unwrap(), data);
+
+    for _ in 0..DEAL_EPOCHS {
+        network.advance_epoch();
+    }
+    for node_id in &nodes {
+        assert_eq!(network.get_balance(node_id), units(100 * DEAL_EPOCHS));
+    }
+}
+
+#[test]
+fn test_stopping_mid_upload_only_pays_for_stored_bytes() {
+    let (mut network, client_id, _) = setup(2);
+
+    let stream_id = network.open_upload_stream(&client_id, "partial.bin".to_string(), 2, units(1_000 * DEAL_EPOCHS)).unwrap();
+    assert_eq!(network.get_balance(&client_id), initial_balance() - units(2_000 * DEAL_EPOCHS));
+
+    network.stream_chunk(stream_id, &[1u8; 40]).unwrap();
+    network.stream_chunk(stream_id, &[2u8; 25]).unwrap();
+    let targets = network.upload_streams[&stream_id].targets().to_vec();
+
+    let paid = network.abort_upload_stream(stream_id).unwrap();
+
+    assert_eq!(paid, units(2 * 65 * DEAL_EPOCHS));
+    assert_eq!(network.get_balance(&client_id), initial_balance() - units(130 * DEAL_EPOCHS));
+    for node_id in &targets {
+        assert_eq!(network.get_balance(node_id), units(65 * DEAL_EPOCHS));
+        assert!(network.get_file_content(node_id, "partial.bin").is_err());
+    }
+    assert!(network.get_file_locations(&client_id, "partial.bin").is_err());
+}
+
+#[test]
+fn test_stream_stops_when_channel_is_exhausted() {
+    let (mut network, client_id, _) = setup(1);
+
+    let result = network.upload_file_streaming(&client_id, "too_big.bin".to_string(), vec![0u8; 100], 1, 30, units(50 * DEAL_EPOCHS));
+
+    assert!(result.is_err());
+    // Only the first chunk fit within the channel budget
+    assert_eq!(network.get_balance(&client_id), initial_balance() - units(30 * DEAL_EPOCHS));
+}
+
+#[test]
+fn test_chunk_is_rejected_everywhere_when_a_later_sp_is_full() {
+    let (mut network, client_id, _) = setup(2);
+    let stream_id = network.open_upload_stream(&client_id, "all_or_nothing.bin".to_string(), 2, units(1_000 * DEAL_EPOCHS)).unwrap();
+    let targets = network.upload_streams[&stream_id].targets().to_vec();
+    let (first, second) = (targets[0], targets[1]);
+    let almost_all = network.storage_nodes()[&second].available_space() - 5;
+    network.storage_nodes.get_mut(&second).unwrap().reserve_space(almost_all).unwrap();
+
+    assert!(network.stream_chunk(stream_id, &[9u8; 10]).is_err());
+
+    assert_eq!(network.upload_streams[&stream_id].bytes_stored(), 0);
+    assert!(network.get_file_content(&first, "all_or_nothing.bin").is_err());
+    assert_eq!(network.storage_nodes()[&first].used_space(), 0);
+    // Nothing was stored, so nothing is owed to either SP
+    assert_eq!(network.abort_upload_stream(stream_id).unwrap(), TokenAmount::ZERO);
+    assert_eq!(network.get_balance(&first), TokenAmount::ZERO);
+    assert_eq!(network.get_balance(&client_id), initial_balance());
+}
+
+#[test]
+fn test_chain_replication_streams_payments() {
+    let (mut network, client_id, _) = setup(4);
+    let data = vec![3u8; 20];
+
+    network.upload_file_streaming(&client_id, "chain.bin".to_string(), data.clone(), 1, 8, units(100 * DEAL_EPOCHS)).unwrap();
+    let new_nodes = network.replicate_file_streaming(&client_id, "chain.bin", 2, 8, units(100 * DEAL_EPOCHS)).unwrap();
+
+    assert_eq!(network.get_file_locations(&client_id, "chain.bin").unwrap().len(), 3);
+    for node_id in &new_nodes {
+        let deal = network.deals_by_storage_node(node_id)[0];
+        assert_eq!(network.token.escrow_balance(deal.escrow_id().unwrap()), units(20 * DEAL_EPOCHS));
+        assert_eq!(network.get_file_content(node_id, "chain.bin").unwrap(), data);
+    }
+    assert_eq!(network.get_balance(&client_id), initial_balance() - units(60 * DEAL_EPOCHS));
+}
+
+#[test]
+fn test_streamed_replication_leaves_other_clients_files_alone() {
+    let (mut network, alice, _) = setup(3);
+    let bob = PeerId::random();
+    network.add_client(bob);
+    network.token.disburse(&bob, initial_balance(), "test funding").unwrap();
+
+    let alices_node = network.upload_file(&alice, "shared.bin".to_string(), b"alice's data".to_vec(), 1).unwrap()[0];
+    let bobs_node = network.upload_file(&bob, "shared.bin".to_string(), b"bob's data".to_vec(), 1).unwrap()[0];
+
+    // Only one SP holds neither copy
+    assert!(network.replicate_file_streaming(&bob, "shared.bin", 2, 4, units(100 * DEAL_EPOCHS)).is_err());
+    let new_nodes = network.replicate_file_streaming(&bob, "shared.bin", 1, 4, units(100 * DEAL_EPOCHS)).unwrap();
+
+    assert!(!new_nodes.contains(&alices_node) && !new_nodes.contains(&bobs_node));
+    assert_eq!(network.get_file_content(&alices_node, "shared.bin").unwrap(), b"alice's data");
+    assert_eq!(network.download_file(&alice, "shared.bin").unwrap(), b"alice's data");
+    assert_eq!(network.get_file_content(&new_nodes[0], "shared.bin").unwrap(), b"bob's data");
+}
+
+#[test]
+fn test_chunks_are_refused_for_a_draining_sp() {
+    let (mut network, client_id, _) = setup(2);
+    let stream_id = network.open_upload_stream(&client_id, "leaving.bin".to_string(), 1, units(1_000 * DEAL_EPOCHS)).unwrap();
+    let target = network.upload_streams[&stream_id].targets()[0];
+    network.stream_chunk(stream_id, &[1u8; 10]).unwrap();
+
+    network.drain_storage_node(&target).unwrap();
+
+    assert!(network.stream_chunk(stream_id, &[2u8; 10]).is_err());
+    assert_eq!(network.abort_upload_stream(stream_id).unwrap(), units(10 * DEAL_EPOCHS));
+    assert!(network.get_file_content(&target, "leaving.bin").is_err());
+}
+
+#[test]
+fn test_first_chunk_never_lands_on_an_existing_file() {
+    let (mut network, client_id, _) = setup(1);
+    let stream_id = network.open_upload_stream(&client_id, "taken.bin".to_string(), 1, units(1_000 * DEAL_EPOCHS)).unwrap();
+    let target = network.upload_streams[&stream_id].targets()[0];
+    // Another file of the same name arrives after the stream was opened
+    network.storage_nodes.get_mut(&target).unwrap().store_file("taken.bin".to_string(), b"first".to_vec()).unwrap();
+
+    assert!(network.stream_chunk(stream_id, &[1u8; 10]).is_err());
+    network.abort_upload_stream(stream_id).unwrap();
+
+    assert_eq!(network.get_file_content(&target, "taken.bin").unwrap(), b"first");
+    assert_eq!(network.get_balance(&client_id), initial_balance());
+}
+
+#[test]
+fn test_forged_and_stale_vouchers_are_rejected() {
+    use pioneerfs::payment_channel::{ChannelSigner, PaymentChannel};
+
+    let mut signer = ChannelSigner::new(1);
+    let mut channel = PaymentChannel::new(1, PeerId::random(), PeerId::random(), units(100), signer.public_key());
+
+    let first = signer.pay(units(10)).unwrap();
+    let second = signer.pay(units(5)).unwrap();
+    assert_eq!(channel.accept_voucher(second.clone()).unwrap(), units(15));
+    assert!(channel.accept_voucher(first).is_err());
+
+    let mut forged = signer.pay(units(5)).unwrap();
+    forged.cumulative_amount = units(90);
+    assert!(channel.accept_voucher(forged).is_err());
+    assert_eq!(channel.redeemable(), units(15));
+}
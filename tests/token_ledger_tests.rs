@@ -0,0 +1,77 @@
+use libp2p::PeerId;
+use pioneerfs::erc20::{TokenError, ERC20};
+use pioneerfs::jsonrpc::{self, JsonRpcLedger, MockRpcServer};
+use pioneerfs::token_ledger::TokenLedger;
+use pioneerfs::TokenAmount;
+
+mod common;
+use common::pio;
+
+/// Runs the same payment flow against any backend.
+#[allow(clippy::result_large_err)]
+fn pay_for_chain_replication(ledger: &mut dyn TokenLedger, client: &PeerId, first_sp: &PeerId, second_sp: &PeerId) -> Result<(), TokenError> {
+    ledger.approve(client, first_sp, pio(30))?;
+    ledger.transfer(client, first_sp, pio(10))?;
+    ledger.transfer_from(first_sp, client, second_sp, pio(20))?;
+    Ok(())
+}
+
+#[test]
+fn test_abi_encoding_matches_the_erc20_layout() {
+    let to = [0x11u8; 20];
+    let data = jsonrpc::encode_transfer(&to, TokenAmount::from_base_units(258));
+
+    assert_eq!(jsonrpc::to_hex(&data[..4]), "0xa9059cbb");
+    assert_eq!(data.len(), 4 + 32 + 32);
+    assert_eq!(&data[4..16], &[0u8; 12]);
+    assert_eq!(&data[16..36], &to);
+    assert_eq!(&data[66..], &[0x01, 0x02]);
+    assert_eq!(jsonrpc::decode_uint(&data[36..]).unwrap(), TokenAmount::from_base_units(258));
+    assert_eq!(jsonrpc::decode_uint(&[0xff; 32]), Err(TokenError::Overflow));
+}
+
+#[test]
+fn test_in_memory_and_json_rpc_backends_agree() {
+    let client = PeerId::random();
+    let first_sp = PeerId::random();
+    let second_sp = PeerId::random();
+
+    let mut memory = ERC20::new("PIONEER".to_string(), "PIO".to_string(), TokenAmount::ZERO);
+    memory.mint(&client, pio(100)).unwrap();
+    pay_for_chain_replication(&mut memory, &client, &first_sp, &second_sp).unwrap();
+
+    let server = MockRpcServer::start().unwrap();
+    let mut remote = JsonRpcLedger::new(&server.endpoint(), [0xee; 20]);
+    for (index, peer_id) in [client, first_sp, second_sp].iter().enumerate() {
+        remote.register_account(*peer_id, [index as u8 + 1; 20]);
+    }
+    server.mint([1; 20], pio(100)).unwrap();
+    pay_for_chain_replication(&mut remote, &client, &first_sp, &second_sp).unwrap();
+
+    for peer_id in [client, first_sp, second_sp] {
+        assert_eq!(TokenLedger::balance_of(&remote, &peer_id).unwrap(), TokenLedger::balance_of(&memory, &peer_id).unwrap());
+    }
+    assert_eq!(TokenLedger::allowance(&remote, &client, &first_sp).unwrap(), pio(10));
+    assert_eq!(server.balance(&[3; 20]), pio(20));
+}
+
+#[test]
+fn test_json_rpc_reverts_surface_as_errors() {
+    let server = MockRpcServer::start().unwrap();
+    let mut remote = JsonRpcLedger::new(&server.endpoint(), [0xee; 20]);
+    let client = PeerId::random();
+    let sp = PeerId::random();
+    remote.register_account(client, [1; 20]);
+    remote.register_account(sp, [2; 20]);
+    server.mint([1; 20], pio(5)).unwrap();
+
+    // The transactions are mined, but their receipts report a revert
+    assert!(matches!(remote.transfer(&client, &sp, pio(6)), Err(TokenError::Reverted(_))));
+    assert!(matches!(remote.transfer_from(&sp, &client, &sp, pio(1)), Err(TokenError::Reverted(_))));
+    assert_eq!(server.balance(&[1; 20]), pio(5));
+    assert_eq!(server.balance(&[2; 20]), TokenAmount::ZERO);
+
+    remote.transfer(&client, &sp, pio(5)).unwrap();
+    assert_eq!(server.balance(&[2; 20]), pio(5));
+    assert!(matches!(TokenLedger::balance_of(&remote, &PeerId::random()), Err(TokenError::Backend(_))));
+}
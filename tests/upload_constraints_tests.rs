@@ -0,0 +1,55 @@
+use pioneerfs::TokenAmount;
+use pioneerfs::placement::UploadConstraints;
+
+mod common;
+use common::setup_with_prices;
+
+#[test]
+fn test_upload_only_uses_eligible_nodes() {
+    let (mut network, client_id, nodes) = setup_with_prices(&[1, 1, 5, 1]);
+    network.storage_nodes.get_mut(&nodes[3]).unwrap().decrease_reputation(50);
+    let constraints = UploadConstraints {
+        max_price_per_byte_epoch: Some(TokenAmount::from_base_units(2)),
+        min_reputation: 80,
+        excluded_peers: [nodes[1]].into_iter().collect(),
+        ..Default::default()
+    };
+
+    let stored = network.upload_file_with_constraints(&client_id, "picky.txt".to_string(), b"picky".to_vec(), 1, &constraints, None).unwrap();
+
+    assert_eq!(stored, vec![nodes[0]]);
+}
+
+#[test]
+fn test_unsatisfiable_constraints_name_the_failure() {
+    let (mut network, client_id, nodes) = setup_with_prices(&[1, 5, 5]);
+    let constraints = UploadConstraints {
+        max_price_per_byte_epoch: Some(TokenAmount::from_base_units(1)),
+        ..Default::default()
+    };
+
+    let err = network.upload_file_with_constraints(&client_id, "cheap.txt".to_string(), b"cheap".to_vec(), 2, &constraints, None).unwrap_err();
+
+    assert!(err.contains("Required: 2, Eligible: 1"), "{}", err);
+    assert!(err.contains("2 above the maximum price"), "{}", err);
+    for node_id in &nodes {
+        assert_eq!(network.storage_nodes()[node_id].used_space(), 0);
+    }
+    assert!(network.deals.is_empty());
+}
+
+#[test]
+fn test_min_free_space_counts_the_file() {
+    let (mut network, client_id, nodes) = setup_with_prices(&[1, 1]);
+    let cramped = nodes[1];
+    let leave = 100;
+    let take = network.storage_nodes()[&cramped].available_space() - leave;
+    network.storage_nodes.get_mut(&cramped).unwrap().reserve_space(take).unwrap();
+    let constraints = UploadConstraints { min_free_space: 96, ..Default::default() };
+
+    let err = network.upload_file_with_constraints(&client_id, "roomy.txt".to_string(), b"eight by".to_vec(), 2, &constraints, None).unwrap_err();
+    assert!(err.contains("1 short of free space"), "{}", err);
+
+    let stored = network.upload_file_with_constraints(&client_id, "roomy.txt".to_string(), b"eight by".to_vec(), 1, &constraints, None).unwrap();
+    assert_eq!(stored, vec![nodes[0]]);
+}